@@ -0,0 +1,232 @@
+use crate::{postprocess::HDR_FORMAT, shaders, texture};
+
+/// How the scene is anti-aliased, cycled at runtime with F8. MSAA is only
+/// ever 4x, see `supported_modes`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum AntiAliasing {
+    Off,
+    /// Post pass on the tonemapped image, cheap but blurs a little.
+    Fxaa,
+    /// Multisampled scene with this many samples per pixel.
+    Msaa(u32),
+}
+
+impl AntiAliasing {
+    pub(crate) fn sample_count(self) -> u32 {
+        match self {
+            AntiAliasing::Msaa(samples) => samples,
+            _ => 1,
+        }
+    }
+}
+
+/// Modes the adapter can run. WebGPU guarantees 4x for the HDR and depth
+/// formats. wgpu only reports whether a format multisamples at all, not
+/// which counts, and doesn't check the count either, so 2x and 8x aren't
+/// offered.
+fn supported_modes(adapter: &wgpu::Adapter) -> Vec<AntiAliasing> {
+    let mut modes = vec![AntiAliasing::Off, AntiAliasing::Fxaa];
+    let color = adapter.get_texture_format_features(HDR_FORMAT).flags;
+    let depth = adapter
+        .get_texture_format_features(texture::Texture::DEPTH_FORMAT)
+        .flags;
+    let multisample = color.contains(
+        wgpu::TextureFormatFeatureFlags::MULTISAMPLE
+            | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE,
+    ) && depth.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE);
+    if multisample {
+        modes.push(AntiAliasing::Msaa(4));
+    }
+    modes
+}
+
+/// Multisampled colour and depth for the scene pass, recreated with the
+/// window.
+pub(crate) struct MultisampleTargets {
+    /// Resolves into the HDR target.
+    pub color: texture::Texture,
+    /// Resolved into the single sampled depth texture by `resolve_depth`.
+    pub depth: texture::Texture,
+    resolve_bind_group: wgpu::BindGroup,
+}
+
+/// Tracks the anti-aliasing mode and owns the MSAA targets it needs.
+pub(crate) struct Multisampling {
+    modes: Vec<AntiAliasing>,
+    mode: AntiAliasing,
+    resolve_bind_group_layout: wgpu::BindGroupLayout,
+    resolve_pipeline: wgpu::RenderPipeline,
+    targets: Option<MultisampleTargets>,
+}
+
+impl Multisampling {
+    /// Starts on 4x MSAA, or FXAA where that isn't available.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> anyhow::Result<Self> {
+        let modes = supported_modes(adapter);
+        let mode = if modes.contains(&AntiAliasing::Msaa(4)) {
+            AntiAliasing::Msaa(4)
+        } else {
+            AntiAliasing::Fxaa
+        };
+
+        let resolve_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: true,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                }],
+                label: Some("depth_resolve_bind_group_layout"),
+            });
+        let resolve_pipeline = depth_resolve_pipeline_init(device, &resolve_bind_group_layout)?;
+
+        let mut multisampling = Self {
+            modes,
+            mode,
+            resolve_bind_group_layout,
+            resolve_pipeline,
+            targets: None,
+        };
+        multisampling.resize(device, config);
+        Ok(multisampling)
+    }
+
+    /// Rebuilds the depth resolve pipeline from the shader on disk, keeping
+    /// the old one if the new shader or pipeline doesn't validate.
+    pub(crate) fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        self.resolve_pipeline = shaders::catch_validation_errors(device, || {
+            depth_resolve_pipeline_init(device, &self.resolve_bind_group_layout)
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn mode(&self) -> AntiAliasing {
+        self.mode
+    }
+
+    /// Switches to the next supported mode.
+    pub(crate) fn cycle(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> AntiAliasing {
+        let index = self.modes.iter().position(|&mode| mode == self.mode);
+        self.mode = self.modes[index.map_or(0, |index| (index + 1) % self.modes.len())];
+        log::info!(
+            "anti-aliasing {:?}, F8 cycles through {:?} (MSAA is 4x only)",
+            self.mode,
+            self.modes
+        );
+        self.resize(device, config);
+        self.mode
+    }
+
+    /// Recreates the MSAA targets to match the surface, or drops them when
+    /// MSAA is off.
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        let sample_count = self.mode.sample_count();
+        if sample_count == 1 {
+            self.targets = None;
+            return;
+        }
+        let color = texture::Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            HDR_FORMAT,
+            sample_count,
+            "msaa_color",
+        );
+        let depth =
+            texture::Texture::create_depth_texture(device, config, sample_count, "msaa_depth");
+        let resolve_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.resolve_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            }],
+            label: Some("depth_resolve_bind_group"),
+        });
+        self.targets = Some(MultisampleTargets {
+            color,
+            depth,
+            resolve_bind_group,
+        });
+    }
+
+    pub(crate) fn targets(&self) -> Option<&MultisampleTargets> {
+        self.targets.as_ref()
+    }
+
+    /// Copies the nearest depth sample of each pixel into `depth_texture` so
+    /// the post chain can read it. Does nothing without MSAA.
+    pub(crate) fn resolve_depth(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_texture: &texture::Texture,
+    ) {
+        let Some(targets) = &self.targets else { return };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        pass.set_pipeline(&self.resolve_pipeline);
+        pass.set_bind_group(0, &targets.resolve_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn depth_resolve_pipeline_init(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    let shader = shaders::create_module(device, "post.wgsl", &[], "Post Shader")?;
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Depth Resolve Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    Ok(
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Resolve Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_fullscreen",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_resolve_depth",
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        }),
+    )
+}
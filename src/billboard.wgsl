@@ -0,0 +1,28 @@
+// Billboards lay their mesh's x, y and z axes along these instead of
+// rotating with their instance. Modes match `Billboard`.
+
+let BILLBOARD_NONE: u32 = 0u;
+let BILLBOARD_CYLINDRICAL: u32 = 1u;
+let BILLBOARD_SPHERICAL: u32 = 2u;
+let BILLBOARD_SCREEN_SIZE: u32 = 3u;
+
+// Turns +Z towards `to_viewer`, about the world's up axis only for
+// cylindrical billboards. Screen sized ones turn like spherical ones.
+fn billboard_basis(mode: u32, to_viewer: vec3<f32>) -> mat3x3<f32> {
+    var facing = to_viewer;
+    if (mode == BILLBOARD_CYLINDRICAL) {
+        facing.y = 0.0;
+    }
+    // The viewer is on top of the billboard, or right above a cylindrical one.
+    if (dot(facing, facing) < 1e-8) {
+        facing = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    facing = normalize(facing);
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), facing);
+    // Looking straight down at a spherical one.
+    if (dot(right, right) < 1e-8) {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    return mat3x3<f32>(right, cross(facing, right), facing);
+}
@@ -0,0 +1,568 @@
+use anyhow::bail;
+
+/// Decodes one mip level of a block compressed texture into 8-bit RGBA, for
+/// adapters that can't sample the format. `bc1_rgb` marks BC1 data from a
+/// format without alpha, whose three-colour blocks end in opaque black.
+pub(crate) fn decompress(
+    format: wgpu::TextureFormat,
+    bc1_rgb: bool,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> anyhow::Result<image::RgbaImage> {
+    use wgpu::TextureFormat as F;
+    let decode_block: fn(&[u8]) -> [[u8; 4]; 16] = match format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb if bc1_rgb => |block| bc1(block, Bc1::Rgb),
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => |block| bc1(block, Bc1::Rgba),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => bc2,
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => bc3,
+        F::Bc4RUnorm => bc4,
+        F::Bc5RgUnorm => bc5,
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => |block| etc2(block, false),
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => |block| etc2(block, true),
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => etc2_eac,
+        _ => bail!("no CPU decoder for {:?}", format),
+    };
+    let block_size = format.describe().block_size as usize;
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    let expected = (blocks_x * blocks_y) as usize * block_size;
+    if data.len() < expected {
+        bail!(
+            "{}x{} {:?} level needs {} bytes, got {}",
+            width,
+            height,
+            format,
+            expected,
+            data.len()
+        );
+    }
+
+    let mut img = image::RgbaImage::new(width, height);
+    for (index, block) in data[..expected].chunks_exact(block_size).enumerate() {
+        let (bx, by) = (index as u32 % blocks_x * 4, index as u32 / blocks_x * 4);
+        // Texels are row-major within a block, blocks past the edge are cut.
+        for (texel, rgba) in decode_block(block).into_iter().enumerate() {
+            let (x, y) = (bx + texel as u32 % 4, by + texel as u32 / 4);
+            if x < width && y < height {
+                img.put_pixel(x, y, image::Rgba(rgba));
+            }
+        }
+    }
+    Ok(img)
+}
+
+/// Whether `decompress` can decode `format`. BC6H and BC7 have no decoder.
+pub(crate) fn can_decompress(format: wgpu::TextureFormat) -> bool {
+    use wgpu::TextureFormat as F;
+    !matches!(
+        format,
+        F::Bc6hRgbUfloat | F::Bc6hRgbSfloat | F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb
+    )
+}
+
+/// Whether any BC1 block in `data` has a three-colour texel that's black,
+/// which only formats without alpha keep opaque.
+pub(crate) fn bc1_has_black(data: &[u8]) -> bool {
+    data.chunks_exact(8).any(|block| {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        c0 <= c1 && (0..16).any(|texel| indices >> (2 * texel) & 3 == 3)
+    })
+}
+
+fn rgb565(c: u16) -> [i32; 3] {
+    let (r, g, b) = ((c >> 11) & 31, (c >> 5) & 63, c & 31);
+    [
+        (r << 3 | r >> 2) as i32,
+        (g << 2 | g >> 4) as i32,
+        (b << 3 | b >> 2) as i32,
+    ]
+}
+
+/// How a BC1 colour block is read.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Bc1 {
+    /// Blocks whose first colour isn't the larger have three colours and
+    /// transparent black.
+    Rgba,
+    /// Like `Rgba`, but the black is opaque.
+    Rgb,
+    /// Always four colours, as the colour half of BC2 and BC3.
+    FourColour,
+}
+
+/// The colour half of BC1 to BC3.
+fn bc1(block: &[u8], variant: Bc1) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: i32, wb: i32, d: i32| {
+        let [r, g, bl] = [0, 1, 2].map(|i| ((a[i] * wa + b[i] * wb) / d) as u8);
+        [r, g, bl, 255]
+    };
+    let palette = if c0 > c1 || variant == Bc1::FourColour {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        let black = [0, 0, 0, if variant == Bc1::Rgb { 255 } else { 0 }];
+        [mix(1, 0, 1), mix(0, 1, 1), mix(1, 1, 2), black]
+    };
+    std::array::from_fn(|texel| palette[(indices >> (2 * texel) & 3) as usize])
+}
+
+fn bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = bc1(&block[8..], Bc1::FourColour);
+    for (texel, rgba) in texels.iter_mut().enumerate() {
+        rgba[3] = (alpha >> (4 * texel) & 15) as u8 * 17;
+    }
+    texels
+}
+
+fn bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = bc4_channel(&block[..8]);
+    let mut texels = bc1(&block[8..], Bc1::FourColour);
+    for (rgba, a) in texels.iter_mut().zip(alpha) {
+        rgba[3] = a;
+    }
+    texels
+}
+
+/// BC4 and the alpha of BC3: two endpoints and eight steps, or six steps
+/// plus 0 and 255 when the endpoints are in ascending order.
+fn bc4_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let palette: [u8; 8] = std::array::from_fn(|i| match i {
+        0 => a0 as u8,
+        1 => a1 as u8,
+        _ if a0 > a1 => ((a0 * (8 - i as u32) + a1 * (i as u32 - 1)) / 7) as u8,
+        6 => 0,
+        7 => 255,
+        _ => ((a0 * (6 - i as u32) + a1 * (i as u32 - 1)) / 5) as u8,
+    });
+    std::array::from_fn(|texel| palette[(indices >> (3 * texel) & 7) as usize])
+}
+
+/// Red only, as the GPU would sample it.
+fn bc4(block: &[u8]) -> [[u8; 4]; 16] {
+    bc4_channel(block).map(|r| [r, 0, 0, 255])
+}
+
+fn bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let (red, green) = (bc4_channel(&block[..8]), bc4_channel(&block[8..]));
+    std::array::from_fn(|texel| [red[texel], green[texel], 0, 255])
+}
+
+const ETC_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+fn extend4(c: u64) -> i32 {
+    (c << 4 | c) as i32
+}
+
+fn extend5(c: u64) -> i32 {
+    (c << 3 | c >> 2) as i32
+}
+
+/// ETC2 colour blocks, which include ETC1's. With `punchthrough` the
+/// individual mode bit instead says whether the block is opaque, and
+/// non-opaque blocks turn one index into transparent black.
+fn etc2(block: &[u8], punchthrough: bool) -> [[u8; 4]; 16] {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let field = |shift: u32, len: u32| bits >> shift & ((1 << len) - 1);
+    let differential = punchthrough || field(33, 1) == 1;
+    let opaque = !punchthrough || field(33, 1) == 1;
+    let flip = field(32, 1) == 1;
+    // Two bits per texel split across the low half, column-major.
+    let index = |texel: usize| {
+        let i = texel % 4 * 4 + texel / 4;
+        (field(16 + i as u32, 1) << 1 | field(i as u32, 1)) as usize
+    };
+    let clamp = |c: i32| c.clamp(0, 255) as u8;
+    let paint = |colours: [[i32; 3]; 4]| -> [[u8; 4]; 16] {
+        std::array::from_fn(|texel| {
+            let i = index(texel);
+            if !opaque && i == 2 {
+                return [0; 4];
+            }
+            let [r, g, b] = colours[i].map(clamp);
+            [r, g, b, 255]
+        })
+    };
+
+    if !differential {
+        let base = |shift: u32| {
+            [
+                extend4(field(shift + 24, 4)),
+                extend4(field(shift + 16, 4)),
+                extend4(field(shift + 8, 4)),
+            ]
+        };
+        return etc1_subblocks(bits, [base(36), base(32)], flip, opaque, index);
+    }
+
+    let (r, g, b) = (
+        field(59, 5) as i32,
+        field(51, 5) as i32,
+        field(43, 5) as i32,
+    );
+    let delta = |shift: u32| ((field(shift, 3) as i32) << 29) >> 29;
+    let (r2, g2, b2) = (r + delta(56), g + delta(48), b + delta(40));
+    if !(0..32).contains(&r2) {
+        // T mode: one colour alone, the other spread either side.
+        let c1 = [
+            extend4(field(59, 2) << 2 | field(56, 2)),
+            extend4(field(52, 4)),
+            extend4(field(48, 4)),
+        ];
+        let c2 = [
+            extend4(field(44, 4)),
+            extend4(field(40, 4)),
+            extend4(field(36, 4)),
+        ];
+        let d = ETC_DISTANCES[(field(34, 2) << 1 | field(32, 1)) as usize];
+        paint([c1, c2.map(|c| c + d), c2, c2.map(|c| c - d)])
+    } else if !(0..32).contains(&g2) {
+        // H mode: both colours spread either side, the distance's lowest bit
+        // given by their order.
+        let c1 = [
+            field(59, 4),
+            field(56, 3) << 1 | field(52, 1),
+            field(51, 1) << 3 | field(47, 3),
+        ];
+        let c2 = [field(43, 4), field(39, 4), field(35, 4)];
+        let order = |c: [u64; 3]| c[0] << 8 | c[1] << 4 | c[2];
+        let low = (order(c1) >= order(c2)) as u64;
+        let d = ETC_DISTANCES[(field(34, 1) << 2 | field(32, 1) << 1 | low) as usize];
+        let (c1, c2) = (c1.map(extend4), c2.map(extend4));
+        paint([
+            c1.map(|c| c + d),
+            c1.map(|c| c - d),
+            c2.map(|c| c + d),
+            c2.map(|c| c - d),
+        ])
+    } else if !(0..32).contains(&b2) {
+        // Planar mode: a gradient from three colours, always opaque.
+        let extend6 = |c: u64| (c << 2 | c >> 4) as i32;
+        let extend7 = |c: u64| (c << 1 | c >> 6) as i32;
+        let o = [
+            extend6(field(57, 6)),
+            extend7(field(56, 1) << 6 | field(49, 6)),
+            extend6(field(48, 1) << 5 | field(43, 2) << 3 | field(39, 3)),
+        ];
+        let h = [
+            extend6(field(34, 5) << 1 | field(32, 1)),
+            extend7(field(25, 7)),
+            extend6(field(19, 6)),
+        ];
+        let v = [
+            extend6(field(13, 6)),
+            extend7(field(6, 7)),
+            extend6(field(0, 6)),
+        ];
+        std::array::from_fn(|texel| {
+            let (x, y) = ((texel % 4) as i32, (texel / 4) as i32);
+            let [r, g, b] = [0, 1, 2]
+                .map(|i| clamp((x * (h[i] - o[i]) + y * (v[i] - o[i]) + 4 * o[i] + 2) >> 2));
+            [r, g, b, 255]
+        })
+    } else {
+        let bases = [
+            [r, g, b].map(|c| extend5(c as u64)),
+            [r2, g2, b2].map(|c| extend5(c as u64)),
+        ];
+        etc1_subblocks(bits, bases, flip, opaque, index)
+    }
+}
+
+/// ETC1's two sub-blocks, side by side or stacked when flipped, each a base
+/// colour shifted by one row of the modifier table.
+fn etc1_subblocks(
+    bits: u64,
+    bases: [[i32; 3]; 2],
+    flip: bool,
+    opaque: bool,
+    index: impl Fn(usize) -> usize,
+) -> [[u8; 4]; 16] {
+    let tables = [(bits >> 37 & 7) as usize, (bits >> 34 & 7) as usize];
+    std::array::from_fn(|texel| {
+        let (x, y) = (texel % 4, texel / 4);
+        let subblock = if flip { y / 2 } else { x / 2 };
+        let i = index(texel);
+        let [small, large] = ETC_MODIFIERS[tables[subblock]];
+        let modifier = match i {
+            // Punchthrough blocks that aren't opaque have no small steps.
+            0 | 2 if !opaque => 0,
+            0 => small,
+            1 => large,
+            2 => -small,
+            _ => -large,
+        };
+        if !opaque && i == 2 {
+            return [0; 4];
+        }
+        let [r, g, b] = bases[subblock].map(|c| (c + modifier).clamp(0, 255) as u8);
+        [r, g, b, 255]
+    })
+}
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// ETC2 RGBA: an EAC alpha block followed by an opaque colour block.
+fn etc2_eac(block: &[u8]) -> [[u8; 4]; 16] {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let base = (bits >> 56) as i32;
+    let multiplier = (bits >> 52 & 15) as i32;
+    let modifiers = EAC_MODIFIERS[(bits >> 48 & 15) as usize];
+    let mut texels = etc2(&block[8..], false);
+    for (texel, rgba) in texels.iter_mut().enumerate() {
+        // Three bits per texel from the top, column-major.
+        let i = texel % 4 * 4 + texel / 4;
+        let index = (bits >> (45 - 3 * i) & 7) as usize;
+        rgba[3] = (base + modifiers[index] * multiplier).clamp(0, 255) as u8;
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indices 0 to 3 across the top row and 0 elsewhere, in the
+    /// three-colour mode when `c0 <= c1`.
+    fn bc1_block(c0: u16, c1: u16) -> [u8; 8] {
+        let [a, b] = c0.to_le_bytes();
+        let [c, d] = c1.to_le_bytes();
+        [a, b, c, d, 0b11_10_01_00, 0, 0, 0]
+    }
+
+    #[test]
+    fn bc1_four_colours() {
+        let texels = bc1(&bc1_block(0xf800, 0x001f), Bc1::Rgba);
+        assert_eq!(texels[0], [255, 0, 0, 255]);
+        assert_eq!(texels[1], [0, 0, 255, 255]);
+        assert_eq!(texels[2], [170, 0, 85, 255]);
+        assert_eq!(texels[3], [85, 0, 170, 255]);
+        assert_eq!(texels[15], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc1_three_colours_and_black() {
+        let block = bc1_block(0x001f, 0xf800);
+        let texels = bc1(&block, Bc1::Rgba);
+        assert_eq!(texels[0], [0, 0, 255, 255]);
+        assert_eq!(texels[1], [255, 0, 0, 255]);
+        assert_eq!(texels[2], [127, 0, 127, 255]);
+        assert_eq!(texels[3], [0, 0, 0, 0]);
+        // Formats without alpha keep the black opaque.
+        assert_eq!(bc1(&block, Bc1::Rgb)[3], [0, 0, 0, 255]);
+        assert!(bc1_has_black(&block));
+        assert!(!bc1_has_black(&bc1_block(0xf800, 0x001f)));
+    }
+
+    #[test]
+    fn bc2_explicit_alpha_and_four_colours() {
+        let mut block = [0; 16];
+        block[0] = 0x8f;
+        block[8..].copy_from_slice(&bc1_block(0x001f, 0xf800));
+        let texels = bc2(&block);
+        assert_eq!(texels[0], [0, 0, 255, 255]);
+        assert_eq!(texels[1], [255, 0, 0, 136]);
+        // No three-colour mode, whichever endpoint is larger.
+        assert_eq!(texels[2], [85, 0, 170, 0]);
+        assert_eq!(texels[3], [170, 0, 85, 0]);
+    }
+
+    /// Endpoints followed by three-bit indices 0, 1, 2 and 7 across the top
+    /// row, or 2, 5, 6 and 7 with `six_steps`.
+    fn bc4_block(a0: u8, a1: u8, six_steps: bool) -> [u8; 8] {
+        let indices: u16 = if six_steps { 0x0faa } else { 0x0e88 };
+        let [lo, hi] = indices.to_le_bytes();
+        [a0, a1, lo, hi, 0, 0, 0, 0]
+    }
+
+    #[test]
+    fn bc4_eight_steps() {
+        let texels = bc4(&bc4_block(200, 100, false));
+        let reds: Vec<u8> = texels[..5].iter().map(|texel| texel[0]).collect();
+        assert_eq!(reds, [200, 100, 185, 114, 200]);
+        assert_eq!(texels[0], [200, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc4_six_steps_with_black_and_white() {
+        let reds = bc4_channel(&bc4_block(100, 200, true));
+        assert_eq!(reds[..5], [120, 180, 0, 255, 100]);
+    }
+
+    #[test]
+    fn bc3_and_bc5_combine_channels() {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&bc4_block(200, 100, false));
+        block[8..].copy_from_slice(&bc1_block(0xf800, 0x001f));
+        let texels = bc3(&block);
+        assert_eq!(texels[1], [0, 0, 255, 100]);
+        assert_eq!(texels[3], [85, 0, 170, 114]);
+
+        block[8..].copy_from_slice(&bc4_block(100, 200, true));
+        let texels = bc5(&block);
+        assert_eq!(texels[0], [200, 120, 0, 255]);
+        assert_eq!(texels[3], [114, 255, 0, 255]);
+    }
+
+    /// Texel at `x`, `y` of a decoded block.
+    fn at(texels: &[[u8; 4]; 16], x: usize, y: usize) -> [u8; 4] {
+        texels[y * 4 + x]
+    }
+
+    #[test]
+    fn etc1_individual_mode() {
+        // Bases 0x88 then 0x44 grey side by side, tables 0 and 7. Index 3
+        // at (3, 0) and 1 at (0, 1), the rest 0.
+        let block = [0x84, 0x84, 0x84, 0x1c, 0x10, 0x00, 0x10, 0x02];
+        let texels = etc2(&block, false);
+        assert_eq!(at(&texels, 0, 0), [138, 138, 138, 255]);
+        assert_eq!(at(&texels, 0, 1), [144, 144, 144, 255]);
+        assert_eq!(at(&texels, 2, 0), [115, 115, 115, 255]);
+        assert_eq!(at(&texels, 3, 0), [0, 0, 0, 255]);
+    }
+
+    /// Base 16 grey, then 17, 15 and 16 by difference, stacked with tables 1
+    /// and 2. Index 2 at (1, 3), the rest 0.
+    const DIFFERENTIAL: [u8; 8] = [0x81, 0x87, 0x80, 0x2b, 0x00, 0x80, 0x00, 0x00];
+
+    #[test]
+    fn etc1_differential_mode() {
+        let texels = etc2(&DIFFERENTIAL, false);
+        assert_eq!(at(&texels, 0, 0), [137, 137, 137, 255]);
+        assert_eq!(at(&texels, 0, 2), [149, 132, 141, 255]);
+        assert_eq!(at(&texels, 1, 3), [131, 114, 123, 255]);
+    }
+
+    #[test]
+    fn etc2_punchthrough_drops_small_steps_and_index_two() {
+        let mut block = DIFFERENTIAL;
+        // Clearing the differential bit marks the block as not opaque.
+        block[3] &= !0x02;
+        let texels = etc2(&block, true);
+        assert_eq!(at(&texels, 0, 0), [132, 132, 132, 255]);
+        assert_eq!(at(&texels, 1, 3), [0, 0, 0, 0]);
+        // Set, it's decoded like any differential block.
+        assert_eq!(etc2(&DIFFERENTIAL, true), etc2(&DIFFERENTIAL, false));
+    }
+
+    /// Indices 0 to 3 across the top row, the rest 0.
+    const ACROSS: [u8; 4] = [0x11, 0x00, 0x10, 0x10];
+
+    #[test]
+    fn etc2_t_mode() {
+        // Red overflows. Colours 0xa3c and 0x888, distance 11.
+        let mut block = [0xf2, 0x3c, 0x88, 0x86, 0, 0, 0, 0];
+        block[4..].copy_from_slice(&ACROSS);
+        let texels = etc2(&block, false);
+        assert_eq!(at(&texels, 0, 0), [170, 51, 204, 255]);
+        assert_eq!(at(&texels, 1, 0), [147, 147, 147, 255]);
+        assert_eq!(at(&texels, 2, 0), [136, 136, 136, 255]);
+        assert_eq!(at(&texels, 3, 0), [125, 125, 125, 255]);
+
+        block[3] &= !0x02;
+        let texels = etc2(&block, true);
+        assert_eq!(at(&texels, 0, 0), [170, 51, 204, 255]);
+        assert_eq!(at(&texels, 2, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn etc2_h_mode() {
+        // Green overflows. Colours 0xc5b and 0x444, the first larger, so
+        // distance 16.
+        let mut block = [0x62, 0xf9, 0xa2, 0x23, 0, 0, 0, 0];
+        block[4..].copy_from_slice(&ACROSS);
+        let texels = etc2(&block, false);
+        assert_eq!(at(&texels, 0, 0), [220, 101, 203, 255]);
+        assert_eq!(at(&texels, 1, 0), [188, 69, 171, 255]);
+        assert_eq!(at(&texels, 2, 0), [84, 84, 84, 255]);
+        assert_eq!(at(&texels, 3, 0), [52, 52, 52, 255]);
+    }
+
+    #[test]
+    fn etc2_planar_mode() {
+        // Blue overflows. Origin (130, 129, 0), horizontal (255, 0, 0) and
+        // vertical (130, 255, 255).
+        let block = [0x41, 0x00, 0x04, 0x7f, 0x00, 0x04, 0x1f, 0xff];
+        let texels = etc2(&block, false);
+        assert_eq!(at(&texels, 0, 0), [130, 129, 0, 255]);
+        assert_eq!(at(&texels, 1, 0), [161, 97, 0, 255]);
+        assert_eq!(at(&texels, 0, 1), [130, 161, 64, 255]);
+        assert_eq!(at(&texels, 3, 3), [224, 127, 191, 255]);
+    }
+
+    #[test]
+    fn eac_alpha() {
+        // Base 128, multiplier 2, table 0. Column-major indices: 7 at (0, 0),
+        // 0 at (0, 1), 3 at (1, 0) and 4 elsewhere.
+        let indices = (0..16).fold(0u64, |bits, i| {
+            let index = match i {
+                0 => 7,
+                1 => 0,
+                4 => 3,
+                _ => 4,
+            };
+            bits | index << (45 - 3 * i)
+        });
+        let alpha = (128u64 << 56 | 2 << 52 | indices).to_be_bytes();
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&alpha);
+        block[8..].copy_from_slice(&DIFFERENTIAL);
+        let texels = etc2_eac(&block);
+        assert_eq!(at(&texels, 0, 0), [137, 137, 137, 156]);
+        assert_eq!(at(&texels, 0, 1)[3], 122);
+        assert_eq!(at(&texels, 1, 0)[3], 98);
+        assert_eq!(at(&texels, 3, 3)[3], 132);
+    }
+
+    #[test]
+    fn decompress_crops_partial_blocks() {
+        use wgpu::TextureFormat as F;
+        let block = bc1_block(0x001f, 0xf800);
+        let img = decompress(F::Bc1RgbaUnorm, false, 3, 2, &block).unwrap();
+        assert_eq!(img.dimensions(), (3, 2));
+        assert_eq!(img.get_pixel(2, 0).0, [127, 0, 127, 255]);
+        let rgb_img = decompress(F::Bc1RgbaUnorm, true, 4, 1, &block).unwrap();
+        assert_eq!(rgb_img.get_pixel(3, 0).0, [0, 0, 0, 255]);
+        assert_eq!(rgb_img.get_pixel(0, 0).0, [0, 0, 255, 255]);
+
+        assert!(decompress(F::Bc1RgbaUnorm, false, 8, 4, &block).is_err());
+        assert!(decompress(F::Bc7RgbaUnorm, false, 4, 4, &[0; 16]).is_err());
+        assert!(!can_decompress(F::Bc7RgbaUnormSrgb));
+    }
+}
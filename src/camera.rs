@@ -29,22 +29,25 @@ impl Camera {
     }
 
     pub(crate) fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), Vector3::unit_y())
+    }
+
+    /// Unit vector the camera is looking along.
+    pub(crate) fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
-
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
     }
 }
 
 pub(crate) struct Projection {
     pub aspect: f32,
-    fovy: Rad<f32>,
-    znear: f32,
-    zfar: f32,
+    /// Size of the viewport in pixels.
+    pub width: u32,
+    pub height: u32,
+    pub fovy: Rad<f32>,
+    pub znear: f32,
+    pub zfar: f32,
 }
 
 impl Projection {
@@ -57,6 +60,8 @@ impl Projection {
     ) -> Self {
         Self {
             aspect: width as f32 / height as f32,
+            width,
+            height,
             fovy: fovy.into(),
             znear,
             zfar,
@@ -65,6 +70,8 @@ impl Projection {
 
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
+        self.width = width;
+        self.height = height;
     }
 
     pub(crate) fn calc_matrix(&self) -> Matrix4<f32> {
@@ -12,6 +12,8 @@ use crate::collision_detection::CollisionDetection;
 
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+/// Ground covered between footsteps, in world units.
+const STRIDE_LENGTH: f32 = 1.2;
 
 #[derive(Debug)]
 pub(crate) struct CameraController {
@@ -33,6 +35,9 @@ pub(crate) struct CameraController {
     left_vel: f32,
     jump_vel: f32,
     on_floor: bool,
+    pub max_step_height: f32,
+    /// Ground covered on foot since the last footstep.
+    stride: f32,
 }
 
 impl CameraController {
@@ -56,6 +61,8 @@ impl CameraController {
             left_vel: 0.0,
             jump_vel: 0.0,
             on_floor: false,
+            max_step_height: 0.6,
+            stride: 0.0,
         }
     }
 
@@ -159,9 +166,14 @@ impl CameraController {
     }
 
     fn movement(&mut self, collision: CollisionDetection, camera: &mut camera::Camera, dt: f32) {
+        let start = camera.position;
         if collision.up {
             self.on_floor = true;
             self.jump_vel = 0.0;
+            // Climb onto anything low enough instead of treating it as a wall.
+            if collision.step_up <= self.max_step_height {
+                camera.position.y += collision.step_up;
+            }
         } else {
             self.on_floor = false;
         }
@@ -248,5 +260,17 @@ impl CameraController {
         // modify the y coordinate directly.
         //camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
         camera.position.y += self.jump_vel * self.speed * dt;
+
+        match collision.ground.filter(|_| self.on_floor) {
+            Some(ground) => {
+                self.stride += Vector2::new(camera.position.x - start.x, camera.position.z - start.z)
+                    .magnitude();
+                if self.stride >= STRIDE_LENGTH {
+                    self.stride -= STRIDE_LENGTH;
+                    log::debug!("footstep on {:?}", ground);
+                }
+            }
+            None => self.stride = 0.0,
+        }
     }
 }
@@ -7,14 +7,17 @@ use crate::camera;
 pub(crate) struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
-    pub(crate) input_values: [f32; 4],
+    /// Viewport width and height in pixels, for sizing things on screen.
+    pub(crate) viewport: [f32; 4],
+    pub(crate) view_position: [f32; 4],
     pub(crate) view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
     pub(crate) fn new() -> Self {
         Self {
-            input_values: [0.0; 4],
+            viewport: [0.0; 4],
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
         }
     }
@@ -24,8 +27,8 @@ impl CameraUniform {
         camera: &camera::Camera,
         projection: &camera::Projection,
     ) {
-        //let cam_pos: [f32; 4] = camera.position.to_homogeneous().into();
-        self.input_values = [projection.aspect, 0.0, 0.0, 0.0];
+        self.viewport = [projection.width as f32, projection.height as f32, 0.0, 0.0];
+        self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
     }
 }
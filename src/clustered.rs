@@ -0,0 +1,286 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Transform, Vector3};
+
+use crate::{
+    camera::{Camera, Projection},
+    light::{PointLight, PointLightRaw},
+};
+
+pub(crate) const CLUSTERS_X: u32 = 16;
+pub(crate) const CLUSTERS_Y: u32 = 9;
+pub(crate) const CLUSTERS_Z: u32 = 24;
+const CLUSTER_COUNT: usize = (CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z) as usize;
+
+pub(crate) const MAX_LIGHTS: usize = 1024;
+/// Lights past this count in a single cluster are dropped.
+const MAX_LIGHTS_PER_CLUSTER: usize = 64;
+const MAX_LIGHT_INDICES: usize = CLUSTER_COUNT * 32;
+
+#[derive(Copy, Clone, Debug)]
+struct Aabb {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Aabb {
+    fn intersects_sphere(&self, centre: Vector3<f32>, radius: f32) -> bool {
+        let closest = Vector3::new(
+            centre.x.clamp(self.min.x, self.max.x),
+            centre.y.clamp(self.min.y, self.max.y),
+            centre.z.clamp(self.min.z, self.max.z),
+        );
+        (closest - centre).magnitude2() <= radius * radius
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    /// Cluster counts along x, y and z, then the number of lights.
+    grid: [u32; 4],
+    /// Screen width and height, then near and far planes.
+    screen: [f32; 4],
+    /// Camera forward vector, for the view depth of a fragment.
+    forward: [f32; 4],
+}
+
+/// Assigns lights to view-space clusters on the CPU and keeps the GPU copies in sync.
+///
+/// Clusters split the screen into tiles and the view depth into slices that grow
+/// exponentially between the near and far planes, so nearby slices stay thin.
+pub(crate) struct LightClusters {
+    /// View-space bounds of each cluster, x fastest, then y, then z.
+    bounds: Vec<Aabb>,
+    znear: f32,
+    zfar: f32,
+    width: u32,
+    height: u32,
+    light_buffer: wgpu::Buffer,
+    cluster_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl LightClusters {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        projection: &Projection,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let storage = |label, size: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let light_buffer = storage(
+            "Point Light Buffer",
+            MAX_LIGHTS * std::mem::size_of::<PointLightRaw>(),
+        );
+        let cluster_buffer = storage("Cluster Buffer", CLUSTER_COUNT * 2 * 4);
+        let index_buffer = storage("Cluster Light Index Buffer", MAX_LIGHT_INDICES * 4);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Uniform Buffer"),
+            size: std::mem::size_of::<ClusterUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_cluster_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: cluster_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: index_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_cluster_bind_group"),
+        });
+
+        let mut clusters = Self {
+            bounds: Vec::new(),
+            znear: projection.znear,
+            zfar: projection.zfar,
+            width,
+            height,
+            light_buffer,
+            cluster_buffer,
+            index_buffer,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+        };
+        clusters.resize(projection, width, height);
+        clusters
+    }
+
+    /// Rebuilds the cluster bounds after the projection or the window changes.
+    pub(crate) fn resize(&mut self, projection: &Projection, width: u32, height: u32) {
+        self.znear = projection.znear;
+        self.zfar = projection.zfar;
+        self.width = width.max(1);
+        self.height = height.max(1);
+
+        let tan_y = (projection.fovy.0 / 2.0).tan();
+        let tan_x = tan_y * projection.aspect;
+        self.bounds.clear();
+        for k in 0..CLUSTERS_Z {
+            let near = self.slice_depth(k);
+            let far = self.slice_depth(k + 1);
+            for j in 0..CLUSTERS_Y {
+                // Tile rows count down from the top of the screen.
+                let y0 = 1.0 - 2.0 * (j + 1) as f32 / CLUSTERS_Y as f32;
+                let y1 = 1.0 - 2.0 * j as f32 / CLUSTERS_Y as f32;
+                for i in 0..CLUSTERS_X {
+                    let x0 = -1.0 + 2.0 * i as f32 / CLUSTERS_X as f32;
+                    let x1 = -1.0 + 2.0 * (i + 1) as f32 / CLUSTERS_X as f32;
+                    let mut aabb = Aabb {
+                        min: Vector3::new(f32::MAX, f32::MAX, -far),
+                        max: Vector3::new(f32::MIN, f32::MIN, -near),
+                    };
+                    for depth in [near, far] {
+                        for x in [x0, x1] {
+                            for y in [y0, y1] {
+                                let (vx, vy) = (x * tan_x * depth, y * tan_y * depth);
+                                aabb.min.x = aabb.min.x.min(vx);
+                                aabb.min.y = aabb.min.y.min(vy);
+                                aabb.max.x = aabb.max.x.max(vx);
+                                aabb.max.y = aabb.max.y.max(vy);
+                            }
+                        }
+                    }
+                    self.bounds.push(aabb);
+                }
+            }
+        }
+    }
+
+    /// View depth where slice `k` starts.
+    fn slice_depth(&self, k: u32) -> f32 {
+        self.znear * (self.zfar / self.znear).powf(k as f32 / CLUSTERS_Z as f32)
+    }
+
+    fn slice_of(&self, depth: f32) -> u32 {
+        if depth <= self.znear {
+            return 0;
+        }
+        let slice = (depth / self.znear).ln() / (self.zfar / self.znear).ln() * CLUSTERS_Z as f32;
+        (slice as u32).min(CLUSTERS_Z - 1)
+    }
+
+    /// Assigns `lights` to clusters for this frame's camera and uploads the result.
+    /// `shadow_layers` holds the shadow map layer of each light, if it has one.
+    pub(crate) fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        lights: &[PointLight],
+        shadow_layers: &[Option<u32>],
+    ) {
+        let lights = &lights[..lights.len().min(MAX_LIGHTS)];
+        let view: Matrix4<f32> = camera.calc_matrix();
+
+        let mut cluster_lights: Vec<Vec<u32>> = vec![Vec::new(); CLUSTER_COUNT];
+        for (index, light) in lights.iter().enumerate() {
+            let centre = view
+                .transform_point(cgmath::Point3::from_vec(light.position))
+                .to_vec();
+            let depth = -centre.z;
+            if depth + light.range < self.znear || depth - light.range > self.zfar {
+                continue;
+            }
+            let first = self.slice_of(depth - light.range);
+            let last = self.slice_of(depth + light.range);
+            for k in first..=last {
+                let per_slice = (CLUSTERS_X * CLUSTERS_Y) as usize;
+                let slice = k as usize * per_slice;
+                let clusters = cluster_lights[slice..slice + per_slice]
+                    .iter_mut()
+                    .zip(&self.bounds[slice..slice + per_slice]);
+                for (list, bounds) in clusters {
+                    if list.len() < MAX_LIGHTS_PER_CLUSTER
+                        && bounds.intersects_sphere(centre, light.range)
+                    {
+                        list.push(index as u32);
+                    }
+                }
+            }
+        }
+
+        let mut grid = Vec::with_capacity(CLUSTER_COUNT * 2);
+        let mut indices = Vec::new();
+        for list in &cluster_lights {
+            let room = MAX_LIGHT_INDICES - indices.len();
+            let count = list.len().min(room);
+            grid.push(indices.len() as u32);
+            grid.push(count as u32);
+            indices.extend_from_slice(&list[..count]);
+        }
+        if indices.is_empty() {
+            indices.push(0);
+        }
+
+        let raw = lights
+            .iter()
+            .enumerate()
+            .map(|(index, light)| light.to_raw(shadow_layers.get(index).copied().flatten()))
+            .collect::<Vec<_>>();
+        if !raw.is_empty() {
+            queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+        queue.write_buffer(&self.cluster_buffer, 0, bytemuck::cast_slice(&grid));
+        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+
+        let forward = camera.forward();
+        let uniform = ClusterUniform {
+            grid: [CLUSTERS_X, CLUSTERS_Y, CLUSTERS_Z, lights.len() as u32],
+            screen: [self.width as f32, self.height as f32, self.znear, self.zfar],
+            forward: [forward.x, forward.y, forward.z, 0.0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{camera, cube::Cube, floor::Floor, instance::Instance, slope::Slope};
+use crate::{
+    camera,
+    instance::Instance,
+    level::LoadedLayer,
+    material::Footstep,
+    primitive::{CollisionShape, Primitive},
+};
 
 pub(crate) struct CollisionDetection {
     pub left: bool,
@@ -7,6 +13,10 @@ pub(crate) struct CollisionDetection {
     pub backward: bool,
     pub up: bool,
     pub down: bool,
+    /// How far the camera has to rise to stand on the ground beneath it.
+    pub step_up: f32,
+    /// What the camera is standing on, if anything.
+    pub ground: Option<Footstep>,
 }
 
 impl CollisionDetection {
@@ -18,97 +28,124 @@ impl CollisionDetection {
             backward: false,
             up: false,
             down: false,
+            step_up: 0.0,
+            ground: None,
         }
     }
 
-    pub fn detect(&mut self, camera: &mut camera::Camera, instances: &[Instance], cube: &mut Cube) {
+    /// Runs every layer of a level through the detector matching its collision shape.
+    pub fn detect_level(
+        &mut self,
+        camera: &mut camera::Camera,
+        layers: &[LoadedLayer],
+        max_step_height: f32,
+    ) {
+        for layer in layers {
+            if layer.primitive.collision_shape() == CollisionShape::Solid {
+                self.detect(camera, &layer.instances, layer.primitive.as_ref());
+                if self.up && self.ground.is_none() {
+                    self.ground = Some(layer.footstep);
+                }
+            }
+        }
+
+        for shape in [CollisionShape::Ramp, CollisionShape::Steps, CollisionShape::Floor] {
+            for layer in layers {
+                if self.up || layer.primitive.collision_shape() != shape {
+                    continue;
+                }
+                let primitive = layer.primitive.as_ref();
+                match shape {
+                    CollisionShape::Ramp => self.slope_detect(camera, &layer.instances, primitive),
+                    CollisionShape::Steps => {
+                        self.stairs_detect(camera, &layer.instances, primitive, max_step_height)
+                    }
+                    _ => self.floor_detect(camera, &layer.instances, primitive),
+                }
+                if self.up {
+                    self.ground = Some(layer.footstep);
+                }
+            }
+        }
+    }
+
+    pub fn detect(&mut self, camera: &mut camera::Camera, instances: &[Instance], cube: &dyn Primitive) {
+        let cube = cube.bounds().half_extents();
         for instance in instances {
-            if (camera.position.x < (instance.position.x + cube.width + 0.5))
-                && (camera.position.x > (instance.position.x + cube.width))
-                && (camera.position.z < (instance.position.z + cube.depth + 0.3))
-                && (camera.position.z > (instance.position.z - (cube.depth + 0.3)))
-                && (camera.position.y < (instance.position.y + cube.height + 0.7))
-                && (camera.position.y > (instance.position.y - (cube.height + 0.7)))
+            if (camera.position.x < (instance.position.x + cube.x + 0.5))
+                && (camera.position.x > (instance.position.x + cube.x))
+                && (camera.position.z < (instance.position.z + cube.z + 0.3))
+                && (camera.position.z > (instance.position.z - (cube.z + 0.3)))
+                && (camera.position.y < (instance.position.y + cube.y + 0.7))
+                && (camera.position.y > (instance.position.y - (cube.y + 0.7)))
             {
                 self.left = true;
                 break;
-            } else {
-                self.left = false;
             }
         }
 
         for instance in instances {
-            if (camera.position.x < (instance.position.x - cube.width))
-                && (camera.position.x > (instance.position.x - (cube.width + 0.5)))
-                && (camera.position.z < (instance.position.z + cube.depth + 0.3))
-                && (camera.position.z > (instance.position.z - (cube.depth + 0.3)))
-                && (camera.position.y < (instance.position.y + cube.height + 0.7))
-                && (camera.position.y > (instance.position.y - (cube.height + 0.7)))
+            if (camera.position.x < (instance.position.x - cube.x))
+                && (camera.position.x > (instance.position.x - (cube.x + 0.5)))
+                && (camera.position.z < (instance.position.z + cube.z + 0.3))
+                && (camera.position.z > (instance.position.z - (cube.z + 0.3)))
+                && (camera.position.y < (instance.position.y + cube.y + 0.7))
+                && (camera.position.y > (instance.position.y - (cube.y + 0.7)))
             {
                 self.right = true;
                 break;
-            } else {
-                self.right = false;
             }
         }
 
         for instance in instances {
-            if (camera.position.x < (instance.position.x + cube.width + 0.3))
-                && (camera.position.x > (instance.position.x - (cube.width + 0.3)))
-                && (camera.position.z < (instance.position.z + cube.depth + 0.5))
-                && (camera.position.z > (instance.position.z + cube.depth))
-                && (camera.position.y < (instance.position.y + cube.height + 0.7))
-                && (camera.position.y > (instance.position.y - (cube.height + 0.7)))
+            if (camera.position.x < (instance.position.x + cube.x + 0.3))
+                && (camera.position.x > (instance.position.x - (cube.x + 0.3)))
+                && (camera.position.z < (instance.position.z + cube.z + 0.5))
+                && (camera.position.z > (instance.position.z + cube.z))
+                && (camera.position.y < (instance.position.y + cube.y + 0.7))
+                && (camera.position.y > (instance.position.y - (cube.y + 0.7)))
             {
                 self.forward = true;
                 break;
-            } else {
-                self.forward = false;
             }
         }
 
         for instance in instances {
-            if (camera.position.x < (instance.position.x + cube.width + 0.3))
-                && (camera.position.x > (instance.position.x - (cube.width + 0.3)))
-                && (camera.position.z < (instance.position.z - cube.depth))
-                && (camera.position.z > (instance.position.z - (cube.depth + 0.5)))
-                && (camera.position.y < (instance.position.y + cube.height + 0.7))
-                && (camera.position.y > (instance.position.y - (cube.height + 0.7)))
+            if (camera.position.x < (instance.position.x + cube.x + 0.3))
+                && (camera.position.x > (instance.position.x - (cube.x + 0.3)))
+                && (camera.position.z < (instance.position.z - cube.z))
+                && (camera.position.z > (instance.position.z - (cube.z + 0.5)))
+                && (camera.position.y < (instance.position.y + cube.y + 0.7))
+                && (camera.position.y > (instance.position.y - (cube.y + 0.7)))
             {
                 self.backward = true;
                 break;
-            } else {
-                self.backward = false;
             }
         }
 
         for instance in instances {
-            if (camera.position.x < (instance.position.x + cube.width + 0.3))
-                && (camera.position.x > (instance.position.x - (cube.width + 0.3)))
-                && (camera.position.z < (instance.position.z + cube.depth + 0.3))
-                && (camera.position.z > (instance.position.z - (cube.depth + 0.3)))
-                && (camera.position.y < (instance.position.y + cube.height + 1.0))
-                && (camera.position.y > (instance.position.y + cube.height + 0.5))
+            if (camera.position.x < (instance.position.x + cube.x + 0.3))
+                && (camera.position.x > (instance.position.x - (cube.x + 0.3)))
+                && (camera.position.z < (instance.position.z + cube.z + 0.3))
+                && (camera.position.z > (instance.position.z - (cube.z + 0.3)))
+                && (camera.position.y < (instance.position.y + cube.y + 1.0))
+                && (camera.position.y > (instance.position.y + cube.y + 0.5))
             {
                 self.up = true;
                 break;
-            } else {
-                self.up = false;
             }
         }
 
         for instance in instances {
-            if (camera.position.x < (instance.position.x + cube.width + 0.3))
-                && (camera.position.x > (instance.position.x - (cube.width + 0.3)))
-                && (camera.position.z < (instance.position.z + cube.depth + 0.3))
-                && (camera.position.z > (instance.position.z - (cube.depth + 0.3)))
-                && (camera.position.y < (instance.position.y - cube.height))
-                && (camera.position.y > (instance.position.y - (cube.height + 0.5)))
+            if (camera.position.x < (instance.position.x + cube.x + 0.3))
+                && (camera.position.x > (instance.position.x - (cube.x + 0.3)))
+                && (camera.position.z < (instance.position.z + cube.z + 0.3))
+                && (camera.position.z > (instance.position.z - (cube.z + 0.3)))
+                && (camera.position.y < (instance.position.y - cube.y))
+                && (camera.position.y > (instance.position.y - (cube.y + 0.5)))
             {
                 self.down = true;
                 break;
-            } else {
-                self.down = false;
             }
         }
     }
@@ -117,15 +154,16 @@ impl CollisionDetection {
         &mut self,
         camera: &mut camera::Camera,
         instances: &[Instance],
-        floor: &mut Floor,
+        floor: &dyn Primitive,
     ) {
+        let floor = floor.bounds().half_extents();
         for instance in instances {
-            if (camera.position.x < (instance.position.x + floor.width + 0.3))
-                && (camera.position.x > (instance.position.x - (floor.width + 0.3)))
-                && (camera.position.z < (instance.position.z + floor.depth + 0.3))
-                && (camera.position.z > (instance.position.z - (floor.depth + 0.3)))
+            if (camera.position.x < (instance.position.x + floor.x + 0.3))
+                && (camera.position.x > (instance.position.x - (floor.x + 0.3)))
+                && (camera.position.z < (instance.position.z + floor.z + 0.3))
+                && (camera.position.z > (instance.position.z - (floor.z + 0.3)))
                 && (camera.position.y < (instance.position.y))
-                && (camera.position.y > (instance.position.y - (floor.height / 2.0)))
+                && (camera.position.y > (instance.position.y - (floor.y / 2.0)))
             {
                 self.up = true;
                 break;
@@ -139,37 +177,81 @@ impl CollisionDetection {
         &mut self,
         camera: &mut camera::Camera,
         instances: &[Instance],
-        slope: &mut Slope,
+        slope: &dyn Primitive,
     ) {
-        if slope.orientation == 2 {
-            for instance in instances {
-                if (camera.position.x < (instance.position.x + slope.width + 0.2))
-                    && (camera.position.x > (instance.position.x - (slope.width + 0.2)))
-                    && (camera.position.z < (instance.position.z + slope.depth + 0.2))
-                    && (camera.position.z > (instance.position.z - (slope.depth + 0.2)))
-                    && (camera.position.y
-                        < (instance.position.y
-                            + (1.0
-                                - (camera.position.z - instance.position.z)
-                                    * (slope.height / slope.depth))
-                            + 0.4))
-                    && (camera.position.y
-                        > (instance.position.y
-                            + (1.0
-                                - (camera.position.z - instance.position.z)
-                                    * (slope.height / slope.depth))
-                            - 0.4))
-                {
-                    camera.position.y = instance.position.y
-                        + (1.0
-                            - (camera.position.z - instance.position.z)
-                                * (slope.height / slope.depth))
-                        + 0.3;
+        let size = slope.bounds().half_extents();
+        for instance in instances {
+            let surface = instance.position.y
+                + slope.surface_height(
+                    camera.position.x - instance.position.x,
+                    camera.position.z - instance.position.z,
+                )
+                + 1.0;
+            if (camera.position.x < (instance.position.x + size.x + 0.2))
+                && (camera.position.x > (instance.position.x - (size.x + 0.2)))
+                && (camera.position.z < (instance.position.z + size.z + 0.2))
+                && (camera.position.z > (instance.position.z - (size.z + 0.2)))
+                && (camera.position.y < (surface + 0.4))
+                && (camera.position.y > (surface - 0.4))
+            {
+                camera.position.y = surface + 0.3;
+
+                self.up = true;
+                break;
+            } else {
+                self.up = false;
+            }
+        }
+    }
 
+    /// Stands the camera on the treads it is over, and treats steps taller than
+    /// `max_step_height` as walls.
+    pub fn stairs_detect(
+        &mut self,
+        camera: &mut camera::Camera,
+        instances: &[Instance],
+        stairs: &dyn Primitive,
+        max_step_height: f32,
+    ) {
+        let size = stairs.bounds().half_extents();
+        let rest_height = |instance: &Instance, x: f32, z: f32| {
+            instance.position.y
+                + stairs.surface_height(x - instance.position.x, z - instance.position.z)
+                + 1.3
+        };
+        let over = |instance: &Instance, x: f32, z: f32| {
+            (x < (instance.position.x + size.x + 0.2))
+                && (x > (instance.position.x - (size.x + 0.2)))
+                && (z < (instance.position.z + size.z + 0.2))
+                && (z > (instance.position.z - (size.z + 0.2)))
+        };
+
+        self.up = false;
+        for instance in instances {
+            if over(instance, camera.position.x, camera.position.z) {
+                let step = rest_height(instance, camera.position.x, camera.position.z)
+                    - camera.position.y;
+                if step > -0.4 && step <= max_step_height {
+                    self.step_up = step;
                     self.up = true;
                     break;
-                } else {
-                    self.up = false;
+                }
+            }
+        }
+
+        let probes = [(-0.5, 0.0), (0.5, 0.0), (0.0, -0.5), (0.0, 0.5)];
+        for (dx, dz) in probes {
+            let (x, z) = (camera.position.x + dx, camera.position.z + dz);
+            let blocked = instances.iter().any(|instance| {
+                over(instance, x, z)
+                    && rest_height(instance, x, z) - camera.position.y > max_step_height
+            });
+            if blocked {
+                match (dx < 0.0, dx > 0.0, dz < 0.0) {
+                    (true, _, _) => self.left = true,
+                    (_, true, _) => self.right = true,
+                    (_, _, true) => self.forward = true,
+                    _ => self.backward = true,
                 }
             }
         }
@@ -1,4 +1,7 @@
-use crate::model::ModelVertex;
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+};
 
 pub(crate) struct Cube {
     pub width: f32,
@@ -10,7 +13,7 @@ pub(crate) struct Cube {
 
 impl Cube {
     pub(crate) fn new(width: f32, height: f32, depth: f32) -> Self {
-        Self {
+        let mut cube = Self {
             width,
             height,
             depth,
@@ -19,133 +22,225 @@ impl Cube {
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 //Z backward
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, -1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, -1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, -1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, -1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 //X left
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 //X right
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [-1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [-1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [-1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [-1.0, 0.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 //Y top
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 //Y bottom
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, -1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, -1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, -1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, -1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
             ],
             indices: vec![
                 0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4, 8, 9, 10, 10, 11, 8, 12, 13, 14, 14, 15, 12,
                 16, 17, 18, 18, 19, 16, 20, 21, 22, 22, 23, 20,
             ],
-        }
+        };
+        model::compute_tangents(&mut cube.vertexes, &cube.indices);
+        cube
+    }
+}
+
+impl Primitive for Cube {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.width, self.height, self.depth)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Solid
     }
 }
@@ -0,0 +1,229 @@
+// GPU culling: a compute pass tests every instance against the frustum, the
+// PVS and the previous frame's depth pyramid, appending survivors to their
+// layer's range of the culled buffer and counting them into its indirect draw.
+
+let OCCLUSION: u32 = 1u;
+let PVS: u32 = 2u;
+
+#include "billboard.wgsl"
+
+struct Cull {
+    previous_view_proj: mat4x4<f32>,
+    planes: array<vec4<f32>, 6>,
+    pyramid_size: vec2<f32>,
+    pyramid_levels: u32,
+    instance_count: u32,
+    grid: vec2<i32>,
+    cell_size: f32,
+    flags: u32,
+}
+
+// Bounding sphere in the instance's space and where its survivors go.
+struct Layer {
+    sphere: vec4<f32>,
+    cell_min: vec2<i32>,
+    cell_max: vec2<i32>,
+    base: u32,
+    // Merged meshes test every cell they overlap rather than the one their
+    // origin is in.
+    is_static: u32,
+}
+
+// Matches `InstanceRaw`.
+struct Instance {
+    model: mat4x4<f32>,
+    material: u32,
+    billboard: u32,
+    billboard_size: f32,
+    uv_offset: vec2<f32>,
+    uv_scale: vec2<f32>,
+}
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(2)
+var<storage, read> instance_layers: array<u32>;
+@group(0) @binding(3)
+var<storage, read> layers: array<Layer>;
+@group(0) @binding(4)
+var<storage, read_write> culled: array<Instance>;
+@group(0) @binding(5)
+var<storage, read_write> draws: array<DrawArgs>;
+// The row of the eye's cell, one bit per cell.
+@group(0) @binding(6)
+var<storage, read> pvs: array<u32>;
+@group(0) @binding(7)
+var t_pyramid: texture_2d<f32>;
+
+fn in_frustum(centre: vec3<f32>, radius: f32) -> bool {
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = cull.planes[i];
+        if (dot(plane.xyz, centre) + plane.w < -radius) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Anything outside the grid counts as visible.
+fn cell_visible(cell: vec2<i32>) -> bool {
+    if (any(cell < vec2<i32>(0)) || any(cell >= cull.grid)) {
+        return true;
+    }
+    let index = u32(cell.y * cull.grid.x + cell.x);
+    return (pvs[index / 32u] & (1u << (index % 32u))) != 0u;
+}
+
+fn world_cell(position: vec3<f32>) -> vec2<i32> {
+    return vec2<i32>(floor((position.xz + cull.cell_size) / (2.0 * cull.cell_size)));
+}
+
+fn in_pvs(layer: Layer, model: mat4x4<f32>) -> bool {
+    if ((cull.flags & PVS) == 0u) {
+        return true;
+    }
+    if (layer.is_static == 0u) {
+        return cell_visible(world_cell(model[3].xyz));
+    }
+    for (var z = layer.cell_min.y; z <= layer.cell_max.y; z = z + 1) {
+        for (var x = layer.cell_min.x; x <= layer.cell_max.x; x = x + 1) {
+            if (cell_visible(vec2<i32>(x, z))) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// Projects the sphere's box with last frame's camera and compares its
+// nearest depth against the furthest depth of the pyramid texels under it.
+fn occluded(centre: vec3<f32>, radius: f32) -> bool {
+    if ((cull.flags & OCCLUSION) == 0u) {
+        return false;
+    }
+    var lo = vec3<f32>(1e30);
+    var hi = vec3<f32>(-1e30);
+    for (var i = 0; i < 8; i = i + 1) {
+        let corner = vec3<f32>(
+            select(-1.0, 1.0, (i & 1) != 0),
+            select(-1.0, 1.0, (i & 2) != 0),
+            select(-1.0, 1.0, (i & 4) != 0),
+        );
+        let clip = cull.previous_view_proj * vec4<f32>(centre + radius * corner, 1.0);
+        // Crosses the camera plane, too close to tell.
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc);
+        hi = max(hi, ndc);
+    }
+    if (lo.z < 0.0) {
+        return false;
+    }
+
+    let uv_min = clamp(vec2<f32>(lo.x, -hi.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let uv_max = clamp(vec2<f32>(hi.x, -lo.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    // The level where the box spans at most two texels each way.
+    let extent = (uv_max - uv_min) * cull.pyramid_size;
+    let level = clamp(
+        i32(ceil(log2(max(max(extent.x, extent.y), 1.0)))),
+        0,
+        i32(cull.pyramid_levels) - 1,
+    );
+    let dimensions = textureDimensions(t_pyramid, level);
+    let last = dimensions - vec2<i32>(1);
+    let p0 = min(vec2<i32>(uv_min * vec2<f32>(dimensions)), last);
+    let p1 = min(vec2<i32>(uv_max * vec2<f32>(dimensions)), last);
+    let furthest = max(
+        max(textureLoad(t_pyramid, p0, level).r, textureLoad(t_pyramid, vec2<i32>(p1.x, p0.y), level).r),
+        max(textureLoad(t_pyramid, vec2<i32>(p0.x, p1.y), level).r, textureLoad(t_pyramid, p1, level).r),
+    );
+    return lo.z > furthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= cull.instance_count) {
+        return;
+    }
+    let instance = instances[index];
+    let model = instance.model;
+    let layer_index = instance_layers[index];
+    let layer = layers[layer_index];
+    // Instances only rotate and translate, so the radius stays put.
+    var centre = (model * vec4<f32>(layer.sphere.xyz, 1.0)).xyz;
+    var radius = layer.sphere.w;
+    if (instance.billboard != BILLBOARD_NONE) {
+        // Billboards turn about their origin rather than with the instance.
+        centre = model[3].xyz;
+        radius = (length(layer.sphere.xyz) + layer.sphere.w) * instance.billboard_size;
+    }
+    // Screen sized billboards grow with distance, so only the PVS applies.
+    let sized = instance.billboard != BILLBOARD_SCREEN_SIZE;
+    if (!in_pvs(layer, model) || (sized && (!in_frustum(centre, radius) || occluded(centre, radius)))) {
+        return;
+    }
+    let slot = atomicAdd(&draws[layer_index].instance_count, 1u);
+    culled[layer.base + slot] = instance;
+}
+
+// Depth pyramid: level 0 is the largest power of two size that fits in the
+// depth buffer, each texel holding the furthest depth it covers.
+
+@group(0) @binding(8)
+var t_depth: texture_depth_2d;
+@group(0) @binding(9)
+var t_source: texture_2d<f32>;
+@group(0) @binding(10)
+var t_level: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_depth_level(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_level);
+    let p = vec2<i32>(id.xy);
+    if (any(p >= size)) {
+        return;
+    }
+    // Every depth texel the pyramid texel overlaps, up to three each way.
+    let depth_size = textureDimensions(t_depth);
+    let ratio = vec2<f32>(depth_size) / vec2<f32>(size);
+    let first = vec2<i32>(floor(vec2<f32>(p) * ratio));
+    let end = min(vec2<i32>(ceil(vec2<f32>(p + vec2<i32>(1)) * ratio)), depth_size);
+    var furthest = 0.0;
+    for (var y = first.y; y < end.y; y = y + 1) {
+        for (var x = first.x; x < end.x; x = x + 1) {
+            furthest = max(furthest, textureLoad(t_depth, vec2<i32>(x, y), 0));
+        }
+    }
+    textureStore(t_level, p, vec4<f32>(furthest, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_level);
+    let p = vec2<i32>(id.xy);
+    if (any(p >= size)) {
+        return;
+    }
+    // Levels stay one texel wide once a side runs out.
+    let last = textureDimensions(t_source, 0) - vec2<i32>(1);
+    let a = min(p * 2, last);
+    let b = min(p * 2 + vec2<i32>(1), last);
+    let furthest = max(
+        max(textureLoad(t_source, a, 0).r, textureLoad(t_source, vec2<i32>(b.x, a.y), 0).r),
+        max(textureLoad(t_source, vec2<i32>(a.x, b.y), 0).r, textureLoad(t_source, b, 0).r),
+    );
+    textureStore(t_level, p, vec4<f32>(furthest, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,26 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// The six planes of a view-projection's clip volume, pointing inwards.
+pub(crate) struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Planes of a matrix with wgpu's 0..1 clip depth.
+    pub(crate) fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_proj.row(row));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z]
+            .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub(crate) fn intersects_sphere(&self, centre: Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(centre) + plane.w >= -radius)
+    }
+
+    pub(crate) fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+}
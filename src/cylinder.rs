@@ -0,0 +1,102 @@
+use std::f32::consts::TAU;
+
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+};
+
+/// Upright cylinder, used for pillars.
+pub(crate) struct Cylinder {
+    pub radius: f32,
+    pub height: f32,
+    pub vertexes: Vec<ModelVertex>,
+    pub indices: Vec<u16>,
+}
+
+impl Cylinder {
+    pub(crate) fn new(radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut vertexes = Vec::new();
+        let mut indices = Vec::new();
+
+        // Side, with a duplicated seam so the texture wraps once around.
+        for i in 0..=segments {
+            let t = i as f32 / segments as f32;
+            let (sin, cos) = (t * TAU).sin_cos();
+            for (y, v) in [(-height, 1.0), (height, 0.0)] {
+                vertexes.push(ModelVertex {
+                    position: [radius * cos, y, -radius * sin],
+                    tex_coords: [t, v],
+                    normal: [cos, 0.0, -sin],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
+                });
+            }
+        }
+        for i in 0..segments as u16 {
+            let base = i * 2;
+            indices.extend_from_slice(&[base, base + 2, base + 3, base + 3, base + 1, base]);
+        }
+
+        // Caps
+        for (y, winding) in [(height, true), (-height, false)] {
+            let centre = vertexes.len() as u16;
+            vertexes.push(ModelVertex {
+                position: [0.0, y, 0.0],
+                tex_coords: [0.5, 0.5],
+                normal: [0.0, y.signum(), 0.0],
+                tangent: [0.0; 4],
+                lightmap_coords: [0.0; 2],
+                ao: 1.0,
+            });
+            for i in 0..segments {
+                let (sin, cos) = (i as f32 / segments as f32 * TAU).sin_cos();
+                vertexes.push(ModelVertex {
+                    position: [radius * cos, y, -radius * sin],
+                    tex_coords: [0.5 + cos / 2.0, 0.5 - sin / 2.0],
+                    normal: [0.0, y.signum(), 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
+                });
+            }
+            for i in 0..segments as u16 {
+                let a = centre + 1 + i;
+                let b = centre + 1 + (i + 1) % segments as u16;
+                if winding {
+                    indices.extend_from_slice(&[centre, a, b]);
+                } else {
+                    indices.extend_from_slice(&[centre, b, a]);
+                }
+            }
+        }
+
+        model::compute_tangents(&mut vertexes, &indices);
+
+        Self {
+            radius,
+            height,
+            vertexes,
+            indices,
+        }
+    }
+}
+
+impl Primitive for Cylinder {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.radius, self.height, self.radius)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Solid
+    }
+}
@@ -1,4 +1,7 @@
-use crate::model::ModelVertex;
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+};
 
 pub(crate) struct Floor {
     pub width: f32,
@@ -10,7 +13,7 @@ pub(crate) struct Floor {
 
 impl Floor {
     pub(crate) fn new(width: f32, height: f32, depth: f32) -> Self {
-        Self {
+        let mut floor = Self {
             width,
             height,
             depth,
@@ -18,25 +21,57 @@ impl Floor {
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, -1.0 * depth],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, 1.0 * depth],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
             ],
             indices: vec![0, 1, 2, 2, 3, 0],
-        }
+        };
+        model::compute_tangents(&mut floor.vertexes, &floor.indices);
+        floor
+    }
+}
+
+impl Primitive for Floor {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.width, self.height, self.depth)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Floor
     }
 }
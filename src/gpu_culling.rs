@@ -0,0 +1,619 @@
+use std::{mem::size_of, ops::Range};
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera_uniform::CameraUniform,
+    culling::Frustum,
+    instance::{Instance, InstanceRaw},
+    level::{LoadedLayer, CELL_SIZE},
+    material::BlendMode,
+    pvs::{self, Pvs},
+    shaders, texture,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+const PYRAMID_WORKGROUP_SIZE: u32 = 8;
+const PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const OCCLUSION: u32 = 1;
+const PVS: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    previous_view_proj: [[f32; 4]; 4],
+    planes: [[f32; 4]; 6],
+    pyramid_size: [f32; 2],
+    pyramid_levels: u32,
+    instance_count: u32,
+    grid: [i32; 2],
+    cell_size: f32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerRaw {
+    /// Bounding sphere in instance space, radius in w.
+    sphere: [f32; 4],
+    cell_min: [i32; 2],
+    cell_max: [i32; 2],
+    base: u32,
+    is_static: u32,
+    _padding: [u32; 2],
+}
+
+/// Arguments of one `draw_indexed_indirect`.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+/// Bind group layouts and the buffers that don't change with the window.
+struct Resources {
+    cull_bind_group_layout: wgpu::BindGroupLayout,
+    depth_bind_group_layout: wgpu::BindGroupLayout,
+    downsample_bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    /// Every culled instance of every layer, in layer order.
+    instance_buffer: wgpu::Buffer,
+    /// The layer each of those belongs to.
+    instance_layer_buffer: wgpu::Buffer,
+    layer_buffer: wgpu::Buffer,
+    /// What gets drawn, a range per layer.
+    culled_buffer: wgpu::Buffer,
+    /// One `DrawIndexedIndirect` per layer.
+    draw_buffer: wgpu::Buffer,
+    pvs_buffer: wgpu::Buffer,
+}
+
+/// Furthest depth of the last frame at power of two sizes, recreated with
+/// the depth buffer.
+struct Pyramid {
+    size: [u32; 2],
+    levels: u32,
+    cull_bind_group: wgpu::BindGroup,
+    /// Level 0 from the depth buffer, then each level from the one above.
+    level_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+/// Culls every opaque and alpha tested instance on the GPU against the
+/// frustum, the PVS and last frame's depth, then draws the survivors with
+/// one indirect draw per layer. Each layer owns a range of the culled
+/// instance buffer; blended layers fill theirs from the CPU in sorted order.
+pub(crate) struct GpuCulling {
+    resources: Resources,
+    pyramid: Pyramid,
+    cull_pipeline: wgpu::ComputePipeline,
+    depth_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    /// Each layer's instances in the culled buffer.
+    ranges: Vec<Range<u32>>,
+    /// Where each instanced, unblended layer's instances start in the
+    /// instance buffer.
+    inputs: Vec<Option<u32>>,
+    /// Written every frame to reset the instance counts.
+    draws: Vec<DrawIndexedIndirect>,
+    instance_count: u32,
+    grid: [i32; 2],
+    /// The camera the pyramid was rendered with.
+    previous_view_proj: Matrix4<f32>,
+    /// Whether an earlier frame has filled the pyramid since it was created,
+    /// so this frame's cull pass can read it.
+    pyramid_filled: bool,
+}
+
+impl GpuCulling {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        layers: &[LoadedLayer],
+        pvs: &Pvs,
+        depth_texture: &texture::Texture,
+    ) -> anyhow::Result<Self> {
+        let mut ranges = Vec::with_capacity(layers.len());
+        let mut layer_data = Vec::with_capacity(layers.len());
+        let mut draws = Vec::with_capacity(layers.len());
+        let mut instances = Vec::new();
+        let mut instance_layers = Vec::new();
+        let mut inputs = Vec::with_capacity(layers.len());
+        let mut culled_count = 0;
+        for (index, layer) in layers.iter().enumerate() {
+            draws.push(DrawIndexedIndirect {
+                index_count: layer.num_indices,
+                instance_count: 0,
+                first_index: layer.first_index,
+                base_vertex: layer.base_vertex,
+                first_instance: 0,
+            });
+            layer_data.push(layer_raw(layer, culled_count));
+            ranges.push(culled_count..culled_count + layer.instance_count);
+            culled_count += layer.instance_count;
+
+            if layer.blend == BlendMode::Blended {
+                inputs.push(None);
+                continue;
+            }
+            // Static meshes are already in world space.
+            let material = index as u32;
+            if layer.static_bounds.is_some() {
+                inputs.push(None);
+                instances.push(Instance::identity().to_raw(material));
+            } else {
+                inputs.push(Some(instances.len() as u32));
+                instances.extend(
+                    layer
+                        .instances
+                        .iter()
+                        .map(|instance| instance.to_raw(material)),
+                );
+            }
+            instance_layers.resize(instances.len(), index as u32);
+        }
+        let instance_count = instances.len() as u32;
+        // Bindings can't be empty.
+        instances.resize(instances.len().max(1), Instance::identity().to_raw(0));
+        instance_layers.resize(instances.len(), 0);
+
+        let storage_init = |label, contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            // Rewritten as sprites animate.
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let instance_layer_buffer = storage_init(
+            "Cull Instance Layer Buffer",
+            bytemuck::cast_slice(&instance_layers),
+        );
+        let layer_buffer = storage_init("Cull Layer Buffer", bytemuck::cast_slice(&layer_data));
+        let culled_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled Instance Buffer"),
+            size: (culled_count.max(1) as usize * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            contents: bytemuck::cast_slice(&draws),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let (width, depth) = pvs.grid_size();
+        let pvs_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull PVS Buffer"),
+            size: (pvs_words(width * depth) * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Buffer"),
+            size: size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = |read_only| wgpu::BufferBindingType::Storage { read_only };
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+        let level_entry = wgpu::BindGroupLayoutEntry {
+            binding: 10,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: PYRAMID_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let unfilterable = wgpu::TextureSampleType::Float { filterable: false };
+        let cull_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                    buffer_entry(1, storage(true)),
+                    buffer_entry(2, storage(true)),
+                    buffer_entry(3, storage(true)),
+                    buffer_entry(4, storage(false)),
+                    buffer_entry(5, storage(false)),
+                    buffer_entry(6, storage(true)),
+                    texture_entry(7, unfilterable),
+                ],
+                label: Some("cull_bind_group_layout"),
+            });
+        let depth_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(8, wgpu::TextureSampleType::Depth),
+                    level_entry,
+                ],
+                label: Some("depth_level_bind_group_layout"),
+            });
+        let downsample_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[texture_entry(9, unfilterable), level_entry],
+                label: Some("downsample_bind_group_layout"),
+            });
+
+        let resources = Resources {
+            cull_bind_group_layout,
+            depth_bind_group_layout,
+            downsample_bind_group_layout,
+            uniform_buffer,
+            instance_buffer,
+            instance_layer_buffer,
+            layer_buffer,
+            culled_buffer,
+            draw_buffer,
+            pvs_buffer,
+        };
+        let pyramid = resources.pyramid(device, depth_texture);
+        let [cull_pipeline, depth_pipeline, downsample_pipeline] = resources.pipelines(device)?;
+        Ok(Self {
+            resources,
+            pyramid,
+            cull_pipeline,
+            depth_pipeline,
+            downsample_pipeline,
+            ranges,
+            inputs,
+            draws,
+            instance_count,
+            grid: [width as i32, depth as i32],
+            previous_view_proj: Matrix4::identity(),
+            pyramid_filled: false,
+        })
+    }
+
+    /// Rebuilds the pipelines from the shader on disk, keeping the old ones
+    /// if the new shader or pipelines don't validate.
+    pub(crate) fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        [
+            self.cull_pipeline,
+            self.depth_pipeline,
+            self.downsample_pipeline,
+        ] = shaders::catch_validation_errors(device, || self.resources.pipelines(device))?;
+        Ok(())
+    }
+
+    /// Rebuilds the pyramid for a new depth buffer. Occlusion culling is off
+    /// until it's been filled.
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, depth_texture: &texture::Texture) {
+        self.pyramid = self.resources.pyramid(device, depth_texture);
+        self.pyramid_filled = false;
+    }
+
+    /// Uploads the camera the frame is rendered with and resets the draw
+    /// counts. Call once per frame after the camera has moved; the frame must
+    /// also call `build_pyramid`.
+    pub(crate) fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera_uniform: &CameraUniform,
+        pvs: &Pvs,
+    ) {
+        let view_proj = Matrix4::from(camera_uniform.view_proj);
+        let eye = Vector4::from(camera_uniform.view_position).truncate();
+        let view = pvs.view_from(eye);
+        if let Some(view) = &view {
+            let mut row = view.row().to_vec();
+            row.resize(pvs_words((self.grid[0] * self.grid[1]) as usize) * 4, 0);
+            queue.write_buffer(&self.resources.pvs_buffer, 0, &row);
+        }
+
+        let mut flags = 0;
+        if self.pyramid_filled {
+            flags |= OCCLUSION;
+        }
+        if view.is_some() {
+            flags |= PVS;
+        }
+        let uniform = CullUniform {
+            previous_view_proj: self.previous_view_proj.into(),
+            planes: Frustum::from_view_proj(view_proj).planes(),
+            pyramid_size: self.pyramid.size.map(|size| size as f32),
+            pyramid_levels: self.pyramid.levels,
+            instance_count: self.instance_count,
+            grid: self.grid,
+            cell_size: CELL_SIZE,
+            flags,
+        };
+        queue.write_buffer(
+            &self.resources.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+        queue.write_buffer(
+            &self.resources.draw_buffer,
+            0,
+            bytemuck::cast_slice(&self.draws),
+        );
+        self.previous_view_proj = view_proj;
+        self.pyramid_filled = true;
+    }
+
+    /// Fills the culled buffer and the indirect draw counts.
+    pub(crate) fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+        });
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.set_bind_group(0, &self.pyramid.cull_bind_group, &[]);
+        pass.dispatch_workgroups(dispatch_size(self.instance_count, WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// Reduces this frame's depth into the pyramid the next frame culls
+    /// against. Call once the depth buffer is final.
+    pub(crate) fn build_pyramid(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Depth Pyramid Pass"),
+        });
+        for (level, bind_group) in self.pyramid.level_bind_groups.iter().enumerate() {
+            let pipeline = if level == 0 {
+                &self.depth_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            let [width, height] = self.pyramid.size.map(|size| (size >> level).max(1));
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(
+                dispatch_size(width, PYRAMID_WORKGROUP_SIZE),
+                dispatch_size(height, PYRAMID_WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    /// The layer's range of the culled buffer, `None` if it has no
+    /// instances.
+    pub(crate) fn instances(&self, layer: usize) -> Option<wgpu::BufferSlice<'_>> {
+        let stride = size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        let range = &self.ranges[layer];
+        (!range.is_empty()).then(|| {
+            self.resources.culled_buffer.slice(
+                range.start as wgpu::BufferAddress * stride
+                    ..range.end as wgpu::BufferAddress * stride,
+            )
+        })
+    }
+
+    /// Fills a blended layer's range, which the GPU leaves alone.
+    pub(crate) fn write_instances(&self, queue: &wgpu::Queue, layer: usize, raw: &[InstanceRaw]) {
+        let offset = self.ranges[layer].start as usize * size_of::<InstanceRaw>();
+        queue.write_buffer(
+            &self.resources.culled_buffer,
+            offset as wgpu::BufferAddress,
+            bytemuck::cast_slice(raw),
+        );
+    }
+
+    /// Replaces an opaque or alpha tested layer's instances before they're
+    /// culled. Blended and static layers don't have any here.
+    pub(crate) fn update_instances(&self, queue: &wgpu::Queue, layer: usize, raw: &[InstanceRaw]) {
+        if let Some(start) = self.inputs[layer] {
+            let offset = start as usize * size_of::<InstanceRaw>();
+            queue.write_buffer(
+                &self.resources.instance_buffer,
+                offset as wgpu::BufferAddress,
+                bytemuck::cast_slice(raw),
+            );
+        }
+    }
+
+    /// Draws whatever survived of an opaque or alpha tested layer, with its
+    /// buffers already bound.
+    pub(crate) fn draw_indirect<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        layer: usize,
+    ) {
+        let offset = layer * size_of::<DrawIndexedIndirect>();
+        render_pass
+            .draw_indexed_indirect(&self.resources.draw_buffer, offset as wgpu::BufferAddress);
+    }
+}
+
+impl Resources {
+    /// The cull, depth level and downsample pipelines.
+    fn pipelines(&self, device: &wgpu::Device) -> anyhow::Result<[wgpu::ComputePipeline; 3]> {
+        let shader = shaders::create_module(device, "cull.wgsl", &[], "Cull Shader")?;
+        let compute_pipeline = |bind_group_layout, entry_point, label| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let cull_pipeline =
+            compute_pipeline(&self.cull_bind_group_layout, "cs_cull", "Cull Pipeline");
+        let depth_pipeline = compute_pipeline(
+            &self.depth_bind_group_layout,
+            "cs_depth_level",
+            "Depth Level Pipeline",
+        );
+        let downsample_pipeline = compute_pipeline(
+            &self.downsample_bind_group_layout,
+            "cs_downsample",
+            "Downsample Pipeline",
+        );
+        Ok([cull_pipeline, depth_pipeline, downsample_pipeline])
+    }
+
+    fn pyramid(&self, device: &wgpu::Device, depth_texture: &texture::Texture) -> Pyramid {
+        let depth_size = depth_texture.size;
+        // The largest power of two that fits, so each level exactly halves
+        // the one above.
+        let floor_pow2 = |size: u32| 1u32 << (31 - size.max(1).leading_zeros());
+        let size = [floor_pow2(depth_size.width), floor_pow2(depth_size.height)];
+        let levels = 32 - size[0].max(size[1]).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("depth_pyramid"),
+            size: wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PYRAMID_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let level_views: Vec<_> = (0..levels)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.cull_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.instance_layer_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.layer_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.culled_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.draw_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.pvs_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+            label: Some("cull_bind_group"),
+        });
+        let level_bind_groups = level_views
+            .iter()
+            .enumerate()
+            .map(|(level, target)| {
+                let (layout, source_binding, source) = match level {
+                    0 => (&self.depth_bind_group_layout, 8, &depth_texture.view),
+                    _ => (
+                        &self.downsample_bind_group_layout,
+                        9,
+                        &level_views[level - 1],
+                    ),
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: source_binding,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 10,
+                            resource: wgpu::BindingResource::TextureView(target),
+                        },
+                    ],
+                    label: Some("depth_pyramid_bind_group"),
+                })
+            })
+            .collect();
+        Pyramid {
+            size,
+            levels,
+            cull_bind_group,
+            level_bind_groups,
+        }
+    }
+}
+
+/// Bounding sphere and, for static meshes, the grid cells they cover.
+fn layer_raw(layer: &LoadedLayer, base: u32) -> LayerRaw {
+    let bounds = layer
+        .static_bounds
+        .unwrap_or_else(|| layer.primitive.bounds());
+    let (cell_min, cell_max) = match &layer.static_bounds {
+        Some(bounds) => (pvs::world_cell(bounds.min), pvs::world_cell(bounds.max)),
+        None => ((0, 0), (0, 0)),
+    };
+    let centre = bounds.centre();
+    LayerRaw {
+        sphere: [
+            centre.x,
+            centre.y,
+            centre.z,
+            bounds.half_extents().magnitude(),
+        ],
+        cell_min: [cell_min.0, cell_min.1],
+        cell_max: [cell_max.0, cell_max.1],
+        base,
+        is_static: layer.static_bounds.is_some() as u32,
+        _padding: [0; 2],
+    }
+}
+
+/// 32 bit words holding a bit per cell.
+fn pvs_words(cells: usize) -> usize {
+    cells.div_ceil(32).max(1)
+}
+
+fn dispatch_size(count: u32, workgroup_size: u32) -> u32 {
+    count.div_ceil(workgroup_size).max(1)
+}
@@ -1,20 +1,108 @@
 pub(crate) struct Instance {
     pub(crate) position: cgmath::Vector3<f32>,
     pub(crate) rotation: cgmath::Quaternion<f32>,
+    pub(crate) billboard: Billboard,
+    /// The part of the texture the mesh's coordinates span, moved about to
+    /// play sprite sheet animations.
+    pub(crate) uv: UvRect,
+}
+
+/// A rectangle of texture coordinates, mapped onto by scaling then offsetting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct UvRect {
+    pub(crate) offset: [f32; 2],
+    pub(crate) scale: [f32; 2],
+}
+
+impl UvRect {
+    /// The whole texture.
+    pub(crate) const FULL: Self = Self {
+        offset: [0.0; 2],
+        scale: [1.0; 2],
+    };
+}
+
+/// How an instance turns to face the camera. Billboards ignore the
+/// instance's rotation and lay the mesh's x and y axes across the view,
+/// scaled by their size, so they suit flat quads facing +Z.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) enum Billboard {
+    /// Drawn the way it's rotated.
+    #[default]
+    None,
+    /// Turns about the world's up axis only, so it stays upright, `size`
+    /// world units per mesh unit.
+    Cylindrical { size: f32 },
+    /// Turns to face the camera from any angle, `size` world units per mesh
+    /// unit.
+    Spherical { size: f32 },
+    /// Faces the camera at the same size on screen however far away it is,
+    /// `pixels` per mesh unit.
+    ScreenSize { pixels: f32 },
+}
+
+impl Billboard {
+    /// The mode the shaders switch on and its size.
+    fn to_raw(self) -> (u32, f32) {
+        match self {
+            Billboard::None => (0, 1.0),
+            Billboard::Cylindrical { size } => (1, size),
+            Billboard::Spherical { size } => (2, size),
+            Billboard::ScreenSize { pixels } => (3, pixels),
+        }
+    }
+
+    /// Radius of a sphere around the instance's origin that the billboard
+    /// stays inside, given one around the mesh at `centre_distance` from its
+    /// origin. Screen sized billboards have none, as they grow with distance.
+    pub(crate) fn bounding_radius(self, centre_distance: f32, radius: f32) -> Option<f32> {
+        match self {
+            Billboard::None => Some(radius),
+            Billboard::Cylindrical { size } | Billboard::Spherical { size } => {
+                Some((centre_distance + radius) * size)
+            }
+            Billboard::ScreenSize { .. } => None,
+        }
+    }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub(crate) struct InstanceRaw {
     pub(crate) model: [[f32; 4]; 4],
+    /// Index into the level's material buffer.
+    pub(crate) material: u32,
+    /// `Billboard` mode, 0 for none.
+    pub(crate) billboard: u32,
+    pub(crate) billboard_size: f32,
+    pub(crate) _padding: u32,
+    pub(crate) uv_offset: [f32; 2],
+    pub(crate) uv_scale: [f32; 2],
 }
 
 impl Instance {
-    pub(crate) fn to_raw(&self) -> InstanceRaw {
+    /// Places a mesh that's already in world space.
+    pub(crate) fn identity() -> Self {
+        Self {
+            position: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            billboard: Billboard::None,
+            uv: UvRect::FULL,
+        }
+    }
+
+    pub(crate) fn to_raw(&self, material: u32) -> InstanceRaw {
+        let (billboard, billboard_size) = self.billboard.to_raw();
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
                 * cgmath::Matrix4::from(self.rotation))
             .into(),
+            material,
+            billboard,
+            billboard_size,
+            _padding: 0,
+            uv_offset: self.uv.offset,
+            uv_scale: self.uv.scale,
         }
     }
 }
@@ -54,6 +142,32 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // Location 9 is the vertex's ambient occlusion.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 17]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
@@ -0,0 +1,951 @@
+use cgmath::{Deg, Point3, Vector3};
+
+use crate::{
+    gpu_culling::GpuCulling,
+    instance::{Billboard, Instance, InstanceRaw},
+    light::{Lighting, PointLight, Spot},
+    lightmap,
+    material::{BlendMode, Footstep, MaterialRaw, MaterialTextures},
+    material_library::MaterialLibrary,
+    mesher::{self, LevelMesh, VoxelGrid},
+    model::ModelVertex,
+    primitive::{Bounds, Primitive, PrimitiveParams, PrimitiveRegistry},
+    slope::{Corner, Direction, SlopeOrientation},
+    sprite_sheet::{SpritePlayback, SpriteSheet},
+    systems::{
+        create_buffers, create_texture_bind_group, instance_buffer_init, slope_tile_instances,
+        static_instance_init, tile_instances,
+    },
+    texture::{self, Sampling},
+    texture_file::TextureFile,
+};
+
+/// Half the size of a map cell in world units.
+pub(crate) const CELL_SIZE: f32 = 1.0;
+
+pub(crate) struct MapTiles {
+    pub map: Vec<i32>,
+    pub width: usize,
+    pub depth: usize,
+}
+
+/// How a layer's instances are stacked inside their cell.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Placement {
+    /// Centred in the cell, stacked by the primitive's height.
+    Stacked,
+    /// Resting on the floor of the cell, however tall the primitive is.
+    Grounded,
+}
+
+pub(crate) struct LevelLayer {
+    pub primitive: &'static str,
+    pub params: PrimitiveParams,
+    /// Name of the layer's material in the level's material library.
+    pub material: &'static str,
+    pub tiles: MapTiles,
+    pub placement: Placement,
+    /// Greedy-mesh the whole layer into one static mesh. Only meaningful for
+    /// primitives that fill their cell.
+    pub merge: bool,
+    /// Static geometry whose sun, sky and baked lights come from a lightmap.
+    pub lightmap: bool,
+    /// Darken corners by the merged layers' cells. Layers that aren't merged are
+    /// flattened into one static mesh so each tile gets its own vertices.
+    pub ambient_occlusion: bool,
+    /// Turns each instance to face the camera. Billboards are always drawn
+    /// instanced, which rules out merging, lightmaps and ambient occlusion.
+    pub billboard: Billboard,
+    /// Animation from the material's sprite sheet that every instance starts
+    /// out playing.
+    pub animation: Option<&'static str>,
+}
+
+pub(crate) struct Level {
+    /// Names the level's lightmap directory.
+    pub name: &'static str,
+    pub layers: Vec<LevelLayer>,
+    pub lighting: Lighting,
+    /// Dynamic lights, uploaded every frame so they can be moved.
+    pub lights: Vec<PointLight>,
+}
+
+/// A level layer with its GPU resources. Its geometry is a range of the
+/// level's shared buffers and its material is the one at its own index.
+pub(crate) struct LoadedLayer {
+    pub primitive: Box<dyn Primitive>,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub num_indices: u32,
+    /// Which of the batch's bind groups holds its textures, sampled the way
+    /// its level layer asks.
+    pub bind_group: usize,
+    /// Every instance, drawn whole by the shadow passes. The camera's
+    /// visible instances are compacted elsewhere, never into this buffer.
+    pub instance_buffer: wgpu::Buffer,
+    pub instance_count: u32,
+    /// Per-cell instances, kept for collision even when the layer is merged.
+    pub instances: Vec<Instance>,
+    /// Blended layers are always drawn instanced so their instances can be
+    /// sorted, which rules out merging, lightmaps and ambient occlusion.
+    pub blend: BlendMode,
+    pub footstep: Footstep,
+    /// World bounds of a static mesh. Instanced layers are culled per
+    /// instance instead.
+    pub static_bounds: Option<Bounds>,
+    /// Frames cut from the material's texture, which each instance shows
+    /// one of.
+    pub sheet: Option<SpriteSheet>,
+    /// Each instance's place in its animation, empty if the level layer
+    /// doesn't play one.
+    pub playback: Vec<SpritePlayback>,
+}
+
+impl LoadedLayer {
+    /// Advances the layer's animations and turns its directional sprites to
+    /// `eye`, returning whether any instance shows another frame.
+    pub(crate) fn animate(&mut self, dt: f32, eye: Point3<f32>) -> bool {
+        let sheet = match &self.sheet {
+            Some(sheet) => sheet,
+            None => return false,
+        };
+        let mut changed = false;
+        for (index, instance) in self.instances.iter_mut().enumerate() {
+            let frame = self.playback.get_mut(index).map_or(0, |playback| {
+                playback.advance(dt);
+                playback.frame(sheet)
+            });
+            let uv = sheet.uv(frame, sheet.direction(instance, eye));
+            changed |= uv != instance.uv;
+            instance.uv = uv;
+        }
+        changed
+    }
+}
+
+/// Animates every layer, rewriting the instances of those that changed
+/// everywhere they're drawn from.
+pub(crate) fn animate_layers(
+    queue: &wgpu::Queue,
+    layers: &mut [LoadedLayer],
+    culling: &GpuCulling,
+    dt: f32,
+    eye: Point3<f32>,
+) {
+    for (index, layer) in layers.iter_mut().enumerate() {
+        if !layer.animate(dt, eye) || layer.static_bounds.is_some() {
+            continue;
+        }
+        let raw: Vec<InstanceRaw> = layer
+            .instances
+            .iter()
+            .map(|instance| instance.to_raw(index as u32))
+            .collect();
+        queue.write_buffer(&layer.instance_buffer, 0, bytemuck::cast_slice(&raw));
+        culling.update_instances(queue, index, &raw);
+    }
+}
+
+/// Every layer's vertices and indices in one pair of buffers, and every
+/// texture, material and lightmap in a bind group per size of texture and
+/// distinct sampling, so the level is bound a handful of times per pass.
+pub(crate) struct LevelBatch {
+    pub bind_groups: Vec<wgpu::BindGroup>,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+}
+
+impl LevelBatch {
+    /// Binds the level's geometry to vertex slot 0, leaving slot 1 for each
+    /// layer's instances.
+    pub(crate) fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    /// Binds `layer`'s textures to group 0, sampled the way it asks.
+    pub(crate) fn bind_textures<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        layer: &LoadedLayer,
+    ) {
+        render_pass.set_bind_group(0, &self.bind_groups[layer.bind_group], &[]);
+    }
+}
+
+/// A layer's geometry before it's packed into the level's buffers.
+struct LayerMesh {
+    primitive: Box<dyn Primitive>,
+    instances: Vec<Instance>,
+    vertexes: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    /// Set when the vertexes are already in world space, drawn with a single
+    /// identity instance.
+    static_bounds: Option<Bounds>,
+    lightmap: Option<image::RgbaImage>,
+}
+
+impl Level {
+    pub(crate) fn load(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        registry: &PrimitiveRegistry,
+        library: &MaterialLibrary,
+    ) -> anyhow::Result<(Vec<LoadedLayer>, LevelBatch)> {
+        let occluders = self.occluders();
+        let defs = self
+            .layers
+            .iter()
+            .map(|layer| library.resolve(layer.material))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let meshes = self
+            .layers
+            .iter()
+            .zip(&defs)
+            .enumerate()
+            .map(|(index, (layer, def))| {
+                let lightmap = if layer.lightmap {
+                    lightmap::load_atlas(self.name, index)
+                } else {
+                    None
+                };
+                layer.mesh(registry, def.blend, lightmap, &occluders)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (files, texture_layers) =
+            dedupe_images(defs.iter().map(|def| Some(&def.texture[..])), false)?;
+        let (normal_files, normal_layers) =
+            dedupe_images(defs.iter().map(|def| def.maps.normal.as_deref()), true)?;
+        let (roughness_files, roughness_layers) =
+            dedupe_images(defs.iter().map(|def| def.maps.roughness_metallic.as_deref()), true)?;
+        let (emissive_files, emissive_layers) =
+            dedupe_images(defs.iter().map(|def| def.maps.emissive.as_deref()), false)?;
+        let mut samplings: Vec<Sampling> = Vec::new();
+        for def in &defs {
+            if !samplings.contains(&def.sampling) {
+                samplings.push(def.sampling);
+            }
+        }
+        let sampling = samplings.first().copied().unwrap_or_default();
+
+        // Arrays only hold images of one size, so layers whose images differ
+        // in size get textures of their own.
+        let kinds = [&files, &normal_files, &roughness_files, &emissive_files];
+        let mut groups: Vec<TextureGroup> = Vec::new();
+        let placements: Vec<_> = (0..defs.len())
+            .map(|index| {
+                let layers = [
+                    texture_layers[index],
+                    normal_layers[index],
+                    roughness_layers[index],
+                    emissive_layers[index],
+                ];
+                let images = std::array::from_fn(|kind| {
+                    layers[kind].map(|layer| {
+                        let file = &kinds[kind][layer as usize];
+                        (layer, (file.width, file.height))
+                    })
+                });
+                TextureGroup::place(&mut groups, images)
+            })
+            .collect();
+        let textures = groups
+            .iter()
+            .enumerate()
+            .map(|(group_index, group)| {
+                let [diffuse, normal, roughness, emissive] = std::array::from_fn(|kind| {
+                    group.images[kind]
+                        .iter()
+                        .map(|&image| kinds[kind][image as usize].clone())
+                        .collect::<Vec<_>>()
+                });
+                let label = format!("level_{}", group_index);
+                let diffuse = texture::Texture::from_files(
+                    device,
+                    queue,
+                    &diffuse,
+                    sampling,
+                    &format!("{}_textures", label),
+                )?;
+                let lightmap_images: Vec<_> = meshes
+                    .iter()
+                    .zip(&placements)
+                    .filter(|(_, (group, _))| *group == group_index)
+                    .filter_map(|(mesh, _)| mesh.lightmap.clone())
+                    .collect();
+                let lightmaps = texture::Texture::from_lightmaps(
+                    device,
+                    queue,
+                    &lightmap_images,
+                    &format!("{}_lightmaps", label),
+                );
+                MaterialTextures::new(
+                    device,
+                    queue,
+                    diffuse,
+                    [normal, roughness, emissive],
+                    lightmaps,
+                    sampling,
+                    &label,
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // One bind group for each group of textures and sampling used together.
+        let mut bindings: Vec<(usize, usize)> = Vec::new();
+
+        let mut materials = Vec::with_capacity(self.layers.len());
+        let mut vertexes = Vec::new();
+        let mut indices = Vec::new();
+        let mut layers = Vec::with_capacity(self.layers.len());
+        let mut lightmap_layers = vec![0; groups.len()];
+        for (index, ((def, mut mesh), level_layer)) in
+            defs.iter().zip(meshes).zip(&self.layers).enumerate()
+        {
+            let playback = match (level_layer.animation, &def.sheet) {
+                (None, _) => Vec::new(),
+                (Some(animation), Some(sheet)) => {
+                    sheet.animation(animation)?;
+                    vec![SpritePlayback::new(animation); mesh.instances.len()]
+                }
+                (Some(animation), None) => {
+                    anyhow::bail!(
+                        "{} plays {} but has no sprite sheet",
+                        level_layer.material,
+                        animation
+                    )
+                }
+            };
+            // Every instance starts on the same frame, facing the same way
+            // until the first `animate`.
+            if let Some(sheet) = &def.sheet {
+                let frame = playback.first().map_or(0, |playback| playback.frame(sheet));
+                for instance in &mut mesh.instances {
+                    instance.uv = sheet.uv(frame, 0);
+                }
+            }
+            let (group, [texture_layer, normal_layer, roughness_layer, emissive_layer]) =
+                placements[index];
+            let lightmap = mesh.lightmap.as_ref().map(|img| {
+                let layer = lightmap_layers[group];
+                lightmap_layers[group] += 1;
+                (layer, texture::Texture::lightmap_scale(img, textures[group].lightmaps.size))
+            });
+            // Every layer has a diffuse texture.
+            let texture_layer = texture_layer.unwrap();
+            let map_layers = [normal_layer, roughness_layer, emissive_layer];
+            let binding = (
+                group,
+                samplings
+                    .iter()
+                    .position(|sampling| *sampling == def.sampling)
+                    .unwrap(),
+            );
+            let bind_group = match bindings.iter().position(|used| *used == binding) {
+                Some(bind_group) => bind_group,
+                None => {
+                    bindings.push(binding);
+                    bindings.len() - 1
+                }
+            };
+            materials.push(
+                MaterialRaw::new(
+                    def.uv_mode,
+                    files[texture_layers[index].unwrap() as usize].width,
+                    texture_layer,
+                    lightmap.map(|(layer, _)| layer),
+                )
+                .with_maps(&def.maps, map_layers)
+                .with_tint(def.tint),
+            );
+
+            let base_vertex = vertexes.len() as i32;
+            let first_index = indices.len() as u32;
+            vertexes.extend(mesh.vertexes.iter().map(|vertex| {
+                let mut vertex = *vertex;
+                if let Some((_, [u, v])) = lightmap {
+                    vertex.lightmap_coords[0] *= u;
+                    vertex.lightmap_coords[1] *= v;
+                }
+                vertex
+            }));
+            indices.extend_from_slice(&mesh.indices);
+
+            let material = index as u32;
+            let (instance_buffer, instance_count) = match mesh.static_bounds {
+                Some(_) => (static_instance_init(device, material), 1),
+                None => (
+                    instance_buffer_init(device, &mesh.instances, material),
+                    mesh.instances.len() as u32,
+                ),
+            };
+            layers.push(LoadedLayer {
+                primitive: mesh.primitive,
+                first_index,
+                base_vertex,
+                num_indices: mesh.indices.len() as u32,
+                bind_group,
+                instance_buffer,
+                instance_count,
+                instances: mesh.instances,
+                blend: def.blend,
+                footstep: def.footstep,
+                static_bounds: mesh.static_bounds,
+                sheet: def.sheet.clone(),
+                playback,
+            });
+        }
+
+        let (vertex_buffer, index_buffer, _) = create_buffers(device, &vertexes, &indices);
+        let material_buffer = MaterialRaw::create_buffer(&materials, device);
+        let samplers: Vec<_> = samplings
+            .iter()
+            .map(|sampling| sampling.create_sampler(device, "level_sampler"))
+            .collect();
+        let bind_groups = bindings
+            .iter()
+            .map(|&(group, sampler)| {
+                create_texture_bind_group(
+                    device,
+                    texture_bind_group_layout,
+                    &textures[group],
+                    &samplers[sampler],
+                    &material_buffer,
+                )
+            })
+            .collect();
+        log::info!(
+            "level batch: {} layers, {} textures in {} sizes, {} lightmaps, {} samplers, {} vertexes",
+            layers.len(),
+            files.len(),
+            groups.len(),
+            lightmap_layers.iter().sum::<u32>(),
+            samplings.len(),
+            vertexes.len()
+        );
+        let batch = LevelBatch {
+            bind_groups,
+            vertex_buffer,
+            index_buffer,
+        };
+        Ok((layers, batch))
+    }
+
+    /// Cells filled by the merged layers, which occlude their neighbours.
+    pub(crate) fn occluders(&self) -> VoxelGrid {
+        VoxelGrid::from_layers(self.layers.iter().filter(|layer| layer.merge).map(|layer| &layer.tiles))
+    }
+
+    pub(crate) fn demo() -> Self {
+        Self {
+            name: "demo",
+            layers: vec![
+                LevelLayer {
+                    primitive: "cube",
+                    params: PrimitiveParams::default(),
+                    material: "wall",
+                    tiles: MapTiles {
+                        map: vec![
+                            6, 5, 6, 5, 6, 5, 6, 5,
+                            5, 0, 0, 5, 0, 0, 0, 6,
+                            6, 0, 0, 5, 0, 0, 0, 5,
+                            5, 5, 0, 5, 0, 0, 0, 6,
+                            6, 0, 0, 0, 0, 0, 0, 5,
+                            5, 0, 0, 0, 0, 0, 0, 6,
+                            6, 0, 0, 0, 0, 0, 0, 5,
+                            5, 6, 5, 6, 5, 6, 5, 6,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: true,
+                    lightmap: true,
+                    ambient_occlusion: true,
+                    billboard: Billboard::None,
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "floor",
+                    params: PrimitiveParams::default(),
+                    material: "floor",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 1, 1, 0, 1, 1, 1, 0,
+                            0, 1, 1, 0, 1, 1, 1, 0,
+                            0, 0, 1, 0, 1, 1, 1, 0,
+                            0, 1, 1, 1, 1, 1, 1, 0,
+                            0, 1, 1, 1, 1, 1, 1, 0,
+                            0, 1, 1, 1, 1, 1, 1, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: false,
+                    lightmap: true,
+                    ambient_occlusion: true,
+                    billboard: Billboard::None,
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "sprite",
+                    params: PrimitiveParams::default(),
+                    material: "enemy",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 1, 0, 0,
+                            0, 0, 1, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: false,
+                    lightmap: false,
+                    ambient_occlusion: false,
+                    billboard: Billboard::Cylindrical { size: 1.0 },
+                    animation: Some("walk"),
+                },
+                LevelLayer {
+                    primitive: "sprite",
+                    params: PrimitiveParams::default(),
+                    material: "orb",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 1, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: false,
+                    lightmap: false,
+                    ambient_occlusion: false,
+                    billboard: Billboard::Spherical { size: 0.3 },
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "sprite",
+                    params: PrimitiveParams::default(),
+                    material: "marker",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 1, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: false,
+                    lightmap: false,
+                    ambient_occlusion: false,
+                    billboard: Billboard::ScreenSize { pixels: 12.0 },
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "slope",
+                    params: PrimitiveParams {
+                        height: 6.0,
+                        depth: 5.0,
+                        orientation: SlopeOrientation::Ramp(Direction::Backward),
+                        ..Default::default()
+                    },
+                    material: "floor_world",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 1, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Grounded,
+                    merge: false,
+                    lightmap: true,
+                    ambient_occlusion: false,
+                    billboard: Billboard::None,
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "stairs",
+                    params: PrimitiveParams {
+                        steps: 4,
+                        ..Default::default()
+                    },
+                    material: "wall",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 1, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Grounded,
+                    merge: false,
+                    lightmap: true,
+                    ambient_occlusion: false,
+                    billboard: Billboard::None,
+                    animation: None,
+                },
+                LevelLayer {
+                    primitive: "cylinder",
+                    params: PrimitiveParams {
+                        width: 0.4,
+                        ..Default::default()
+                    },
+                    material: "pillar",
+                    tiles: MapTiles {
+                        map: vec![
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 1, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0,
+                        ],
+                        width: 8,
+                        depth: 8,
+                    },
+                    placement: Placement::Stacked,
+                    merge: false,
+                    lightmap: true,
+                    ambient_occlusion: false,
+                    billboard: Billboard::None,
+                    animation: None,
+                },
+                // A ridge, a pyramid of outer corners and an inner corner
+                // filling the room's corner.
+                demo_slope(SlopeOrientation::Ramp(Direction::Right), (3, 5)),
+                demo_slope(SlopeOrientation::Ramp(Direction::Left), (4, 5)),
+                demo_slope(SlopeOrientation::OuterCorner(Corner::ForwardRight), (5, 5)),
+                demo_slope(SlopeOrientation::OuterCorner(Corner::ForwardLeft), (6, 5)),
+                demo_slope(SlopeOrientation::OuterCorner(Corner::BackwardRight), (5, 6)),
+                demo_slope(SlopeOrientation::OuterCorner(Corner::BackwardLeft), (6, 6)),
+                demo_slope(SlopeOrientation::InnerCorner(Corner::ForwardLeft), (1, 6)),
+            ],
+            lighting: Lighting::default(),
+            lights: vec![
+                PointLight {
+                    position: Vector3::new(0.0, 0.0, 0.0),
+                    color: [1.0, 0.85, 0.6],
+                    intensity: 0.6,
+                    range: 4.0,
+                    spot: None,
+                    baked: false,
+                },
+                PointLight {
+                    position: Vector3::new(2.0, 1.5, 2.8),
+                    color: [1.0, 0.5, 0.15],
+                    intensity: 2.0,
+                    range: 6.0,
+                    spot: None,
+                    baked: true,
+                },
+                PointLight {
+                    position: Vector3::new(10.0, 1.5, 12.8),
+                    color: [1.0, 0.5, 0.15],
+                    intensity: 2.0,
+                    range: 6.0,
+                    spot: None,
+                    baked: true,
+                },
+                PointLight {
+                    position: Vector3::new(10.0, 3.0, 2.0),
+                    color: [0.5, 0.7, 1.0],
+                    intensity: 3.0,
+                    range: 8.0,
+                    spot: Some(Spot {
+                        direction: Vector3::new(0.0, -1.0, 0.3),
+                        inner_angle: Deg(20.0),
+                        outer_angle: Deg(35.0),
+                    }),
+                    baked: true,
+                },
+            ],
+        }
+    }
+}
+
+/// A grounded, cell-sized slope in one cell of the demo level.
+fn demo_slope(orientation: SlopeOrientation, (x, z): (usize, usize)) -> LevelLayer {
+    let mut map = vec![0; 8 * 8];
+    map[z * 8 + x] = 1;
+    LevelLayer {
+        primitive: "slope",
+        params: PrimitiveParams {
+            orientation,
+            ..Default::default()
+        },
+        material: "floor_world",
+        tiles: MapTiles {
+            map,
+            width: 8,
+            depth: 8,
+        },
+        placement: Placement::Grounded,
+        merge: false,
+        lightmap: false,
+        ambient_occlusion: false,
+        billboard: Billboard::None,
+        animation: None,
+    }
+}
+
+impl LevelLayer {
+    /// Where each copy of the primitive sits in the level.
+    pub(crate) fn instances(&self, primitive: &dyn Primitive) -> Vec<Instance> {
+        let height = primitive.bounds().half_extents().y;
+        match self.placement {
+            Placement::Stacked => {
+                tile_instances(&self.tiles, CELL_SIZE, height, CELL_SIZE, self.billboard)
+            }
+            Placement::Grounded => {
+                slope_tile_instances(&self.tiles, CELL_SIZE, height, CELL_SIZE, self.billboard)
+            }
+        }
+    }
+
+    /// The whole layer as one mesh in world space, occluded by `occluders` if the
+    /// layer has ambient occlusion.
+    pub(crate) fn static_mesh(
+        &self,
+        primitive: &dyn Primitive,
+        instances: &[Instance],
+        occluders: &VoxelGrid,
+    ) -> LevelMesh {
+        let occluders = self.ambient_occlusion.then_some(occluders);
+        if self.merge {
+            let half = primitive.bounds().half_extents();
+            return mesher::mesh_tiles(&self.tiles, occluders, half.x, half.y, half.z);
+        }
+        let mut mesh = mesher::flatten_instances(primitive.vertexes(), primitive.indices(), instances);
+        if let Some(occluders) = occluders {
+            mesher::apply_ao(&mut mesh, occluders, [CELL_SIZE; 3]);
+        }
+        mesh
+    }
+
+    fn mesh(
+        &self,
+        registry: &PrimitiveRegistry,
+        blend: BlendMode,
+        lightmap: Option<image::RgbaImage>,
+        occluders: &VoxelGrid,
+    ) -> anyhow::Result<LayerMesh> {
+        let primitive = registry.build(self.primitive, &self.params)?;
+        let instances = self.instances(&*primitive);
+        let instanced = blend == BlendMode::Blended || self.billboard != Billboard::None;
+
+        if let Some(lightmap) = lightmap.filter(|_| !instanced) {
+            let mesh =
+                lightmap::unwrap_mesh(&self.static_mesh(&*primitive, &instances, occluders));
+            if lightmap.dimensions() == (mesh.width, mesh.height) {
+                return Ok(LayerMesh {
+                    primitive,
+                    instances,
+                    static_bounds: Some(mesh_bounds(&mesh.vertexes)),
+                    vertexes: mesh.vertexes,
+                    indices: mesh.indices,
+                    lightmap: Some(lightmap),
+                });
+            }
+            log::warn!(
+                "{} lightmap is {}x{} but the layer unwraps to {}x{}, run `bake` again",
+                self.primitive,
+                lightmap.width(),
+                lightmap.height(),
+                mesh.width,
+                mesh.height
+            );
+        }
+
+        if !instanced && (self.merge || self.ambient_occlusion) {
+            let mesh = self.static_mesh(&*primitive, &instances, occluders);
+            log::info!(
+                "{} mesh: {} triangles (was {})",
+                self.primitive,
+                mesh.triangle_count(),
+                instances.len() * primitive.indices().len() / 3
+            );
+            return Ok(LayerMesh {
+                primitive,
+                instances,
+                static_bounds: Some(mesh_bounds(&mesh.vertexes)),
+                vertexes: mesh.vertexes,
+                indices: mesh.indices,
+                lightmap: None,
+            });
+        }
+
+        let vertexes = primitive.vertexes().to_vec();
+        let indices = primitive.indices().iter().map(|&index| index as u32).collect();
+        Ok(LayerMesh {
+            primitive,
+            instances,
+            vertexes,
+            indices,
+            static_bounds: None,
+            lightmap: None,
+        })
+    }
+}
+
+fn mesh_bounds(vertexes: &[ModelVertex]) -> Bounds {
+    Bounds::enclosing(vertexes.iter().map(|vertex| Vector3::from(vertex.position)))
+}
+
+/// Layers whose images share texture arrays. An array only holds images of
+/// one size, so none of them are stretched.
+#[derive(Default)]
+struct TextureGroup {
+    /// Size of the diffuse, normal, roughness/metallic and emissive images.
+    sizes: [Option<(u32, u32)>; 4],
+    /// The images in each of those arrays, in layer order.
+    images: [Vec<u32>; 4],
+}
+
+impl TextureGroup {
+    /// Puts a layer's images, each given as an image index and its size, in
+    /// the first group with room for them at their own size. Returns the
+    /// group and the images' layers in its arrays.
+    fn place(
+        groups: &mut Vec<TextureGroup>,
+        images: [Option<(u32, (u32, u32))>; 4],
+    ) -> (usize, [Option<u32>; 4]) {
+        let fits = |group: &TextureGroup| {
+            images.iter().zip(&group.sizes).all(|(image, size)| match (image, size) {
+                (Some((_, image_size)), Some(size)) => image_size == size,
+                _ => true,
+            })
+        };
+        let index = match groups.iter().position(fits) {
+            Some(index) => index,
+            None => {
+                groups.push(TextureGroup::default());
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index];
+        let layers = std::array::from_fn(|kind| {
+            images[kind].map(|(image, size)| {
+                group.sizes[kind] = Some(size);
+                let images = &mut group.images[kind];
+                match images.iter().position(|&seen| seen == image) {
+                    Some(layer) => layer as u32,
+                    None => {
+                        images.push(image);
+                        images.len() as u32 - 1
+                    }
+                }
+            })
+        });
+        (index, layers)
+    }
+}
+
+/// Parses each distinct image once, returning the images and each source's
+/// layer among them. Sources sharing an image share its layer.
+fn dedupe_images<'a>(
+    sources: impl Iterator<Item = Option<&'a [u8]>>,
+    linear: bool,
+) -> anyhow::Result<(Vec<TextureFile>, Vec<Option<u32>>)> {
+    let mut seen: Vec<&'a [u8]> = Vec::new();
+    let mut files = Vec::new();
+    let mut layers = Vec::new();
+    for source in sources {
+        let layer = match source {
+            None => None,
+            Some(bytes) => match seen.iter().position(|seen| *seen == bytes) {
+                Some(layer) => Some(layer as u32),
+                None => {
+                    let file = TextureFile::parse(bytes)?;
+                    seen.push(bytes);
+                    files.push(if linear { file.into_linear() } else { file });
+                    Some(files.len() as u32 - 1)
+                }
+            },
+        };
+        layers.push(layer);
+    }
+    Ok((files, layers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_of_different_sizes_get_different_groups() {
+        let mut groups = Vec::new();
+        let small = Some((0, (32, 32)));
+        let sheet = Some((1, (128, 256)));
+        let other_small = Some((2, (32, 32)));
+        let normal = Some((0, (64, 64)));
+
+        assert_eq!(
+            TextureGroup::place(&mut groups, [small, None, None, None]),
+            (0, [Some(0), None, None, None])
+        );
+        assert_eq!(
+            TextureGroup::place(&mut groups, [sheet, None, None, None]),
+            (1, [Some(0), None, None, None])
+        );
+        // Maps have arrays of their own, so any size fits beside the diffuse.
+        assert_eq!(
+            TextureGroup::place(&mut groups, [other_small, normal, None, None]),
+            (0, [Some(1), Some(0), None, None])
+        );
+        // Shared images keep their layer.
+        assert_eq!(
+            TextureGroup::place(&mut groups, [small, normal, None, None]),
+            (0, [Some(0), Some(0), None, None])
+        );
+        // A normal map that doesn't fit the first group's moves the layer on,
+        // diffuse image and all.
+        assert_eq!(
+            TextureGroup::place(&mut groups, [small, Some((1, (16, 16))), None, None]),
+            (2, [Some(0), Some(0), None, None])
+        );
+        assert_eq!(groups.len(), 3);
+    }
+}
@@ -0,0 +1,112 @@
+use cgmath::{Angle, Deg, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+/// Sun and ambient light of a level.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Lighting {
+    /// Direction the sunlight travels in.
+    pub sun_direction: Vector3<f32>,
+    pub sun_color: [f32; 3],
+    pub ambient_color: [f32; 3],
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vector3::new(-0.4, -1.0, -0.3),
+            sun_color: [0.8, 0.78, 0.7],
+            ambient_color: [0.3, 0.32, 0.38],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightUniform {
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+    ambient_color: [f32; 4],
+}
+
+impl Lighting {
+    pub(crate) fn to_uniform(self) -> LightUniform {
+        let direction = self.sun_direction.normalize();
+        let [r, g, b] = self.sun_color;
+        let [ar, ag, ab] = self.ambient_color;
+        LightUniform {
+            sun_direction: [direction.x, direction.y, direction.z, 0.0],
+            sun_color: [r, g, b, 1.0],
+            ambient_color: [ar, ag, ab, 1.0],
+        }
+    }
+
+    pub(crate) fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[self.to_uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+}
+
+/// Cone of a spot light.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Spot {
+    pub direction: Vector3<f32>,
+    /// Full intensity inside this angle from the direction.
+    pub inner_angle: Deg<f32>,
+    /// No light outside this angle.
+    pub outer_angle: Deg<f32>,
+}
+
+/// Point light, or spot light when `spot` is set.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PointLight {
+    pub position: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely.
+    pub range: f32,
+    pub spot: Option<Spot>,
+    /// Baked into lightmaps, so only surfaces without one light it at runtime.
+    pub baked: bool,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct PointLightRaw {
+    position_range: [f32; 4],
+    color_intensity: [f32; 4],
+    /// xyz is the spot direction, w the cosine of the outer angle.
+    spot_direction: [f32; 4],
+    /// x is the cosine of the inner angle, y holds flags (1 = spot, 2 = baked),
+    /// z the shadow map layer and w is 1 when it has one.
+    spot_params: [f32; 4],
+}
+
+impl PointLight {
+    pub(crate) fn to_raw(self, shadow_layer: Option<u32>) -> PointLightRaw {
+        let [r, g, b] = self.color;
+        let baked = if self.baked { 2.0 } else { 0.0 };
+        let (spot_direction, spot_params) = match self.spot {
+            Some(spot) => {
+                let direction = spot.direction.normalize();
+                let flags = 1.0 + baked;
+                (
+                    [direction.x, direction.y, direction.z, spot.outer_angle.cos()],
+                    match shadow_layer {
+                        Some(layer) => [spot.inner_angle.cos(), flags, layer as f32, 1.0],
+                        None => [spot.inner_angle.cos(), flags, 0.0, 0.0],
+                    },
+                )
+            }
+            None => ([0.0, -1.0, 0.0, -1.0], [-1.0, baked, 0.0, 0.0]),
+        };
+        PointLightRaw {
+            position_range: [self.position.x, self.position.y, self.position.z, self.range],
+            color_intensity: [r, g, b, self.intensity],
+            spot_direction,
+            spot_params,
+        }
+    }
+}
@@ -0,0 +1,898 @@
+use std::{collections::HashMap, path::Path};
+
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3, Zero};
+
+use crate::{
+    instance::Billboard,
+    level::Level,
+    light::{Lighting, PointLight},
+    material_library::{MaterialDef, MaterialLibrary},
+    mesher::LevelMesh,
+    model::ModelVertex,
+    primitive::PrimitiveRegistry,
+    texture_file::TextureFile,
+};
+
+/// Where `bake` writes lightmaps and the renderer looks for them.
+pub(crate) const LIGHTMAP_DIR: &str = "lightmaps";
+/// Lightmaps store lighting divided by this, so bright spots survive 8 bits.
+/// Must match `LIGHTMAP_RANGE` in the shader.
+const LIGHTMAP_RANGE: f32 = 4.0;
+const LUXELS_PER_UNIT: f32 = 4.0;
+/// Empty luxels around each chart, so filtering doesn't pick up its neighbours.
+const PADDING: u32 = 2;
+/// Rays per luxel for sky light and the indirect bounce.
+const HEMISPHERE_RAYS: u32 = 64;
+/// Ray origins are lifted off their surface by this much.
+const RAY_OFFSET: f32 = 1e-3;
+
+/// A layer's world-space mesh with lightmap coordinates, and the size of its atlas.
+pub(crate) struct UnwrappedMesh {
+    pub vertexes: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Path of the lightmap atlas for one layer of a level.
+fn atlas_path(dir: &Path, level: &str, layer: usize) -> std::path::PathBuf {
+    dir.join(level).join(format!("layer_{}.png", layer))
+}
+
+/// Loads the baked atlas for a layer, if there is one.
+pub(crate) fn load_atlas(level: &str, layer: usize) -> Option<image::RgbaImage> {
+    let path = atlas_path(Path::new(LIGHTMAP_DIR), level, layer);
+    if !path.exists() {
+        return None;
+    }
+    match image::open(&path) {
+        Ok(img) => Some(img.to_rgba8()),
+        Err(err) => {
+            log::warn!("couldn't load lightmap {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Gives every triangle a unique place in a lightmap atlas.
+///
+/// Connected triangles that face the same way become one chart, projected onto
+/// its own plane at `LUXELS_PER_UNIT`, and the charts are packed into shelves.
+/// Vertices shared between charts are split so each gets its own coordinates.
+pub(crate) fn unwrap_mesh(mesh: &LevelMesh) -> UnwrappedMesh {
+    let position = |index: u32| Vector3::from(mesh.vertexes[index as usize].position);
+    let normals: Vec<Vector3<f32>> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|tri| {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(position);
+            let normal = (b - a).cross(c - a);
+            if normal.magnitude2() > f32::EPSILON {
+                normal.normalize()
+            } else {
+                Vector3::zero()
+            }
+        })
+        .collect();
+
+    let triangle_count = normals.len();
+    let mut parent: Vec<usize> = (0..triangle_count).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut users: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (triangle, tri) in mesh.indices.chunks_exact(3).enumerate() {
+        for &index in tri {
+            users.entry(index).or_default().push(triangle);
+        }
+    }
+    for triangles in users.values() {
+        for (i, &a) in triangles.iter().enumerate() {
+            for &b in &triangles[i + 1..] {
+                if normals[a].dot(normals[b]) > 0.999 {
+                    let (root_a, root_b) = (find(&mut parent, a), find(&mut parent, b));
+                    parent[root_a] = root_b;
+                }
+            }
+        }
+    }
+
+    let mut chart_of_root = HashMap::new();
+    let mut charts: Vec<Vec<usize>> = Vec::new();
+    let mut chart_of = Vec::with_capacity(triangle_count);
+    for triangle in 0..triangle_count {
+        let root = find(&mut parent, triangle);
+        let chart = *chart_of_root.entry(root).or_insert_with(|| {
+            charts.push(Vec::new());
+            charts.len() - 1
+        });
+        charts[chart].push(triangle);
+        chart_of.push(chart);
+    }
+
+    // Project each chart onto its plane, in luxels.
+    struct Chart {
+        /// Projected luxel position of each vertex the chart uses.
+        coords: HashMap<u32, Vector2<f32>>,
+        size: [u32; 2],
+        origin: [u32; 2],
+    }
+    let mut projected: Vec<Chart> = charts
+        .iter()
+        .map(|triangles| {
+            let normal = normals[triangles[0]];
+            let u_axis = if normal.y.abs() < 0.9 {
+                Vector3::unit_y().cross(normal).normalize()
+            } else {
+                normal.cross(Vector3::unit_x()).normalize()
+            };
+            let v_axis = normal.cross(u_axis);
+            let mut coords = HashMap::new();
+            for &triangle in triangles {
+                for &index in &mesh.indices[triangle * 3..triangle * 3 + 3] {
+                    let p = position(index);
+                    coords.insert(index, Vector2::new(p.dot(u_axis), p.dot(v_axis)));
+                }
+            }
+            let min = coords
+                .values()
+                .fold(Vector2::new(f32::MAX, f32::MAX), |m, c| {
+                    Vector2::new(m.x.min(c.x), m.y.min(c.y))
+                });
+            let mut max = Vector2::new(f32::MIN, f32::MIN);
+            for coord in coords.values_mut() {
+                *coord = (*coord - min) * LUXELS_PER_UNIT;
+                max = Vector2::new(max.x.max(coord.x), max.y.max(coord.y));
+            }
+            Chart {
+                coords,
+                size: [
+                    max.x.ceil().max(1.0) as u32 + 2 * PADDING,
+                    max.y.ceil().max(1.0) as u32 + 2 * PADDING,
+                ],
+                origin: [0, 0],
+            }
+        })
+        .collect();
+
+    // Shelf packing, tallest charts first.
+    let area: u32 = projected.iter().map(|c| c.size[0] * c.size[1]).sum();
+    let widest = projected.iter().map(|c| c.size[0]).max().unwrap_or(1);
+    let width = ((area as f32).sqrt().ceil() as u32).max(widest).div_ceil(4) * 4;
+    let mut order: Vec<usize> = (0..projected.len()).collect();
+    order.sort_by_key(|&chart| {
+        std::cmp::Reverse((projected[chart].size[1], projected[chart].size[0]))
+    });
+    let (mut x, mut y, mut shelf) = (0, 0, 0);
+    for chart in order {
+        let [w, h] = projected[chart].size;
+        if x + w > width {
+            x = 0;
+            y += shelf;
+            shelf = 0;
+        }
+        projected[chart].origin = [x, y];
+        x += w;
+        shelf = shelf.max(h);
+    }
+    let height = (y + shelf).max(1).div_ceil(4) * 4;
+
+    let mut vertexes = Vec::new();
+    let mut remap: Vec<HashMap<u32, u32>> = vec![HashMap::new(); projected.len()];
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for (triangle, tri) in mesh.indices.chunks_exact(3).enumerate() {
+        let chart = chart_of[triangle];
+        for &index in tri {
+            let new_index = *remap[chart].entry(index).or_insert_with(|| {
+                let Chart { coords, origin, .. } = &projected[chart];
+                let coord = coords[&index];
+                let mut vertex = mesh.vertexes[index as usize];
+                vertex.lightmap_coords = [
+                    (origin[0] as f32 + PADDING as f32 + coord.x) / width as f32,
+                    (origin[1] as f32 + PADDING as f32 + coord.y) / height as f32,
+                ];
+                vertexes.push(vertex);
+                vertexes.len() as u32 - 1
+            });
+            indices.push(new_index);
+        }
+    }
+
+    UnwrappedMesh {
+        vertexes,
+        indices,
+        width,
+        height,
+    }
+}
+
+struct Triangle {
+    positions: [Vector3<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    /// Lightmap coordinates, in luxels.
+    coords: [Vector2<f32>; 3],
+    layer: usize,
+}
+
+impl Triangle {
+    fn face_normal(&self) -> Vector3<f32> {
+        let [a, b, c] = self.positions;
+        (b - a).cross(c - a)
+    }
+}
+
+struct Hit {
+    triangle: usize,
+    /// Barycentric weights of the second and third corners.
+    u: f32,
+    v: f32,
+}
+
+struct Node {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    /// Leaves hold `count` triangles from `start`; inner nodes have their left
+    /// child next and the right child at `start`.
+    start: usize,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over the baked triangles.
+struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+
+impl Bvh {
+    fn new(triangles: &[Triangle]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            order: (0..triangles.len()).collect(),
+        };
+        if !triangles.is_empty() {
+            bvh.build(triangles, 0, triangles.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, triangles: &[Triangle], start: usize, end: usize) -> usize {
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for &triangle in &self.order[start..end] {
+            for p in triangles[triangle].positions {
+                min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            min,
+            max,
+            start,
+            count: end - start,
+        });
+        if end - start <= 4 {
+            return node;
+        }
+
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let centroid = |triangle: usize| {
+            let [a, b, c] = triangles[triangle].positions;
+            (a[axis] + b[axis] + c[axis]) / 3.0
+        };
+        self.order[start..end].sort_by(|&a, &b| centroid(a).total_cmp(&centroid(b)));
+        let middle = (start + end) / 2;
+        self.build(triangles, start, middle);
+        let right = self.build(triangles, middle, end);
+        self.nodes[node].start = right;
+        self.nodes[node].count = 0;
+        node
+    }
+
+    /// Closest triangle along the ray, within `max_distance`.
+    fn intersect(
+        &self,
+        triangles: &[Triangle],
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<Hit> {
+        let inverse = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let mut closest = max_distance;
+        let mut hit = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let Some(node) = self.nodes.get(index) else {
+                break;
+            };
+            let t0 = (node.min - origin).mul_element_wise(inverse);
+            let t1 = (node.max - origin).mul_element_wise(inverse);
+            let near =
+                t0.x.min(t1.x)
+                    .max(t0.y.min(t1.y))
+                    .max(t0.z.min(t1.z))
+                    .max(0.0);
+            let far = t0.x.max(t1.x).min(t0.y.max(t1.y)).min(t0.z.max(t1.z));
+            if near > far || near > closest {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            for &triangle in &self.order[node.start..node.start + node.count] {
+                if let Some((distance, u, v)) =
+                    ray_triangle(&triangles[triangle], origin, direction)
+                {
+                    if distance < closest {
+                        closest = distance;
+                        hit = Some(Hit { triangle, u, v });
+                    }
+                }
+            }
+        }
+        hit
+    }
+}
+
+/// Möller–Trumbore, hitting both sides.
+fn ray_triangle(
+    triangle: &Triangle,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+) -> Option<(f32, f32, f32)> {
+    let [a, b, c] = triangle.positions;
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = e2.dot(q) * inv_det;
+    (distance > RAY_OFFSET).then_some((distance, u, v))
+}
+
+#[derive(Copy, Clone)]
+struct Sample {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+}
+
+struct BakeLayer {
+    index: usize,
+    width: u32,
+    height: u32,
+    /// Average colour of the layer's texture, for light bouncing off it.
+    albedo: Vector3<f32>,
+    samples: Vec<Option<Sample>>,
+    direct: Vec<Vector3<f32>>,
+}
+
+impl BakeLayer {
+    fn luxel(&self, coord: Vector2<f32>) -> usize {
+        let x = (coord.x.max(0.0) as u32).min(self.width - 1);
+        let y = (coord.y.max(0.0) as u32).min(self.height - 1);
+        (x + y * self.width) as usize
+    }
+}
+
+/// Bakes sun, sky and baked point lights, plus one bounce, for every
+/// lightmapped layer of `level` and writes one PNG atlas per layer to `out_dir`.
+/// Runs entirely on the CPU.
+pub(crate) fn bake(
+    level: &Level,
+    registry: &PrimitiveRegistry,
+    library: &MaterialLibrary,
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    let occluders = level.occluders();
+    let mut layers = Vec::new();
+    let mut triangles = Vec::new();
+    for (index, layer) in level.layers.iter().enumerate() {
+        // Billboards turn with the camera, so there's no one mesh to bake.
+        if !layer.lightmap || layer.billboard != Billboard::None {
+            continue;
+        }
+        let primitive = registry.build(layer.primitive, &layer.params)?;
+        let instances = layer.instances(&*primitive);
+        let mesh = unwrap_mesh(&layer.static_mesh(&*primitive, &instances, &occluders));
+        let scale = Vector2::new(mesh.width as f32, mesh.height as f32);
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| &mesh.vertexes[i as usize]);
+            triangles.push(Triangle {
+                positions: [a, b, c].map(|v| Vector3::from(v.position)),
+                normals: [a, b, c].map(|v| Vector3::from(v.normal)),
+                coords: [a, b, c].map(|v| Vector2::from(v.lightmap_coords).mul_element_wise(scale)),
+                layer: layers.len(),
+            });
+        }
+        let luxels = (mesh.width * mesh.height) as usize;
+        layers.push(BakeLayer {
+            index,
+            width: mesh.width,
+            height: mesh.height,
+            albedo: tinted_albedo(library.resolve(layer.material)?)?,
+            samples: vec![None; luxels],
+            direct: vec![Vector3::zero(); luxels],
+        });
+    }
+
+    for triangle in &triangles {
+        rasterize(triangle, &mut layers[triangle.layer]);
+    }
+    let bvh = Bvh::new(&triangles);
+    let baked_lights: Vec<&PointLight> = level.lights.iter().filter(|light| light.baked).collect();
+
+    for layer in &mut layers {
+        for (luxel, sample) in layer.samples.iter().enumerate() {
+            if let Some(sample) = sample {
+                layer.direct[luxel] =
+                    direct_light(&bvh, &triangles, &level.lighting, &baked_lights, sample);
+            }
+        }
+        let filled: Vec<bool> = layer.samples.iter().map(Option::is_some).collect();
+        dilate(&mut layer.direct, filled, layer.width, layer.height);
+    }
+
+    for (layer_index, layer) in layers.iter().enumerate() {
+        let mut lighting = layer.direct.clone();
+        for (luxel, sample) in layer.samples.iter().enumerate() {
+            if let Some(sample) = sample {
+                let seed = (luxel as u32) ^ ((layer_index as u32) << 24);
+                lighting[luxel] +=
+                    hemisphere_light(&bvh, &triangles, &layers, &level.lighting, sample, seed);
+            }
+        }
+        let filled: Vec<bool> = layer.samples.iter().map(Option::is_some).collect();
+        dilate(&mut lighting, filled, layer.width, layer.height);
+
+        let img = image::RgbaImage::from_fn(layer.width, layer.height, |x, y| {
+            let light = lighting[(x + y * layer.width) as usize] / LIGHTMAP_RANGE;
+            let [r, g, b] = [light.x, light.y, light.z].map(linear_to_srgb);
+            image::Rgba([r, g, b, 255])
+        });
+        let path = atlas_path(out_dir, level.name, layer.index);
+        std::fs::create_dir_all(path.parent().unwrap_or(out_dir))?;
+        img.save(&path)?;
+        log::info!(
+            "baked layer {} ({}): {}x{} luxels",
+            layer.index,
+            level.layers[layer.index].primitive,
+            layer.width,
+            layer.height
+        );
+    }
+    Ok(())
+}
+
+/// Finds the surface point under the centre of each luxel the triangle covers.
+fn rasterize(triangle: &Triangle, layer: &mut BakeLayer) {
+    let [a, b, c] = triangle.coords;
+    let area = (b - a).perp_dot(c - a);
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+    let face_normal = triangle.face_normal().normalize();
+    let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+    let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+    let max_x = (a.x.max(b.x).max(c.x).ceil() as u32).min(layer.width);
+    let max_y = (a.y.max(b.y).max(c.y).ceil() as u32).min(layer.height);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let wa = (b - p).perp_dot(c - p) / area;
+            let wb = (c - p).perp_dot(a - p) / area;
+            let wc = 1.0 - wa - wb;
+            if wa < -1e-4 || wb < -1e-4 || wc < -1e-4 {
+                continue;
+            }
+            let [pa, pb, pc] = triangle.positions;
+            let [na, nb, nc] = triangle.normals;
+            let normal = na * wa + nb * wb + nc * wc;
+            layer.samples[(x + y * layer.width) as usize] = Some(Sample {
+                position: pa * wa + pb * wb + pc * wc,
+                normal: if normal.magnitude2() > f32::EPSILON {
+                    normal.normalize()
+                } else {
+                    face_normal
+                },
+            });
+        }
+    }
+}
+
+/// Sun and baked point lights, matching the runtime falloff.
+fn direct_light(
+    bvh: &Bvh,
+    triangles: &[Triangle],
+    lighting: &Lighting,
+    lights: &[&PointLight],
+    sample: &Sample,
+) -> Vector3<f32> {
+    let origin = sample.position + sample.normal * RAY_OFFSET;
+    let mut total = Vector3::zero();
+
+    let to_sun = -lighting.sun_direction.normalize();
+    let sun = sample.normal.dot(to_sun);
+    if sun > 0.0 && bvh.intersect(triangles, origin, to_sun, f32::MAX).is_none() {
+        total += Vector3::from(lighting.sun_color) * sun;
+    }
+
+    for light in lights {
+        let to_light = light.position - sample.position;
+        let distance = to_light.magnitude();
+        if distance >= light.range || distance < f32::EPSILON {
+            continue;
+        }
+        let direction = to_light / distance;
+        let diffuse = sample.normal.dot(direction);
+        if diffuse <= 0.0 {
+            continue;
+        }
+        let ratio = distance / light.range;
+        let window = (1.0 - ratio.powi(4)).clamp(0.0, 1.0);
+        let mut attenuation = window * window / (distance * distance + 1.0);
+        if let Some(spot) = light.spot {
+            let cos_angle = (-direction).dot(spot.direction.normalize());
+            attenuation *= smoothstep(
+                cgmath::Angle::cos(spot.outer_angle),
+                cgmath::Angle::cos(spot.inner_angle),
+                cos_angle,
+            );
+        }
+        if attenuation <= 0.0
+            || bvh
+                .intersect(triangles, origin, direction, distance)
+                .is_some()
+        {
+            continue;
+        }
+        total += Vector3::from(light.color) * (light.intensity * diffuse * attenuation);
+    }
+    total
+}
+
+/// Cosine-weighted rays over the hemisphere: misses see the sky, hits pick up
+/// the direct light of whatever they land on.
+fn hemisphere_light(
+    bvh: &Bvh,
+    triangles: &[Triangle],
+    layers: &[BakeLayer],
+    lighting: &Lighting,
+    sample: &Sample,
+    seed: u32,
+) -> Vector3<f32> {
+    let normal = sample.normal;
+    let tangent = if normal.y.abs() < 0.9 {
+        Vector3::unit_y().cross(normal).normalize()
+    } else {
+        normal.cross(Vector3::unit_x()).normalize()
+    };
+    let bitangent = normal.cross(tangent);
+    let origin = sample.position + normal * RAY_OFFSET;
+    let (jitter_u, jitter_v) = (hash(seed), hash(seed.wrapping_add(0x9e37_79b9)));
+
+    let mut total = Vector3::zero();
+    for i in 0..HEMISPHERE_RAYS {
+        // Hammersley points, shifted per luxel so neighbours don't band.
+        let u = (i as f32 / HEMISPHERE_RAYS as f32 + jitter_u).fract();
+        let v = (i.reverse_bits() as f32 / 4_294_967_296.0 + jitter_v).fract();
+        let radius = u.sqrt();
+        let (sin, cos) = (v * std::f32::consts::TAU).sin_cos();
+        let direction =
+            (tangent * (radius * cos) + bitangent * (radius * sin) + normal * (1.0 - u).sqrt())
+                .normalize();
+
+        match bvh.intersect(triangles, origin, direction, f32::MAX) {
+            None => total += Vector3::from(lighting.ambient_color),
+            Some(hit) => {
+                let triangle = &triangles[hit.triangle];
+                // The back of a surface is inside something solid.
+                if triangle.face_normal().dot(direction) >= 0.0 {
+                    continue;
+                }
+                let [a, b, c] = triangle.coords;
+                let coord = a * (1.0 - hit.u - hit.v) + b * hit.u + c * hit.v;
+                let layer = &layers[triangle.layer];
+                total += layer.direct[layer.luxel(coord)].mul_element_wise(layer.albedo);
+            }
+        }
+    }
+    total / HEMISPHERE_RAYS as f32
+}
+
+/// Fills empty luxels from their filled neighbours, so filtering at chart
+/// edges doesn't blend in black.
+fn dilate(light: &mut [Vector3<f32>], mut filled: Vec<bool>, width: u32, height: u32) {
+    for _ in 0..=PADDING {
+        let mut next = filled.clone();
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let luxel = (x + y * width as i32) as usize;
+                if filled[luxel] {
+                    continue;
+                }
+                let mut sum = Vector3::zero();
+                let mut count = 0;
+                for (dx, dy) in [
+                    (-1, 0),
+                    (1, 0),
+                    (0, -1),
+                    (0, 1),
+                    (-1, -1),
+                    (1, -1),
+                    (-1, 1),
+                    (1, 1),
+                ] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    let neighbour = (nx + ny * width as i32) as usize;
+                    if filled[neighbour] {
+                        sum += light[neighbour];
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    light[luxel] = sum / count as f32;
+                    next[luxel] = true;
+                }
+            }
+        }
+        filled = next;
+    }
+}
+
+/// The material's average colour as bounced light sees it.
+fn tinted_albedo(material: &MaterialDef) -> anyhow::Result<Vector3<f32>> {
+    let [r, g, b, _] = material.tint;
+    Ok(average_color(&material.texture)?.mul_element_wise(Vector3::new(r, g, b)))
+}
+
+/// Average linear colour of the opaque texels of a texture.
+fn average_color(bytes: &[u8]) -> anyhow::Result<Vector3<f32>> {
+    let file = TextureFile::parse(bytes)?;
+    let texels: Vec<[f32; 4]> = if file.is_hdr() {
+        file.to_rgba32f()?[0]
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect()
+    } else {
+        let to_linear = |c: u8| {
+            if file.format.describe().srgb {
+                srgb_to_linear(c)
+            } else {
+                c as f32 / 255.0
+            }
+        };
+        file.to_rgba8()?[0]
+            .pixels()
+            .map(|pixel| pixel.0.map(to_linear))
+            .collect()
+    };
+    let mut sum = Vector3::zero();
+    let mut count = 0;
+    // Colour keyed texels were made transparent as the image was read.
+    for [r, g, b, a] in texels {
+        if a == 0.0 {
+            continue;
+        }
+        sum += Vector3::new(r, g, b);
+        count += 1;
+    }
+    Ok(if count > 0 {
+        sum / count as f32
+    } else {
+        Vector3::new(0.5, 0.5, 0.5)
+    })
+}
+
+pub(crate) fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> u8 {
+    let c = value.clamp(0.0, 1.0);
+    let encoded = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Cheap integer hash to a float in 0..1.
+fn hash(mut x: u32) -> f32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x as f32 / 4_294_967_296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{LevelLayer, MapTiles, Placement},
+        mesher,
+        primitive::PrimitiveParams,
+    };
+
+    fn triangle(positions: [[f32; 3]; 3]) -> Triangle {
+        let positions = positions.map(Vector3::from);
+        let normal = (positions[1] - positions[0])
+            .cross(positions[2] - positions[0])
+            .normalize();
+        Triangle {
+            positions,
+            normals: [normal; 3],
+            coords: [Vector2::zero(); 3],
+            layer: 0,
+        }
+    }
+
+    fn tiles(map: Vec<i32>, width: usize) -> MapTiles {
+        let depth = map.len() / width;
+        MapTiles { map, width, depth }
+    }
+
+    #[test]
+    fn unwrapped_charts_do_not_overlap() {
+        let mesh = mesher::mesh_tiles(&tiles(vec![2, 1, 1, 0], 2), None, 1.0, 1.0, 1.0);
+        let unwrapped = unwrap_mesh(&mesh);
+        let (width, height) = (unwrapped.width, unwrapped.height);
+        let coords: Vec<Vector2<f32>> = unwrapped
+            .vertexes
+            .iter()
+            .map(|vertex| {
+                let [u, v] = vertex.lightmap_coords;
+                assert!((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v));
+                Vector2::new(u * width as f32, v * height as f32)
+            })
+            .collect();
+        // Every luxel centre is inside at most one triangle.
+        let mut covered = vec![0; (width * height) as usize];
+        for tri in unwrapped.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| coords[i as usize]);
+            let area = (b - a).perp_dot(c - a);
+            for y in 0..height {
+                for x in 0..width {
+                    let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let inside = [(a, b), (b, c), (c, a)]
+                        .iter()
+                        .all(|(from, to)| (to - from).perp_dot(p - from) * area.signum() > 0.0);
+                    if inside {
+                        covered[(x + y * width) as usize] += 1;
+                    }
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count <= 1));
+        assert!(covered.contains(&1));
+    }
+
+    #[test]
+    fn ray_hits_the_front_and_back_of_a_triangle() {
+        let triangle = triangle([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        let (distance, u, v) = ray_triangle(&triangle, Vector3::new(0.25, 0.5, 2.0), down).unwrap();
+        assert!((distance - 2.0).abs() < 1e-5);
+        assert!((u - 0.25).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+        assert!(ray_triangle(&triangle, Vector3::new(0.25, 0.5, -2.0), -down).is_some());
+    }
+
+    #[test]
+    fn ray_misses_beside_behind_and_parallel() {
+        let triangle = triangle([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        assert!(ray_triangle(&triangle, Vector3::new(0.8, 0.8, 1.0), down).is_none());
+        assert!(ray_triangle(&triangle, Vector3::new(0.25, 0.25, 1.0), -down).is_none());
+        let along = Vector3::new(1.0, 0.0, 0.0);
+        assert!(ray_triangle(&triangle, Vector3::new(-1.0, 0.25, 0.0), along).is_none());
+    }
+
+    #[test]
+    fn bvh_finds_the_closest_triangle() {
+        // A row of walls facing along x, one unit apart.
+        let triangles: Vec<Triangle> = (0..20)
+            .map(|i| {
+                let x = i as f32;
+                triangle([[x, -1.0, -1.0], [x, 1.0, -1.0], [x, 0.0, 2.0]])
+            })
+            .collect();
+        let bvh = Bvh::new(&triangles);
+        let along = Vector3::new(1.0, 0.0, 0.0);
+        let hit = bvh
+            .intersect(&triangles, Vector3::new(6.5, 0.0, 0.0), along, f32::MAX)
+            .unwrap();
+        assert_eq!(hit.triangle, 7);
+        let hit = bvh
+            .intersect(&triangles, Vector3::new(6.5, 0.0, 0.0), -along, f32::MAX)
+            .unwrap();
+        assert_eq!(hit.triangle, 6);
+        // Out of range, past the end and above every wall.
+        let origin = Vector3::new(6.5, 0.0, 0.0);
+        assert!(bvh.intersect(&triangles, origin, along, 0.4).is_none());
+        assert!(bvh
+            .intersect(&triangles, Vector3::new(19.5, 0.0, 0.0), along, f32::MAX)
+            .is_none());
+        assert!(bvh
+            .intersect(&triangles, Vector3::new(6.5, 5.0, 0.0), along, f32::MAX)
+            .is_none());
+    }
+
+    #[test]
+    fn bakes_a_one_wall_level() {
+        let level = Level {
+            name: "one_wall",
+            layers: vec![LevelLayer {
+                primitive: "cube",
+                params: PrimitiveParams::default(),
+                material: "wall",
+                tiles: tiles(vec![1], 1),
+                placement: Placement::Stacked,
+                merge: true,
+                lightmap: true,
+                ambient_occlusion: false,
+                billboard: Billboard::None,
+                animation: None,
+            }],
+            lighting: Lighting::default(),
+            lights: Vec::new(),
+        };
+        let registry = PrimitiveRegistry::with_builtins();
+        let library = MaterialLibrary::load_default().unwrap();
+        let out_dir = std::env::temp_dir().join(format!("lightmap_test_{}", std::process::id()));
+        bake(&level, &registry, &library, &out_dir).unwrap();
+
+        let atlas = image::open(atlas_path(&out_dir, level.name, 0))
+            .unwrap()
+            .to_rgba8();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+        let mesh = unwrap_mesh(&level.layers[0].static_mesh(
+            &*registry.build("cube", &PrimitiveParams::default()).unwrap(),
+            &[],
+            &level.occluders(),
+        ));
+        assert_eq!(atlas.dimensions(), (mesh.width, mesh.height));
+        // The sun and sky light the wall.
+        assert!(atlas.pixels().any(|pixel| pixel.0[0] > 0));
+    }
+}
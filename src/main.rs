@@ -1,30 +1,52 @@
 #![deny(clippy::all)]
 
+mod antialiasing;
+mod block_compression;
 mod camera;
 mod collision_detection;
 mod cube;
+mod cylinder;
 mod floor;
+mod gpu_culling;
+mod level;
+mod light;
+mod lightmap;
+mod material;
+mod material_library;
+mod postprocess;
+mod primitive;
+mod pvs;
+mod render_queue;
+mod shaders;
+mod shadow;
+// OBJ loading isn't used by the tile levels yet.
+#[allow(dead_code)]
+mod resources;
 mod sprite;
+mod sprite_sheet;
 mod slope;
+mod stairs;
 mod camera_controller;
 mod camera_uniform;
+mod clustered;
+mod culling;
 mod instance;
+mod mesher;
 mod model;
 mod texture;
+mod texture_file;
 mod systems;
 
 use std::time::Instant;
 
-use collision_detection::CollisionDetection;
-use cube::Cube;
-use floor::Floor;
+use cgmath::EuclideanSpace;
 
-use slope::Slope;
-use sprite::Sprite;
+use collision_detection::CollisionDetection;
+use primitive::PrimitiveRegistry;
 use systems::*;
 use wgpu::util::DeviceExt;
 use winit::{
-    event::{Event, VirtualKeyCode, WindowEvent, DeviceEvent},
+    event::{DeviceEvent, ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
@@ -44,15 +66,69 @@ pub enum CompareFunction {
     Always = 8,
 }
 
-struct MapTiles {
-    map: Vec<i32>,
-    width: usize,
-    depth: usize
-}
-
 fn main() {
     
     env_logger::init(); // Necessary for logging within WGPU
+
+    // `bake [dir]` precomputes the level's lightmaps without opening a window.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bake") {
+        let out_dir = args.get(2).map(String::as_str).unwrap_or(lightmap::LIGHTMAP_DIR);
+        let level = level::Level::demo();
+        let registry = PrimitiveRegistry::with_builtins();
+        let baked = material_library::MaterialLibrary::load_default().and_then(|library| {
+            lightmap::bake(&level, &registry, &library, std::path::Path::new(out_dir))
+        });
+        if let Err(err) = baked {
+            eprintln!("bake failed: {:#}", err);
+            std::process::exit(1);
+        }
+        println!("lightmaps written to {}", out_dir);
+        return;
+    }
+    // `check-shaders` validates every shader variant without a GPU.
+    if args.get(1).map(String::as_str) == Some("check-shaders") {
+        if let Err(err) = shaders::check_all() {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `pvs [dir]` precomputes which grid cells can see each other.
+    if args.get(1).map(String::as_str) == Some("pvs") {
+        let out_dir = args.get(2).map(String::as_str).unwrap_or(pvs::PVS_DIR);
+        let level = level::Level::demo();
+        let start = std::time::Instant::now();
+        let registry = PrimitiveRegistry::with_builtins();
+        let pvs = match pvs::Pvs::compute(&level, &registry) {
+            Ok(pvs) => pvs,
+            Err(err) => {
+                eprintln!("pvs failed: {:#}", err);
+                std::process::exit(1);
+            }
+        };
+        let elapsed = start.elapsed();
+        let path = match pvs.save(std::path::Path::new(out_dir), level.name) {
+            Ok(path) => path,
+            Err(err) => {
+                eprintln!("pvs failed: {:#}", err);
+                std::process::exit(1);
+            }
+        };
+        let stats = pvs.stats();
+        println!(
+            "{} cells, {} open, each sees {}..{} (avg {:.1}, {:.0}% hidden) in {:.2?}",
+            stats.cells,
+            stats.open,
+            stats.min_visible,
+            stats.max_visible,
+            stats.average_visible,
+            100.0 * (1.0 - stats.average_visible / stats.cells.max(1) as f32),
+            elapsed
+        );
+        println!("pvs written to {}", path.display());
+        return;
+    }
     let event_loop = EventLoop::new(); // Loop provided by winit for handling window events
     let window = WindowBuilder::new().build(&event_loop).unwrap(); // Create a window centered around the Loop
 
@@ -68,7 +144,7 @@ fn main() {
     let (device, queue) = pollster::block_on(adapter.request_device(
         &wgpu::DeviceDescriptor {
             label: None,
-            features: wgpu::Features::empty(),
+            features: texture_file::required_features(&adapter),
             limits: wgpu::Limits::default(),
         },
         None, // Trace path
@@ -77,7 +153,7 @@ fn main() {
 
     let size = window.inner_size();
 
-    let mut config = init_config(&surface, adapter, size);
+    let mut config = init_config(&surface, &adapter, size);
 
     surface.configure(&device, &config);
 
@@ -93,132 +169,49 @@ fn main() {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
         
-    let (camera_bind_group_layout, camera_bind_group) = camera_bind_init(&device, &camera_buffer);
+    let level = level::Level::demo();
+    let registry = PrimitiveRegistry::with_builtins();
+    let pvs = or_exit(pvs::Pvs::load_or_compute(&level, &registry));
+    let light_buffer = level.lighting.create_buffer(&device);
+    let (camera_bind_group_layout, camera_bind_group) = camera_bind_init(&device, &camera_buffer, &light_buffer);
 
     let texture_bind_group_layout = texture_bind_group_layout_init(&device);
     let mut depth_texture =
-        texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        texture::Texture::create_depth_texture(&device, &config, 1, "depth_texture");
 
 
-    let mut cube = Cube::new(1.0,1.0,1.0);
-    let wall_bytes = include_bytes!("wall.png");
-    let wall_bind_group = create_texture(&device, &queue, wall_bytes, &texture_bind_group_layout);
-    let (wall_vertex_buffer, wall_index_buffer, wall_num_indices) = create_buffers(&device, &cube.vertexes, &cube.indices);
-        
-    let walls = MapTiles{
-            // map: vec![
-            //     2, 2, 2, 2, 2, 2, 2, 2, 
-            //     2, 0, 0, 2, 0, 0, 0, 2, 
-            //     2, 0, 0, 2, 0, 1, 0, 2, 
-            //     2, 2, 0, 2, 0, 0, 0, 2, 
-            //     2, 0, 0, 0, 0, 0, 0, 2, 
-            //     2, 0, 0, 0, 0, 1, 0, 2, 
-            //     2, 0, 0, 0, 0, 0, 0, 2, 
-            //     2, 2, 2, 2, 2, 2, 2, 2,
-            // ],
-            map: vec![
-                6, 5, 6, 5, 6, 5, 6, 5, 
-                5, 0, 0, 5, 0, 0, 0, 6, 
-                6, 0, 0, 5, 0, 0, 0, 5, 
-                5, 5, 0, 5, 0, 0, 0, 6, 
-                6, 0, 0, 0, 0, 0, 0, 5, 
-                5, 0, 0, 0, 0, 0, 0, 6, 
-                6, 0, 0, 0, 0, 0, 0, 5, 
-                5, 6, 5, 6, 5, 6, 5, 6,
-            ],
-            width: 8,
-            depth: 8
-        };
-
-    let (instances, instance_buffer) = instance_init(&device, walls, cube.width, cube.height, cube.depth);
-        
-    let mut floor = Floor::new(1.0,1.0, 1.0);
-    let floor_bytes = include_bytes!("floor.png");
-    let floor_bind_group = create_texture(&device, &queue, floor_bytes, &texture_bind_group_layout);
-    let (floor_vertex_buffer, floor_index_buffer, floor_num_indices) = create_buffers(&device, &floor.vertexes, &floor.indices);
-
-    let floor_tiles = MapTiles{
-        // map: vec![
-        //     0, 0, 0, 0, 0, 0, 0, 0, 
-        //     0, 1, 1, 0, 1, 1, 1, 0, 
-        //     0, 1, 1, 0, 1, 0, 1, 0, 
-        //     0, 0, 1, 0, 1, 1, 1, 0, 
-        //     0, 1, 1, 1, 1, 1, 1, 0, 
-        //     0, 1, 1, 1, 1, 0, 1, 0, 
-        //     0, 1, 1, 1, 1, 1, 1, 0, 
-        //     0, 0, 0, 0, 0, 0, 0, 0,
-        // ],
-        map: vec![
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 1, 1, 0, 1, 1, 1, 0, 
-            0, 1, 1, 0, 1, 1, 1, 0, 
-            0, 0, 1, 0, 1, 1, 1, 0, 
-            0, 1, 1, 1, 1, 1, 1, 0, 
-            0, 1, 1, 1, 1, 1, 1, 0, 
-            0, 1, 1, 1, 1, 1, 1, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0,
-        ],
-        width: 8,
-        depth: 8
-    };
-
-    let (instances2, instance_buffer2) = instance_init(&device, floor_tiles, floor.width, floor.height, floor.depth);
-
-    let sprite = Sprite::new(1.0,1.0);
-    let sprite_bytes = include_bytes!("enemy.png");
-    let sprite_bind_group = create_texture(&device, &queue, sprite_bytes, &texture_bind_group_layout);
-    let (sprite_vertex_buffer, sprite_index_buffer, sprite_num_indices) = create_buffers(&device, &sprite.vertexes, &sprite.indices);
-
-    let sprite_tiles = MapTiles{
-        map: vec![
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 1, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0,
-        ],
-        width: 8,
-        depth: 8
-    };
-
-    let (instances3, instance_buffer3) = instance_init(&device, sprite_tiles, sprite.width, sprite.height, 1.0);
-
-
-    let mut slope = Slope::new(1.0,6.0, 5.0, "backward");
-    let slope_bytes = include_bytes!("floor.png");
-    let slope_bind_group = create_texture(&device, &queue, slope_bytes, &texture_bind_group_layout);
-    let (slope_vertex_buffer, slope_index_buffer, slope_num_indices) = create_buffers(&device, &slope.vertexes, &slope.indices);
-
-    let slope_tiles = MapTiles{
-        map: vec![
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 1, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0, 
-            0, 0, 0, 0, 0, 0, 0, 0,
-        ],
-        width: 8,
-        depth: 8
+    let library = match material_library::MaterialLibrary::load_default() {
+        Ok(library) => library,
+        Err(err) => {
+            eprintln!("couldn't load materials: {:#}", err);
+            std::process::exit(1);
+        }
     };
+    let (mut layers, level_batch) = level
+        .load(&device, &queue, &texture_bind_group_layout, &registry, &library)
+        .unwrap();
 
-    let (instances4, instance_buffer4) = slope_instance_init(&device, slope_tiles, slope.width, slope.height, 1.0);
+    let mut lights = level.lights.clone();
+    let mut light_clusters = clustered::LightClusters::new(&device, &projection, config.width, config.height);
+    let mut shadow_maps = or_exit(shadow::ShadowMaps::new(&device, &texture_bind_group_layout));
+    let mut gpu_culling = or_exit(gpu_culling::GpuCulling::new(&device, &layers, &pvs, &depth_texture));
 
-
-    let render_pipeline = pipeline_init(
+    let mut multisampling = or_exit(antialiasing::Multisampling::new(&device, &adapter, &config));
+    let mut pipelines = or_exit(render_queue::ScenePipelines::new(
         &device,
-        texture_bind_group_layout,
-        camera_bind_group_layout,
-        &config,
-    );
+        &texture_bind_group_layout,
+        &camera_bind_group_layout,
+        &light_clusters.bind_group_layout,
+        &shadow_maps.bind_group_layout,
+        multisampling.mode().sample_count(),
+    ));
+    let mut shader_watcher = shaders::ShaderWatcher::new();
+    let mut post = or_exit(postprocess::PostProcess::new(&device, &queue, &config, &depth_texture));
+    post.settings.fxaa = multisampling.mode() == antialiasing::AntiAliasing::Fxaa;
 
     let time = Instant::now();
     let mut frame1 = 0;
+    let mut last_cull_log = 0;
 
     // Opens the window and starts processing events (although no events are handled yet)
     event_loop.run(move |event, _, control_flow| {
@@ -242,6 +235,7 @@ fn main() {
                     WindowEvent::Resized(size) => {
                         if size.width * size.height > 0 {
                             projection.resize(size.width,size.height);
+                            light_clusters.resize(&projection, size.width, size.height);
                             config.width = size.width;
                             config.height = size.height;
                             surface.configure(&device, &config);
@@ -249,8 +243,12 @@ fn main() {
                         depth_texture = texture::Texture::create_depth_texture(
                             &device,
                             &config,
+                            1,
                             "depth_texture",
                         );
+                        post.resize(&device, &queue, &config, &depth_texture);
+                        multisampling.resize(&device, &config);
+                        gpu_culling.resize(&device, &depth_texture);
                     }
 
                     WindowEvent::ScaleFactorChanged {
@@ -261,6 +259,15 @@ fn main() {
                             config.width = size.width;
                             config.height = size.height;
                             surface.configure(&device, &config);
+                            depth_texture = texture::Texture::create_depth_texture(
+                                &device,
+                                &config,
+                                1,
+                                "depth_texture",
+                            );
+                            post.resize(&device, &queue, &config, &depth_texture);
+                            multisampling.resize(&device, &config);
+                            gpu_culling.resize(&device, &depth_texture);
                         }
                     }
 
@@ -268,6 +275,15 @@ fn main() {
                         if input.virtual_keycode == Some(VirtualKeyCode::Escape) {
                             *control_flow = ControlFlow::Exit
                         }
+                        if input.state == ElementState::Pressed {
+                            if input.virtual_keycode == Some(VirtualKeyCode::F8) {
+                                let mode = multisampling.cycle(&device, &config);
+                                post.settings.fxaa = mode == antialiasing::AntiAliasing::Fxaa;
+                                pipelines.set_sample_count(&device, mode.sample_count());
+                            } else if let Some(key) = input.virtual_keycode {
+                                post.process_key(key);
+                            }
+                        }
                     }
                     _ => {}
                 }
@@ -285,12 +301,64 @@ fn main() {
                     label: Some("Render Encoder"),
                 });
 
+                let mut collision = CollisionDetection::new();
+                collision.detect_level(&mut camera, &layers, camera_controller.max_step_height);
+
+                camera_controller.update_camera(&mut camera, dt, collision);
+                camera_uniform.update_view_proj(&camera, &projection);
+                queue.write_buffer(&camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+                // Like the camera buffer, this lands before the cull pass runs.
+                gpu_culling.update(&queue, &camera_uniform, &pvs);
+                level::animate_layers(
+                    &queue,
+                    &mut layers,
+                    &gpu_culling,
+                    dt as f32 / 1000.0,
+                    camera.position,
+                );
+
+                // The first level light is the player's lantern.
+                if let Some(lantern) = lights.first_mut() {
+                    lantern.position = camera.position.to_vec();
+                }
+                // Before the shadow passes are recorded, so spot lights that
+                // just came into view get theirs this frame.
+                let shadow_layers =
+                    shadow_maps.update(&queue, &camera, &projection, &level.lighting, &lights);
+                light_clusters.update(&queue, &camera, &lights, &shadow_layers);
+                post.update(&queue, &projection);
+
+                shadow_maps.render(&mut encoder, &layers, &level_batch);
+                gpu_culling.cull(&mut encoder);
+
+                let queues = render_queue::RenderQueues::build(
+                    &queue,
+                    &layers,
+                    &camera_uniform,
+                    &pvs,
+                    &gpu_culling,
+                );
+                if frame1 - last_cull_log >= 1000 {
+                    log::debug!(
+                        "blended culling: {} drawn, {} culled, {} hidden by the PVS",
+                        queues.stats.drawn,
+                        queues.stats.culled,
+                        queues.stats.hidden
+                    );
+                    last_cull_log = frame1;
+                }
+
+                // With MSAA the scene resolves into the HDR target.
+                let (color_view, resolve_target, depth_view) = match multisampling.targets() {
+                    Some(targets) => (&targets.color.view, Some(post.hdr_view()), &targets.depth.view),
+                    None => (post.hdr_view(), None, &depth_texture.view),
+                };
                 {
                     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                         label: Some("Render Pass"),
                         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
+                            view: color_view,
+                            resolve_target,
                             ops: wgpu::Operations {
                                 load: wgpu::LoadOp::Clear(wgpu::Color {
                                     r: 0.1, // Pick any color you want here
@@ -302,7 +370,7 @@ fn main() {
                             },
                         })],
                         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: &depth_texture.view,
+                            view: depth_view,
                             depth_ops: Some(wgpu::Operations {
                                 load: wgpu::LoadOp::Clear(1.0),
                                 store: true,
@@ -311,58 +379,37 @@ fn main() {
                         }),
                     });
 
-                    render_pass.set_pipeline(&render_pipeline);
                     render_pass.set_bind_group(1, &camera_bind_group, &[]);
-
-                    render_pass.set_bind_group(0, &wall_bind_group, &[]);
-                    render_pass.set_vertex_buffer(0, wall_vertex_buffer.slice(..));
-                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
-                    render_pass.set_index_buffer(wall_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..wall_num_indices, 0, 0..instances.len() as _);
-
-
-                    render_pass.set_bind_group(0, &floor_bind_group, &[]);
-                    render_pass.set_vertex_buffer(0, floor_vertex_buffer.slice(..));
-                    render_pass.set_vertex_buffer(1, instance_buffer2.slice(..));
-                    render_pass.set_index_buffer(floor_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..floor_num_indices, 0, 0..instances2.len() as _);
-
-                    render_pass.set_bind_group(0, &sprite_bind_group, &[]);
-                    render_pass.set_vertex_buffer(0, sprite_vertex_buffer.slice(..));
-                    render_pass.set_vertex_buffer(1, instance_buffer3.slice(..));
-                    render_pass.set_index_buffer(sprite_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..sprite_num_indices, 0, 0..instances3.len() as _);
-
-                    render_pass.set_bind_group(0, &slope_bind_group, &[]);
-                    render_pass.set_vertex_buffer(0, slope_vertex_buffer.slice(..));
-                    render_pass.set_vertex_buffer(1, instance_buffer4.slice(..));
-                    render_pass.set_index_buffer(slope_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..slope_num_indices, 0, 0..instances4.len() as _);
-                    
-                    
+                    render_pass.set_bind_group(2, &light_clusters.bind_group, &[]);
+                    render_pass.set_bind_group(3, &shadow_maps.bind_group, &[]);
+                    queues.draw(&mut render_pass, &pipelines, &layers, &level_batch, &gpu_culling);
                 }
+                multisampling.resolve_depth(&mut encoder, &depth_texture);
+                gpu_culling.build_pyramid(&mut encoder);
 
-                let mut collision = CollisionDetection::new();
-
-                collision.detect(&mut camera, instances.as_slice(), &mut cube);
-        
-                if !collision.up {
-                    collision.slope_detect(&mut camera, instances4.as_slice(), &mut slope);
-                }
-        
-                if !collision.up {
-                    collision.floor_detect(&mut camera, instances2.as_slice(), &mut floor);
-                }
-
-                camera_controller.update_camera(&mut camera, dt, collision);
-                camera_uniform.update_view_proj(&camera, &projection);
-                queue.write_buffer(&camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+                post.render(&mut encoder, &view);
 
                 queue.submit(std::iter::once(encoder.finish()));
                 output.present();
             }
 
             Event::MainEventsCleared => {
+                // Saved shaders replace the running ones unless they're broken.
+                if shader_watcher.poll() {
+                    let reloads = [
+                        ("scene", pipelines.reload(&device)),
+                        ("shadow", shadow_maps.reload(&device)),
+                        ("post and FXAA", post.reload(&device)),
+                        ("depth resolve", multisampling.reload(&device)),
+                        ("cull", gpu_culling.reload(&device)),
+                    ];
+                    for (pass, reloaded) in reloads {
+                        match reloaded {
+                            Ok(()) => log::info!("{} shaders reloaded", pass),
+                            Err(err) => log::error!("keeping the old {} shaders: {:#}", pass, err),
+                        }
+                    }
+                }
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
                 window.request_redraw();
@@ -372,3 +419,12 @@ fn main() {
         }
     });
 }
+
+/// Ends the program with the error instead of a panic when part of the
+/// renderer can't be built, such as a shader on disk that doesn't compile.
+fn or_exit<T>(result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("couldn't start the renderer: {:#}", err);
+        std::process::exit(1);
+    })
+}
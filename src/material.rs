@@ -0,0 +1,219 @@
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    level::CELL_SIZE,
+    texture::{self, Sampling},
+    texture_file::TextureFile,
+};
+
+/// Marks a map a material doesn't have, which the shader replaces with a
+/// flat default.
+const NO_MAP: u32 = u32::MAX;
+
+/// How texture coordinates are produced for a surface.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub(crate) enum UvMode {
+    /// The mesh's own coordinates, 0..1 across each face.
+    #[default]
+    Stretch,
+    /// Planar projection along the face's dominant axis, tiled in world space.
+    World { texels_per_unit: f32 },
+    /// Blend of the three axis projections, for surfaces that aren't axis aligned.
+    Triplanar { texels_per_unit: f32 },
+}
+
+/// How a surface's alpha is used, which also decides when it's drawn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BlendMode {
+    /// Alpha is ignored. Drawn first.
+    #[default]
+    Opaque,
+    /// Texels under half alpha are discarded, the rest write depth like opaque
+    /// surfaces. Drawn after the opaque ones.
+    AlphaTested,
+    /// Blended over what's behind without writing depth. Drawn last, with
+    /// instances sorted back to front.
+    Blended,
+}
+
+/// What a surface sounds like underfoot.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Footstep {
+    #[default]
+    Stone,
+    Wood,
+    Metal,
+    Dirt,
+    Water,
+}
+
+/// Maps beyond the diffuse texture, all optional. Normal and
+/// roughness/metallic maps hold data rather than colour, so they're loaded
+/// as linear. Emissive maps are colour, sRGB unless they're HDR images.
+#[derive(Clone, Debug)]
+pub(crate) struct SurfaceMaps {
+    /// Tangent space normals with +Y up the texture, as in glTF.
+    pub normal: Option<Vec<u8>>,
+    /// Roughness in green and metalness in blue, as in glTF.
+    pub roughness_metallic: Option<Vec<u8>>,
+    pub emissive: Option<Vec<u8>>,
+    /// Scales the map's roughness, or is used alone without one.
+    pub roughness: f32,
+    /// Scales the map's metalness, or is used alone without one.
+    pub metallic: f32,
+    /// Scales the emissive map. Above 1 it blooms.
+    pub emissive_strength: f32,
+}
+
+impl Default for SurfaceMaps {
+    /// Fully rough, non-metallic and flat, which looks the same as having
+    /// no maps at all.
+    fn default() -> Self {
+        Self {
+            normal: None,
+            roughness_metallic: None,
+            emissive: None,
+            roughness: 1.0,
+            metallic: 0.0,
+            emissive_strength: 1.0,
+        }
+    }
+}
+
+/// The texture arrays a material buffer indexes into, with a 1x1 default
+/// in any array no material uses.
+pub(crate) struct MaterialTextures {
+    pub diffuse: texture::Texture,
+    pub normal: texture::Texture,
+    pub roughness_metallic: texture::Texture,
+    pub emissive: texture::Texture,
+    pub lightmaps: texture::Texture,
+}
+
+impl MaterialTextures {
+    /// Builds an array for each kind of map from the images given for it,
+    /// in the order normal, roughness/metallic, emissive. Kinds without
+    /// images get a default texel: flat, rough and dark.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        diffuse: texture::Texture,
+        maps: [Vec<TextureFile>; 3],
+        lightmaps: texture::Texture,
+        sampling: Sampling,
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        let [normal, roughness_metallic, emissive] = maps;
+        let array = |mut files: Vec<TextureFile>, texel, format, kind| {
+            if files.is_empty() {
+                files.push(TextureFile::solid(texel, format));
+            }
+            let label = format!("{}_{}", label, kind);
+            texture::Texture::from_files(device, queue, &files, sampling, &label)
+        };
+        let linear = wgpu::TextureFormat::Rgba8Unorm;
+        Ok(Self {
+            diffuse,
+            normal: array(normal, [128, 128, 255, 255], linear, "normal")?,
+            roughness_metallic: array(roughness_metallic, [255; 4], linear, "roughness")?,
+            emissive: array(
+                emissive,
+                [0, 0, 0, 255],
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+                "emissive",
+            )?,
+            lightmaps,
+        })
+    }
+}
+
+/// One layer's entry in the level's material buffer, picked by each
+/// instance's material index.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct MaterialRaw {
+    pub uv_mode: u32,
+    /// Texture repeats per world unit.
+    pub uv_scale: f32,
+    /// Shifts world-space tiling so texture edges land on cell edges.
+    pub uv_offset: f32,
+    /// 1 when the surface's sun, sky and static lights come from its lightmap.
+    pub lightmapped: u32,
+    /// Layer of the diffuse texture array.
+    pub texture: u32,
+    /// Layer of the lightmap array, ignored unless lightmapped.
+    pub lightmap: u32,
+    /// Layers of the normal, roughness/metallic and emissive arrays, or
+    /// `NO_MAP`.
+    pub normal_map: u32,
+    pub roughness_map: u32,
+    pub emissive_map: u32,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub emissive_strength: f32,
+    /// Multiplies the diffuse texture, alpha included.
+    pub tint: [f32; 4],
+}
+
+impl MaterialRaw {
+    /// `texture_width` is the texture's own width, before it was packed into
+    /// the array.
+    pub(crate) fn new(
+        uv_mode: UvMode,
+        texture_width: u32,
+        texture: u32,
+        lightmap: Option<u32>,
+    ) -> Self {
+        let (mode, texels_per_unit) = match uv_mode {
+            UvMode::Stretch => (0, 0.0),
+            UvMode::World { texels_per_unit } => (1, texels_per_unit),
+            UvMode::Triplanar { texels_per_unit } => (2, texels_per_unit),
+        };
+        Self {
+            uv_mode: mode,
+            uv_scale: texels_per_unit / texture_width.max(1) as f32,
+            uv_offset: CELL_SIZE,
+            lightmapped: lightmap.is_some() as u32,
+            texture,
+            lightmap: lightmap.unwrap_or(0),
+            normal_map: NO_MAP,
+            roughness_map: NO_MAP,
+            emissive_map: NO_MAP,
+            roughness: 1.0,
+            metallic: 0.0,
+            emissive_strength: 1.0,
+            tint: [1.0; 4],
+        }
+    }
+
+    /// Adds the factors of `maps` and the array layers of the maps it has,
+    /// in the order normal, roughness/metallic, emissive.
+    pub(crate) fn with_maps(self, maps: &SurfaceMaps, layers: [Option<u32>; 3]) -> Self {
+        let [normal_map, roughness_map, emissive_map] = layers.map(|layer| layer.unwrap_or(NO_MAP));
+        Self {
+            normal_map,
+            roughness_map,
+            emissive_map,
+            roughness: maps.roughness,
+            metallic: maps.metallic,
+            emissive_strength: maps.emissive_strength,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_tint(self, tint: [f32; 4]) -> Self {
+        Self { tint, ..self }
+    }
+
+    pub(crate) fn create_buffer(materials: &[Self], device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(materials),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+}
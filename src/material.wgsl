@@ -0,0 +1,29 @@
+// The level's textures and materials, shared by every pass that draws it.
+
+// Every texture of the level, one per array layer.
+@group(0) @binding(0)
+var t_diffuse: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+struct Material {
+    // 0 = mesh coordinates, 1 = world planar, 2 = triplanar
+    uv_mode: u32,
+    uv_scale: f32,
+    uv_offset: f32,
+    // 1 when sun, sky and baked lights come from the lightmap
+    lightmapped: u32,
+    texture: u32,
+    lightmap: u32,
+    // Map layers, or NO_MAP
+    normal_map: u32,
+    roughness_map: u32,
+    emissive_map: u32,
+    roughness: f32,
+    metallic: f32,
+    emissive_strength: f32,
+    tint: vec4<f32>,
+}
+// One per layer, picked by the instance.
+@group(0) @binding(2)
+var<storage, read> materials: array<Material>;
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{
+    material::{BlendMode, Footstep, SurfaceMaps, UvMode},
+    sprite_sheet::SpriteSheet,
+    texture::Sampling,
+};
+
+/// Where the game looks for material files.
+pub(crate) const MATERIAL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/materials");
+
+/// Built-in copies of every material and the images they use, used when
+/// `MATERIAL_DIR` isn't around.
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("enemy.toml", include_bytes!("../materials/enemy.toml")),
+    ("floor.toml", include_bytes!("../materials/floor.toml")),
+    (
+        "floor_world.toml",
+        include_bytes!("../materials/floor_world.toml"),
+    ),
+    ("marker.toml", include_bytes!("../materials/marker.toml")),
+    ("orb.toml", include_bytes!("../materials/orb.toml")),
+    ("pillar.toml", include_bytes!("../materials/pillar.toml")),
+    ("wall.toml", include_bytes!("../materials/wall.toml")),
+    (
+        "enemy_sheet.png",
+        include_bytes!("../materials/enemy_sheet.png"),
+    ),
+    ("floor.png", include_bytes!("../materials/floor.png")),
+    ("marker.png", include_bytes!("../materials/marker.png")),
+    ("orb.png", include_bytes!("../materials/orb.png")),
+    ("wall.png", include_bytes!("../materials/wall.png")),
+];
+
+/// A material as written in a `.toml` file, named after the file. Image
+/// paths are relative to the file. Everything but the diffuse texture is
+/// optional:
+///
+/// ```toml
+/// texture = "floor.png"
+/// normal_map = "floor_normal.png"
+/// blend = "alpha_tested"
+/// tint = [1.0, 0.9, 0.8, 1.0]
+/// footstep = "wood"
+///
+/// [uv]
+/// mode = "world"
+/// texels_per_unit = 16.0
+///
+/// [sampling]
+/// filter = "smooth"
+/// address_mode = "clamp_to_edge"
+/// ```
+///
+/// A `[sheet]` cuts the texture into animation frames, see `SpriteSheet`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialFile {
+    texture: PathBuf,
+    normal_map: Option<PathBuf>,
+    roughness_metallic_map: Option<PathBuf>,
+    emissive_map: Option<PathBuf>,
+    #[serde(default)]
+    uv: UvMode,
+    #[serde(default)]
+    sampling: SamplingFile,
+    #[serde(default)]
+    blend: BlendMode,
+    tint: Option<[f32; 4]>,
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    emissive_strength: Option<f32>,
+    #[serde(default)]
+    footstep: Footstep,
+    sheet: Option<SpriteSheet>,
+}
+
+/// One of the `Sampling` presets, optionally with another address mode or
+/// anisotropy.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SamplingFile {
+    filter: Filter,
+    address_mode: AddressMode,
+    anisotropy: Option<u8>,
+}
+
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Filter {
+    #[default]
+    Pixelated,
+    Smooth,
+    Nearest,
+}
+
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AddressMode {
+    #[default]
+    Repeat,
+    ClampToEdge,
+    MirrorRepeat,
+}
+
+impl SamplingFile {
+    fn sampling(&self) -> anyhow::Result<Sampling> {
+        let preset = match self.filter {
+            Filter::Pixelated => Sampling::PIXELATED,
+            Filter::Smooth => Sampling::SMOOTH,
+            Filter::Nearest => Sampling::NEAREST,
+        };
+        let address_mode = match self.address_mode {
+            AddressMode::Repeat => wgpu::AddressMode::Repeat,
+            AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+        };
+        let anisotropy = self.anisotropy.unwrap_or(preset.anisotropy);
+        if ![1, 2, 4, 8, 16].contains(&anisotropy) {
+            bail!("anisotropy must be 1, 2, 4, 8 or 16, not {}", anisotropy);
+        }
+        if anisotropy > 1 && !matches!(self.filter, Filter::Smooth) {
+            bail!("anisotropy needs the smooth filter");
+        }
+        Ok(Sampling {
+            anisotropy,
+            ..preset.with_address_mode(address_mode)
+        })
+    }
+}
+
+/// A material with its images read, ready for a level layer or model to
+/// build its textures from.
+#[derive(Clone)]
+pub(crate) struct MaterialDef {
+    /// The diffuse image's file contents.
+    pub texture: Vec<u8>,
+    pub uv_mode: UvMode,
+    pub sampling: Sampling,
+    pub maps: SurfaceMaps,
+    pub blend: BlendMode,
+    /// Multiplies the diffuse texture, alpha included.
+    pub tint: [f32; 4],
+    pub footstep: Footstep,
+    pub sheet: Option<SpriteSheet>,
+}
+
+impl MaterialDef {
+    /// A plain opaque material around a diffuse image.
+    pub(crate) fn new(texture: Vec<u8>) -> Self {
+        Self {
+            texture,
+            uv_mode: UvMode::default(),
+            sampling: Sampling::default(),
+            maps: SurfaceMaps::default(),
+            blend: BlendMode::default(),
+            tint: [1.0; 4],
+            footstep: Footstep::default(),
+            sheet: None,
+        }
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&text, |image| {
+            let path = dir.join(image);
+            std::fs::read(&path).with_context(|| format!("couldn't read {}", path.display()))
+        })
+    }
+
+    /// A material file's contents, with `read` giving the images it names.
+    fn parse(text: &str, read: impl Fn(&Path) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<Self> {
+        let file: MaterialFile = toml::from_str(text)?;
+        if let Some(sheet) = &file.sheet {
+            sheet.validate()?;
+        }
+        let read_map = |image: &Option<PathBuf>| image.as_deref().map(&read).transpose();
+        let defaults = SurfaceMaps::default();
+        Ok(Self {
+            texture: read(&file.texture)?,
+            uv_mode: file.uv,
+            sampling: file.sampling.sampling()?,
+            maps: SurfaceMaps {
+                normal: read_map(&file.normal_map)?,
+                roughness_metallic: read_map(&file.roughness_metallic_map)?,
+                emissive: read_map(&file.emissive_map)?,
+                roughness: file.roughness.unwrap_or(defaults.roughness),
+                metallic: file.metallic.unwrap_or(defaults.metallic),
+                emissive_strength: file.emissive_strength.unwrap_or(defaults.emissive_strength),
+            },
+            blend: file.blend,
+            tint: file.tint.unwrap_or([1.0; 4]),
+            footstep: file.footstep,
+            sheet: file.sheet,
+        })
+    }
+}
+
+/// Every material in a directory, so levels and models can refer to them by
+/// name.
+pub(crate) struct MaterialLibrary {
+    materials: HashMap<String, MaterialDef>,
+}
+
+impl MaterialLibrary {
+    /// Reads every `.toml` file in `dir`, each named after its file.
+    pub(crate) fn load(dir: &Path) -> anyhow::Result<Self> {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("couldn't read materials from {}", dir.display()))?;
+        let mut materials = HashMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() != Some("toml".as_ref()) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let material = MaterialDef::load(&path)
+                .with_context(|| format!("bad material {}", path.display()))?;
+            materials.insert(name, material);
+        }
+        log::info!(
+            "{} materials loaded from {}",
+            materials.len(),
+            dir.display()
+        );
+        Ok(Self { materials })
+    }
+
+    /// Reads `MATERIAL_DIR`, or the built-in materials when it isn't around.
+    pub(crate) fn load_default() -> anyhow::Result<Self> {
+        let dir = Path::new(MATERIAL_DIR);
+        if dir.is_dir() {
+            return Self::load(dir);
+        }
+        log::info!("{} not found, using the built-in materials", dir.display());
+        Self::embedded()
+    }
+
+    fn embedded() -> anyhow::Result<Self> {
+        let read = |image: &Path| {
+            EMBEDDED
+                .iter()
+                .find(|(name, _)| Path::new(name) == image)
+                .map(|(_, bytes)| bytes.to_vec())
+                .with_context(|| format!("no built-in image {}", image.display()))
+        };
+        let mut materials = HashMap::new();
+        for (file, bytes) in EMBEDDED {
+            let name = match file.strip_suffix(".toml") {
+                Some(name) => name,
+                None => continue,
+            };
+            let material = std::str::from_utf8(bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| MaterialDef::parse(text, read))
+                .with_context(|| format!("bad built-in material {}", file))?;
+            materials.insert(name.to_string(), material);
+        }
+        Ok(Self { materials })
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&MaterialDef> {
+        self.materials.get(name)
+    }
+
+    /// Like `get`, but failing for materials that aren't in the library.
+    pub(crate) fn resolve(&self, name: &str) -> anyhow::Result<&MaterialDef> {
+        self.get(name)
+            .with_context(|| format!("no material named `{}`", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_materials_match_the_directory() {
+        let on_disk = MaterialLibrary::load(Path::new(MATERIAL_DIR)).unwrap();
+        let embedded = MaterialLibrary::embedded().unwrap();
+        let names = |library: &MaterialLibrary| {
+            let mut names: Vec<String> = library.materials.keys().cloned().collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&embedded), names(&on_disk));
+        for (name, material) in &on_disk.materials {
+            assert!(
+                embedded.materials[name].texture == material.texture,
+                "{}",
+                name
+            );
+        }
+    }
+}
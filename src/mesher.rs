@@ -0,0 +1,418 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+use crate::{
+    instance::Instance,
+    level::MapTiles,
+    model::{self, ModelVertex},
+};
+
+/// Occupancy of a stacked tile layer, one cell per cube.
+pub(crate) struct VoxelGrid {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    cells: Vec<bool>,
+}
+
+impl VoxelGrid {
+    pub(crate) fn from_tiles(tiles: &MapTiles) -> Self {
+        Self::from_layers([tiles])
+    }
+
+    /// Union of several stacked layers, sized to fit the largest.
+    pub(crate) fn from_layers<'a>(layers: impl IntoIterator<Item = &'a MapTiles> + Clone) -> Self {
+        let width = layers.clone().into_iter().map(|t| t.width).max().unwrap_or(0);
+        let depth = layers.clone().into_iter().map(|t| t.depth).max().unwrap_or(0);
+        let height = layers
+            .clone()
+            .into_iter()
+            .flat_map(|t| t.map.iter().copied())
+            .max()
+            .unwrap_or(0)
+            .max(0) as usize;
+        let mut cells = vec![false; width * height * depth];
+        for tiles in layers {
+            for z in 0..tiles.depth {
+                for x in 0..tiles.width {
+                    for y in 0..tiles.map[z * tiles.width + x].max(0) as usize {
+                        cells[x + width * (y + height * z)] = true;
+                    }
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            depth,
+            cells,
+        }
+    }
+
+    fn dims(&self) -> [usize; 3] {
+        [self.width, self.height, self.depth]
+    }
+
+    /// Cells outside the grid count as empty, so the outer shell of the level is kept.
+    pub(crate) fn is_solid(&self, x: i32, y: i32, z: i32) -> bool {
+        if x < 0 || y < 0 || z < 0 {
+            return false;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.width || y >= self.height || z >= self.depth {
+            return false;
+        }
+        self.cells[x + self.width * (y + self.height * z)]
+    }
+
+    /// Minecraft-style occlusion of one corner of a face, from 0 (darkest) to 3.
+    ///
+    /// `front` is the empty cell the face looks into and `du`/`dv` step from it
+    /// towards the corner along the face's `u` and `v` axes.
+    pub(crate) fn corner_ao(&self, front: [i32; 3], u: usize, v: usize, du: i32, dv: i32) -> u8 {
+        let solid = |su: i32, sv: i32| {
+            let mut cell = front;
+            cell[u] += su;
+            cell[v] += sv;
+            self.is_solid(cell[0], cell[1], cell[2])
+        };
+        let (side1, side2, corner) = (solid(du, 0), solid(0, dv), solid(du, dv));
+        if side1 && side2 {
+            0
+        } else {
+            3 - side1 as u8 - side2 as u8 - corner as u8
+        }
+    }
+}
+
+/// Vertex brightness for each occlusion level.
+const AO_CURVE: [f32; 4] = [0.45, 0.65, 0.82, 1.0];
+/// Corners of a face in `push_quad` order, as steps along u and v.
+const FACE_CORNERS: [(i32, i32); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+pub(crate) struct LevelMesh {
+    pub vertexes: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl LevelMesh {
+    pub(crate) fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+/// Copies a primitive into world space once per instance, for layers that
+/// need every surface to be unique.
+pub(crate) fn flatten_instances(
+    vertexes: &[ModelVertex],
+    indices: &[u16],
+    instances: &[Instance],
+) -> LevelMesh {
+    let mut mesh = LevelMesh {
+        vertexes: Vec::with_capacity(vertexes.len() * instances.len()),
+        indices: Vec::with_capacity(indices.len() * instances.len()),
+    };
+    for instance in instances {
+        let transform =
+            Matrix4::from_translation(instance.position) * Matrix4::from(instance.rotation);
+        let base = mesh.vertexes.len() as u32;
+        mesh.vertexes.extend(vertexes.iter().map(|vertex| {
+            let position = transform.transform_point(Point3::from(vertex.position));
+            let normal = transform.transform_vector(Vector3::from(vertex.normal));
+            let [tx, ty, tz, handedness] = vertex.tangent;
+            let tangent = transform.transform_vector(Vector3::new(tx, ty, tz));
+            ModelVertex {
+                position: position.into(),
+                normal: normal.into(),
+                tangent: [tangent.x, tangent.y, tangent.z, handedness],
+                ..*vertex
+            }
+        }));
+        mesh.indices
+            .extend(indices.iter().map(|&index| base + index as u32));
+    }
+    mesh
+}
+
+/// Merges a stacked wall layer into a single mesh.
+///
+/// Faces shared by two solid cells are dropped, and the remaining coplanar faces are
+/// merged into rectangles with a greedy sweep. Texture coordinates are expressed in
+/// cell units so a repeating sampler tiles the texture exactly like per-cube faces.
+/// With `occluders`, each corner gets ambient occlusion and only faces whose corners
+/// match are merged.
+pub(crate) fn mesh_tiles(
+    tiles: &MapTiles,
+    occluders: Option<&VoxelGrid>,
+    width: f32,
+    height: f32,
+    depth: f32,
+) -> LevelMesh {
+    let grid = VoxelGrid::from_tiles(tiles);
+    let half = [width, height, depth];
+    let dims = grid.dims();
+
+    let mut mesh = LevelMesh {
+        vertexes: Vec::new(),
+        indices: Vec::new(),
+    };
+
+    for axis in 0..3 {
+        let u = (axis + 1) % 3;
+        let v = (axis + 2) % 3;
+        for dir in [1i32, -1] {
+            // Occlusion of each exposed face's corners, None where there's no face.
+            let mut mask: Vec<Option<[u8; 4]>> = vec![None; dims[u] * dims[v]];
+            for slice in 0..dims[axis] {
+                for j in 0..dims[v] {
+                    for i in 0..dims[u] {
+                        let mut cell = [0i32; 3];
+                        cell[axis] = slice as i32;
+                        cell[u] = i as i32;
+                        cell[v] = j as i32;
+                        let mut neighbour = cell;
+                        neighbour[axis] += dir;
+                        let exposed = grid.is_solid(cell[0], cell[1], cell[2])
+                            && !grid.is_solid(neighbour[0], neighbour[1], neighbour[2]);
+                        mask[i + j * dims[u]] = exposed.then(|| match occluders {
+                            Some(occluders) => FACE_CORNERS
+                                .map(|(du, dv)| occluders.corner_ao(neighbour, u, v, du, dv)),
+                            None => [3; 4],
+                        });
+                    }
+                }
+
+                let boundary = if dir > 0 { slice + 1 } else { slice };
+                for j in 0..dims[v] {
+                    let mut i = 0;
+                    while i < dims[u] {
+                        let Some(ao) = mask[i + j * dims[u]] else {
+                            i += 1;
+                            continue;
+                        };
+                        let mut w = 1;
+                        while i + w < dims[u] && mask[i + w + j * dims[u]] == Some(ao) {
+                            w += 1;
+                        }
+                        let mut h = 1;
+                        'grow: while j + h < dims[v] {
+                            for k in 0..w {
+                                if mask[i + k + (j + h) * dims[u]] != Some(ao) {
+                                    break 'grow;
+                                }
+                            }
+                            h += 1;
+                        }
+                        for dj in 0..h {
+                            for di in 0..w {
+                                mask[i + di + (j + dj) * dims[u]] = None;
+                            }
+                        }
+
+                        let mut origin = [0usize; 3];
+                        origin[axis] = boundary;
+                        origin[u] = i;
+                        origin[v] = j;
+                        push_quad(&mut mesh, origin, axis, dir, [w, h], half, ao);
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+
+    model::compute_tangents(&mut mesh.vertexes, &mesh.indices);
+    mesh
+}
+
+fn push_quad(
+    mesh: &mut LevelMesh,
+    origin: [usize; 3],
+    axis: usize,
+    dir: i32,
+    [w, h]: [usize; 2],
+    half: [f32; 3],
+    mut ao: [u8; 4],
+) {
+    let u = (axis + 1) % 3;
+    let v = (axis + 2) % 3;
+
+    let corner = |du: usize, dv: usize| {
+        let mut c = [origin[0] as f32, origin[1] as f32, origin[2] as f32];
+        c[u] += du as f32;
+        c[v] += dv as f32;
+        c
+    };
+    // (e_u x e_v) points along +axis, so this order is counter-clockwise seen from +axis.
+    let mut corners = [corner(0, 0), corner(w, 0), corner(w, h), corner(0, h)];
+    if dir < 0 {
+        corners.swap(1, 3);
+        ao.swap(1, 3);
+    }
+
+    let mut normal = [0.0; 3];
+    normal[axis] = dir as f32;
+
+    let base = mesh.vertexes.len() as u32;
+    for (c, ao) in corners.into_iter().zip(ao) {
+        mesh.vertexes.push(ModelVertex {
+            position: [
+                c[0] * 2.0 * half[0] - half[0],
+                c[1] * 2.0 * half[1] - half[1],
+                c[2] * 2.0 * half[2] - half[2],
+            ],
+            tex_coords: face_uv(axis, dir, c),
+            normal,
+            tangent: [0.0; 4],
+            lightmap_coords: [0.0; 2],
+            ao: AO_CURVE[ao as usize],
+        });
+    }
+    // Split along the brighter diagonal so the occlusion interpolates evenly.
+    if ao[0] as u32 + ao[2] as u32 >= ao[1] as u32 + ao[3] as u32 {
+        mesh.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+    } else {
+        mesh.indices
+            .extend_from_slice(&[base + 1, base + 2, base + 3, base + 3, base, base + 1]);
+    }
+}
+
+/// Occludes the corners of the axis-aligned, cell-sized faces of a world-space
+/// mesh, such as flattened floor tiles, against `occluders`. Other faces are left
+/// as they are.
+pub(crate) fn apply_ao(mesh: &mut LevelMesh, occluders: &VoxelGrid, half: [f32; 3]) {
+    // Positions in cell corner units, where cell `n` spans `n..n + 1`.
+    let to_grid = |p: [f32; 3]| [0, 1, 2].map(|k| (p[k] + half[k]) / (2.0 * half[k]));
+    for t in 0..mesh.indices.len() / 3 {
+        let tri = [0, 1, 2].map(|k| mesh.indices[t * 3 + k] as usize);
+        let [a, b, c] = tri.map(|i| Vector3::from(mesh.vertexes[i].position));
+        let normal = (b - a).cross(c - a);
+        let Some(axis) = (0..3).find(|&k| normal[k].abs() > 0.999 * normal.magnitude()) else {
+            continue;
+        };
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let dir = normal[axis].signum() as i32;
+        let centre = to_grid(((a + b + c) / 3.0).into());
+
+        let mut front = [0i32; 3];
+        let plane = centre[axis].round() as i32;
+        front[axis] = if dir > 0 { plane } else { plane - 1 };
+        front[u] = centre[u].floor() as i32;
+        front[v] = centre[v].floor() as i32;
+
+        for i in tri {
+            let corner = to_grid(mesh.vertexes[i].position);
+            let du = if corner[u] > centre[u] { 1 } else { -1 };
+            let dv = if corner[v] > centre[v] { 1 } else { -1 };
+            let ao = AO_CURVE[occluders.corner_ao(front, u, v, du, dv) as usize];
+            mesh.vertexes[i].ao = mesh.vertexes[i].ao.min(ao);
+        }
+    }
+}
+
+/// Matches the per-face texture orientation of `Cube`, in cell units.
+fn face_uv(axis: usize, dir: i32, c: [f32; 3]) -> [f32; 2] {
+    let [x, y, z] = c;
+    match (axis, dir > 0) {
+        (0, true) => [-z, -y],
+        (0, false) => [z, -y],
+        (1, true) => [x, z],
+        (1, false) => [-x, z],
+        (_, true) => [x, -y],
+        (_, false) => [-x, -y],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(map: Vec<i32>, width: usize) -> MapTiles {
+        let depth = map.len() / width;
+        MapTiles { map, width, depth }
+    }
+
+    fn quads(mesh: &LevelMesh) -> usize {
+        assert_eq!(mesh.vertexes.len() % 4, 0);
+        mesh.vertexes.len() / 4
+    }
+
+    /// Quads whose normal points along `normal`.
+    fn facing(mesh: &LevelMesh, normal: [f32; 3]) -> Vec<&[ModelVertex]> {
+        mesh.vertexes
+            .chunks_exact(4)
+            .filter(|quad| quad[0].normal == normal)
+            .collect()
+    }
+
+    #[test]
+    fn single_cube_has_six_faces() {
+        let mesh = mesh_tiles(&tiles(vec![1], 1), None, 1.0, 1.0, 1.0);
+        assert_eq!(quads(&mesh), 6);
+        assert_eq!(mesh.triangle_count(), 12);
+    }
+
+    #[test]
+    fn row_of_cubes_merges_into_a_box() {
+        let mesh = mesh_tiles(&tiles(vec![1, 1, 1], 3), None, 1.0, 1.0, 1.0);
+        assert_eq!(quads(&mesh), 6);
+        let top = facing(&mesh, [0.0, 1.0, 0.0]);
+        assert_eq!(top.len(), 1);
+        let xs: Vec<f32> = top[0].iter().map(|vertex| vertex.position[0]).collect();
+        let min = xs.iter().copied().fold(f32::MAX, f32::min);
+        let max = xs.iter().copied().fold(f32::MIN, f32::max);
+        assert_eq!((min, max), (-1.0, 5.0));
+    }
+
+    #[test]
+    fn stacked_column_merges_its_sides() {
+        let mesh = mesh_tiles(&tiles(vec![3], 1), None, 1.0, 1.0, 1.0);
+        assert_eq!(quads(&mesh), 6);
+    }
+
+    #[test]
+    fn l_shape_splits_where_it_must() {
+        let mesh = mesh_tiles(&tiles(vec![1, 1, 1, 0], 2), None, 1.0, 1.0, 1.0);
+        assert_eq!(facing(&mesh, [0.0, 1.0, 0.0]).len(), 2);
+        assert_eq!(facing(&mesh, [0.0, -1.0, 0.0]).len(), 2);
+        assert_eq!(facing(&mesh, [1.0, 0.0, 0.0]).len(), 2);
+        assert_eq!(facing(&mesh, [-1.0, 0.0, 0.0]).len(), 1);
+        assert_eq!(facing(&mesh, [0.0, 0.0, 1.0]).len(), 2);
+        assert_eq!(facing(&mesh, [0.0, 0.0, -1.0]).len(), 1);
+    }
+
+    #[test]
+    fn hidden_faces_are_dropped() {
+        let mesh = mesh_tiles(&tiles(vec![1; 9], 3), None, 1.0, 1.0, 1.0);
+        // The middle cube's faces all touch a neighbour or are merged away.
+        assert_eq!(quads(&mesh), 6);
+    }
+
+    #[test]
+    fn triangles_wind_towards_their_normal() {
+        let mesh = mesh_tiles(&tiles(vec![2, 1, 0, 1], 2), None, 1.0, 1.0, 1.0);
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| &mesh.vertexes[triangle[k] as usize]);
+            let [pa, pb, pc] = [a, b, c].map(|vertex| Vector3::from(vertex.position));
+            let winding = (pb - pa).cross(pc - pa);
+            assert!(winding.dot(Vector3::from(a.normal)) > 0.0);
+        }
+    }
+
+    #[test]
+    fn occlusion_stops_faces_merging() {
+        let floor = tiles(vec![1, 1, 1, 1], 4);
+        let wall = tiles(vec![2, 0, 0, 0], 4);
+        let occluders = VoxelGrid::from_layers([&floor, &wall]);
+        let plain = mesh_tiles(&floor, None, 1.0, 1.0, 1.0);
+        let occluded = mesh_tiles(&floor, Some(&occluders), 1.0, 1.0, 1.0);
+        assert_eq!(facing(&plain, [0.0, 1.0, 0.0]).len(), 1);
+        assert!(facing(&occluded, [0.0, 1.0, 0.0]).len() > 1);
+        let darkest = occluded
+            .vertexes
+            .iter()
+            .map(|vertex| vertex.ao)
+            .fold(1.0, f32::min);
+        assert!(darkest < 1.0);
+    }
+}
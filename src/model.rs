@@ -1,6 +1,8 @@
 use std::ops::Range;
 
-use crate::texture;
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+use crate::material::MaterialTextures;
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -12,6 +14,12 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    /// xyz is the direction of increasing u, w the handedness of the bitangent.
+    pub tangent: [f32; 4],
+    /// Second UV set, unique across the level, for baked lightmaps.
+    pub lightmap_coords: [f32; 2],
+    /// Ambient occlusion from neighbouring cells, 1 when nothing is in the way.
+    pub ao: f32,
 }
 
 impl Vertex for ModelVertex {
@@ -36,14 +44,99 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                // 5 to 8 are taken by the instance matrix.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
 
+/// Area-weighted vertex normals from the triangles that use each vertex. Faces that
+/// should stay sharp need their own vertices.
+pub fn compute_normals<I: Copy + Into<u32>>(vertexes: &mut [ModelVertex], indices: &[I]) {
+    let mut normals = vec![Vector3::zero(); vertexes.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0].into(), tri[1].into(), tri[2].into()].map(|i: u32| i as usize);
+        let pa = Vector3::from(vertexes[a].position);
+        let pb = Vector3::from(vertexes[b].position);
+        let pc = Vector3::from(vertexes[c].position);
+        let face = (pb - pa).cross(pc - pa);
+        for i in [a, b, c] {
+            normals[i] += face;
+        }
+    }
+    for (vertex, normal) in vertexes.iter_mut().zip(normals) {
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+}
+
+/// Per-vertex tangents from the texture coordinates, orthogonalised against the normal.
+pub fn compute_tangents<I: Copy + Into<u32>>(vertexes: &mut [ModelVertex], indices: &[I]) {
+    let mut tangents = vec![Vector3::zero(); vertexes.len()];
+    let mut bitangents = vec![Vector3::zero(); vertexes.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0].into(), tri[1].into(), tri[2].into()].map(|i: u32| i as usize);
+        let pa = Vector3::from(vertexes[a].position);
+        let e1 = Vector3::from(vertexes[b].position) - pa;
+        let e2 = Vector3::from(vertexes[c].position) - pa;
+        let ta = Vector2::from(vertexes[a].tex_coords);
+        let d1 = Vector2::from(vertexes[b].tex_coords) - ta;
+        let d2 = Vector2::from(vertexes[c].tex_coords) - ta;
+
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (e1 * d2.y - e2 * d1.y) * r;
+        let bitangent = (e2 * d1.x - e1 * d2.x) * r;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertexes.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            // No usable texture gradient, so pick any direction perpendicular to the normal.
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    pub textures: MaterialTextures,
     pub bind_group: wgpu::BindGroup,
 }
 
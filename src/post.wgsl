@@ -0,0 +1,229 @@
+// Full-screen passes run after the scene is rendered into the HDR target.
+// The bloom filters use bindings 0-2, the composite pass 3-8, FXAA 8-10 and
+// the MSAA depth resolve 11, so each pipeline's bind group only has the
+// resources it reads.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle that covers the screen.
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index & 2u) * 2 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// Bloom
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+struct Filter {
+    // Blur step in uv
+    direction: vec2<f32>,
+    threshold: f32,
+}
+@group(0) @binding(2)
+var<uniform> filter_params: Filter;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Downsamples to half size and keeps what's brighter than the threshold.
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 0.5 / vec2<f32>(textureDimensions(t_source));
+    let color = (textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(-texel.x, -texel.y), 0.0).rgb
+        + textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(texel.x, -texel.y), 0.0).rgb
+        + textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(-texel.x, texel.y), 0.0).rgb
+        + textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(texel.x, texel.y), 0.0).rgb) * 0.25;
+    let brightness = luminance(color);
+    let contribution = max(brightness - filter_params.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// One direction of a 9-tap gaussian.
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    var weights = array<f32, 5>(0.2270270270, 0.1945945946, 0.1216216216, 0.0540540541, 0.0162162162);
+    var color = textureSampleLevel(t_source, s_source, in.uv, 0.0).rgb * weights[0];
+    for (var i = 1; i < 5; i = i + 1) {
+        let offset = filter_params.direction * f32(i);
+        color = color + textureSampleLevel(t_source, s_source, in.uv + offset, 0.0).rgb * weights[i];
+        color = color + textureSampleLevel(t_source, s_source, in.uv - offset, 0.0).rgb * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Composite
+
+@group(0) @binding(3)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(4)
+var s_linear: sampler;
+@group(0) @binding(5)
+var t_depth: texture_depth_2d;
+@group(0) @binding(6)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(7)
+var t_lut: texture_3d<f32>;
+
+struct Post {
+    // See `PostSettings::flags`
+    flags: u32,
+    exposure: f32,
+    bloom_intensity: f32,
+    vignette_strength: f32,
+    // rgb = colour, a = density
+    fog: vec4<f32>,
+    // near, far, LUT size
+    params: vec4<f32>,
+}
+@group(0) @binding(8)
+var<uniform> post: Post;
+
+let TONEMAPPING: u32 = 1u;
+let BLOOM: u32 = 2u;
+let EXPOSURE: u32 = 4u;
+let VIGNETTE: u32 = 8u;
+let FOG: u32 = 16u;
+let COLOR_GRADING: u32 = 32u;
+let ENCODE_SRGB: u32 = 64u;
+let DECODE_SRGB: u32 = 128u;
+
+fn enabled(flag: u32) -> bool {
+    return (post.flags & flag) != 0u;
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSampleLevel(t_hdr, s_linear, in.uv, 0.0).rgb;
+
+    if (enabled(FOG)) {
+        let size = vec2<f32>(textureDimensions(t_depth));
+        let depth = textureLoad(t_depth, vec2<i32>(in.uv * size), 0);
+        let near = post.params.x;
+        let far = post.params.y;
+        // Undo the projection's depth mapping to get view distance.
+        let ndc = depth * 2.0 - 1.0;
+        let distance = 2.0 * near * far / (far + near - ndc * (far - near));
+        let amount = 1.0 - exp(-pow(post.fog.a * distance, 2.0));
+        color = mix(color, post.fog.rgb, amount);
+    }
+    if (enabled(BLOOM)) {
+        color = color + textureSampleLevel(t_bloom, s_linear, in.uv, 0.0).rgb * post.bloom_intensity;
+    }
+    if (enabled(EXPOSURE)) {
+        color = color * post.exposure;
+    }
+    if (enabled(TONEMAPPING)) {
+        color = aces(color);
+    } else {
+        color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    if (enabled(COLOR_GRADING)) {
+        // The LUT is authored on display values.
+        let size = post.params.z;
+        let coords = linear_to_srgb(color) * ((size - 1.0) / size) + 0.5 / size;
+        color = srgb_to_linear(textureSampleLevel(t_lut, s_linear, coords, 0.0).rgb);
+    }
+    if (enabled(VIGNETTE)) {
+        let distance = length(in.uv - vec2<f32>(0.5)) * 1.414;
+        color = color * (1.0 - post.vignette_strength * smoothstep(0.4, 1.0, distance));
+    }
+    if (enabled(ENCODE_SRGB)) {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// FXAA
+
+@group(0) @binding(9)
+var t_ldr: texture_2d<f32>;
+@group(0) @binding(10)
+var s_ldr: sampler;
+
+let FXAA_REDUCE_MIN: f32 = 0.0078125;
+let FXAA_REDUCE_MUL: f32 = 0.125;
+let FXAA_SPAN_MAX: f32 = 8.0;
+
+fn ldr_luma(uv: vec2<f32>) -> f32 {
+    return luminance(textureSampleLevel(t_ldr, s_ldr, uv, 0.0).rgb);
+}
+
+// Blurs along edges found from the luma of the tonemapped image.
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_ldr));
+    let luma_nw = ldr_luma(in.uv + vec2<f32>(-texel.x, -texel.y));
+    let luma_ne = ldr_luma(in.uv + vec2<f32>(texel.x, -texel.y));
+    let luma_sw = ldr_luma(in.uv + vec2<f32>(-texel.x, texel.y));
+    let luma_se = ldr_luma(in.uv + vec2<f32>(texel.x, texel.y));
+    let luma_m = ldr_luma(in.uv);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let inner = 0.5 * (textureSampleLevel(t_ldr, s_ldr, in.uv - direction / 6.0, 0.0).rgb
+        + textureSampleLevel(t_ldr, s_ldr, in.uv + direction / 6.0, 0.0).rgb);
+    let outer = inner * 0.5 + 0.25 * (textureSampleLevel(t_ldr, s_ldr, in.uv - direction * 0.5, 0.0).rgb
+        + textureSampleLevel(t_ldr, s_ldr, in.uv + direction * 0.5, 0.0).rgb);
+    let luma_outer = luminance(outer);
+    // The wider blend crossed another edge, fall back to the narrow one.
+    var color = select(outer, inner, luma_outer < luma_min || luma_outer > luma_max);
+
+    if (enabled(DECODE_SRGB)) {
+        color = srgb_to_linear(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// MSAA depth resolve
+
+@group(0) @binding(11)
+var t_depth_multisampled: texture_depth_multisampled_2d;
+
+// Keeps the nearest sample so fog follows the closest surface in the pixel.
+@fragment
+fn fs_resolve_depth(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    let coords = vec2<i32>(in.clip_position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < textureNumSamples(t_depth_multisampled); i = i + 1) {
+        depth = min(depth, textureLoad(t_depth_multisampled, coords, i));
+    }
+    return depth;
+}
@@ -0,0 +1,727 @@
+use wgpu::util::DeviceExt;
+use winit::event::VirtualKeyCode;
+
+use crate::{camera::Projection, shaders, texture};
+
+/// The scene is rendered into this before the post chain maps it to the
+/// swapchain.
+pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Gamma encoded composite that FXAA reads.
+const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Only light brighter than this feeds the bloom.
+const BLOOM_THRESHOLD: f32 = 1.0;
+const LUT_SIZE: u32 = 16;
+/// How far `[` and `]` move the exposure, in stops.
+const EXPOSURE_STEP: f32 = 0.5;
+
+/// Flags shared with `post.wgsl`.
+const TONEMAPPING: u32 = 1;
+const BLOOM: u32 = 2;
+const EXPOSURE: u32 = 4;
+const VIGNETTE: u32 = 8;
+const FOG: u32 = 16;
+const COLOR_GRADING: u32 = 32;
+const ENCODE_SRGB: u32 = 64;
+const DECODE_SRGB: u32 = 128;
+
+/// Colour grades the LUT pass can apply, each baked into a 3D table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Grade {
+    Warm,
+    Cool,
+    Bleach,
+}
+
+impl Grade {
+    const ALL: [Grade; 3] = [Grade::Warm, Grade::Cool, Grade::Bleach];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&grade| grade == self).unwrap()
+    }
+
+    fn next(self) -> Self {
+        Self::ALL[(self.index() + 1) % Self::ALL.len()]
+    }
+
+    /// Grades a display colour.
+    fn apply(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let (color, contrast) = match self {
+            Grade::Warm => ([r * 1.08 + 0.02, g + 0.01, b * 0.88], 0.3),
+            Grade::Cool => ([r * 0.92, g + 0.02, b * 1.08 + 0.03], 0.2),
+            Grade::Bleach => {
+                let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                ([(r + luma) / 2.0, (g + luma) / 2.0, (b + luma) / 2.0], 0.6)
+            }
+        };
+        color.map(|c| {
+            let c = c.clamp(0.0, 1.0);
+            c + contrast * (c * c * (3.0 - 2.0 * c) - c)
+        })
+    }
+
+    /// The grade as a LUT strip, see `Texture::from_lut_strip`.
+    fn strip(self) -> image::RgbaImage {
+        let max = (LUT_SIZE - 1) as f32;
+        image::RgbaImage::from_fn(LUT_SIZE * LUT_SIZE, LUT_SIZE, |x, y| {
+            let input = [
+                (x % LUT_SIZE) as f32 / max,
+                y as f32 / max,
+                (x / LUT_SIZE) as f32 / max,
+            ];
+            let [r, g, b] = self.apply(input).map(|c| (c * 255.0).round() as u8);
+            image::Rgba([r, g, b, 255])
+        })
+    }
+}
+
+/// Which effects run and how strongly. Each effect can be toggled at runtime,
+/// see `PostProcess::process_key`.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PostSettings {
+    pub tonemapping: bool,
+    pub bloom: bool,
+    pub exposure: bool,
+    pub vignette: bool,
+    pub fog: bool,
+    pub color_grading: bool,
+    /// Exposure in stops, so 1.0 doubles the brightness.
+    pub exposure_stops: f32,
+    pub bloom_intensity: f32,
+    pub vignette_strength: f32,
+    pub fog_color: [f32; 3],
+    pub fog_density: f32,
+    pub grade: Grade,
+    /// Set from the anti-aliasing mode rather than toggled on its own.
+    pub fxaa: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            tonemapping: true,
+            bloom: true,
+            exposure: true,
+            vignette: true,
+            fog: true,
+            color_grading: true,
+            exposure_stops: 0.0,
+            bloom_intensity: 0.6,
+            vignette_strength: 0.35,
+            // Matches the clear colour so geometry fades into the sky.
+            fog_color: [0.1, 0.2, 0.3],
+            fog_density: 0.025,
+            grade: Grade::Warm,
+            fxaa: false,
+        }
+    }
+}
+
+impl PostSettings {
+    fn flags(&self) -> u32 {
+        [
+            (self.tonemapping, TONEMAPPING),
+            (self.bloom, BLOOM),
+            (self.exposure, EXPOSURE),
+            (self.vignette, VIGNETTE),
+            (self.fog, FOG),
+            (self.color_grading, COLOR_GRADING),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |flags, (_, flag)| flags | flag)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterUniform {
+    /// Blur step in uv.
+    direction: [f32; 2],
+    threshold: f32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    flags: u32,
+    exposure: f32,
+    bloom_intensity: f32,
+    vignette_strength: f32,
+    /// rgb = colour, a = density
+    fog: [f32; 4],
+    /// near, far, LUT size
+    params: [f32; 4],
+}
+
+/// Everything that doesn't depend on the window size.
+struct Resources {
+    filter_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    fxaa_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// Bright pass, then the horizontal and vertical blurs.
+    filter_buffers: [wgpu::Buffer; 3],
+    uniform_buffer: wgpu::Buffer,
+    /// One per `Grade`.
+    luts: Vec<texture::Texture>,
+}
+
+/// Window sized targets and the bind groups that read them.
+struct Targets {
+    hdr: texture::Texture,
+    /// Half size ping-pong pair, the finished bloom ends up in the first.
+    bloom: [texture::Texture; 2],
+    filter_bind_groups: [wgpu::BindGroup; 3],
+    /// One per `Grade`.
+    composite_bind_groups: Vec<wgpu::BindGroup>,
+    /// Where the composite goes when FXAA runs after it.
+    ldr: texture::Texture,
+    fxaa_bind_group: wgpu::BindGroup,
+}
+
+/// Renders the scene into an HDR target, then runs bloom, fog, exposure,
+/// tonemapping, colour grading, vignetting and optionally FXAA on the way to
+/// the swapchain.
+pub(crate) struct PostProcess {
+    pub settings: PostSettings,
+    resources: Resources,
+    targets: Targets,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_ldr_pipeline: wgpu::RenderPipeline,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    surface_format: wgpu::TextureFormat,
+    /// Whether the swapchain encodes to sRGB itself, otherwise the last pass
+    /// does it by hand.
+    srgb_surface: bool,
+}
+
+impl PostProcess {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) -> anyhow::Result<Self> {
+        let texture_entry = |binding, sample_type, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let color = wgpu::TextureSampleType::Float { filterable: true };
+        let filter_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0, color, wgpu::TextureViewDimension::D2),
+                    sampler_entry(1),
+                    uniform_entry(2),
+                ],
+                label: Some("filter_bind_group_layout"),
+            });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(3, color, wgpu::TextureViewDimension::D2),
+                    sampler_entry(4),
+                    texture_entry(
+                        5,
+                        wgpu::TextureSampleType::Depth,
+                        wgpu::TextureViewDimension::D2,
+                    ),
+                    texture_entry(6, color, wgpu::TextureViewDimension::D2),
+                    texture_entry(7, color, wgpu::TextureViewDimension::D3),
+                    uniform_entry(8),
+                ],
+                label: Some("composite_bind_group_layout"),
+            });
+        let fxaa_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry(8),
+                    texture_entry(9, color, wgpu::TextureViewDimension::D2),
+                    sampler_entry(10),
+                ],
+                label: Some("fxaa_bind_group_layout"),
+            });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let filter_buffers = [
+            "Bloom Bright Buffer",
+            "Bloom Blur H Buffer",
+            "Bloom Blur V Buffer",
+        ]
+        .map(|label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&[FilterUniform {
+                    direction: [0.0; 2],
+                    threshold: BLOOM_THRESHOLD,
+                    _padding: 0.0,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Buffer"),
+            contents: bytemuck::cast_slice(&[PostUniform {
+                flags: 0,
+                exposure: 1.0,
+                bloom_intensity: 0.0,
+                vignette_strength: 0.0,
+                fog: [0.0; 4],
+                params: [0.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let luts = Grade::ALL
+            .iter()
+            .map(|grade| {
+                texture::Texture::from_lut_strip(device, queue, &grade.strip(), "grade_lut")
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let resources = Resources {
+            filter_bind_group_layout,
+            composite_bind_group_layout,
+            fxaa_bind_group_layout,
+            sampler,
+            filter_buffers,
+            uniform_buffer,
+            luts,
+        };
+        let targets = resources.targets(device, queue, config, depth_texture);
+
+        let [bright_pipeline, blur_pipeline, composite_pipeline, composite_ldr_pipeline, fxaa_pipeline] =
+            resources.pipelines(device, config.format)?;
+
+        Ok(Self {
+            settings: PostSettings::default(),
+            resources,
+            targets,
+            bright_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            composite_ldr_pipeline,
+            fxaa_pipeline,
+            surface_format: config.format,
+            srgb_surface: config.format.describe().srgb,
+        })
+    }
+
+    /// Rebuilds the bloom, composite and FXAA pipelines from the shader on
+    /// disk, keeping the old ones if the new shader or pipelines don't
+    /// validate.
+    pub(crate) fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        [
+            self.bright_pipeline,
+            self.blur_pipeline,
+            self.composite_pipeline,
+            self.composite_ldr_pipeline,
+            self.fxaa_pipeline,
+        ] = shaders::catch_validation_errors(device, || {
+            self.resources.pipelines(device, self.surface_format)
+        })?;
+        Ok(())
+    }
+
+    /// The view the scene should be rendered into.
+    pub(crate) fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.targets.hdr.view
+    }
+
+    /// Recreates the targets to match the surface and its new depth texture.
+    pub(crate) fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) {
+        self.targets = self.resources.targets(device, queue, config, depth_texture);
+    }
+
+    /// F1-F6 toggle tonemapping, bloom, exposure, vignette, fog and colour
+    /// grading, F7 switches grade and `[`/`]` change the exposure. Returns
+    /// whether the key was used.
+    pub(crate) fn process_key(&mut self, key: VirtualKeyCode) -> bool {
+        let settings = &mut self.settings;
+        let (name, enabled) = match key {
+            VirtualKeyCode::F1 => ("tonemapping", &mut settings.tonemapping),
+            VirtualKeyCode::F2 => ("bloom", &mut settings.bloom),
+            VirtualKeyCode::F3 => ("exposure", &mut settings.exposure),
+            VirtualKeyCode::F4 => ("vignette", &mut settings.vignette),
+            VirtualKeyCode::F5 => ("fog", &mut settings.fog),
+            VirtualKeyCode::F6 => ("colour grading", &mut settings.color_grading),
+            VirtualKeyCode::F7 => {
+                settings.grade = settings.grade.next();
+                log::info!("colour grade {:?}", settings.grade);
+                return true;
+            }
+            VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                let step = if key == VirtualKeyCode::LBracket {
+                    -EXPOSURE_STEP
+                } else {
+                    EXPOSURE_STEP
+                };
+                settings.exposure_stops += step;
+                log::info!("exposure {:+.1} stops", settings.exposure_stops);
+                return true;
+            }
+            _ => return false,
+        };
+        *enabled = !*enabled;
+        log::info!("{} {}", name, if *enabled { "on" } else { "off" });
+        true
+    }
+
+    pub(crate) fn update(&self, queue: &wgpu::Queue, projection: &Projection) {
+        let settings = &self.settings;
+        let mut flags = settings.flags();
+        // FXAA wants gamma encoded input, so it decodes again if the surface
+        // expects linear values.
+        if settings.fxaa || !self.srgb_surface {
+            flags |= ENCODE_SRGB;
+        }
+        if settings.fxaa && self.srgb_surface {
+            flags |= DECODE_SRGB;
+        }
+        let [r, g, b] = settings.fog_color;
+        let uniform = PostUniform {
+            flags,
+            exposure: settings.exposure_stops.exp2(),
+            bloom_intensity: settings.bloom_intensity,
+            vignette_strength: settings.vignette_strength,
+            fog: [r, g, b, settings.fog_density],
+            params: [projection.znear, projection.zfar, LUT_SIZE as f32, 0.0],
+        };
+        queue.write_buffer(
+            &self.resources.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+    }
+
+    /// Runs the chain on the HDR target and writes the result to `output`.
+    pub(crate) fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let targets = &self.targets;
+        if self.settings.bloom {
+            let passes = [
+                (
+                    "Bloom Bright Pass",
+                    &self.bright_pipeline,
+                    &targets.bloom[0],
+                ),
+                ("Bloom Blur H Pass", &self.blur_pipeline, &targets.bloom[1]),
+                ("Bloom Blur V Pass", &self.blur_pipeline, &targets.bloom[0]),
+            ];
+            for ((label, pipeline, target), bind_group) in
+                passes.into_iter().zip(&targets.filter_bind_groups)
+            {
+                fullscreen_pass(encoder, label, pipeline, bind_group, &target.view);
+            }
+        }
+        let composite_bind_group = &targets.composite_bind_groups[self.settings.grade.index()];
+        if self.settings.fxaa {
+            fullscreen_pass(
+                encoder,
+                "Composite Pass",
+                &self.composite_ldr_pipeline,
+                composite_bind_group,
+                &targets.ldr.view,
+            );
+            fullscreen_pass(
+                encoder,
+                "FXAA Pass",
+                &self.fxaa_pipeline,
+                &targets.fxaa_bind_group,
+                output,
+            );
+        } else {
+            fullscreen_pass(
+                encoder,
+                "Composite Pass",
+                &self.composite_pipeline,
+                composite_bind_group,
+                output,
+            );
+        }
+    }
+}
+
+impl Resources {
+    /// The bright pass, blur, composite to the surface and to `LDR_FORMAT`,
+    /// and FXAA pipelines.
+    fn pipelines(
+        &self,
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+    ) -> anyhow::Result<[wgpu::RenderPipeline; 5]> {
+        let shader = shaders::create_module(device, "post.wgsl", &[], "Post Shader")?;
+        let filter_layout = [&self.filter_bind_group_layout];
+        let bright_pipeline =
+            fullscreen_pipeline(device, &shader, &filter_layout, "fs_bright", HDR_FORMAT);
+        let blur_pipeline =
+            fullscreen_pipeline(device, &shader, &filter_layout, "fs_blur", HDR_FORMAT);
+        let composite_layout = [&self.composite_bind_group_layout];
+        let composite_pipeline = fullscreen_pipeline(
+            device,
+            &shader,
+            &composite_layout,
+            "fs_composite",
+            surface_format,
+        );
+        let composite_ldr_pipeline = fullscreen_pipeline(
+            device,
+            &shader,
+            &composite_layout,
+            "fs_composite",
+            LDR_FORMAT,
+        );
+        let fxaa_pipeline = fullscreen_pipeline(
+            device,
+            &shader,
+            &[&self.fxaa_bind_group_layout],
+            "fs_fxaa",
+            surface_format,
+        );
+        Ok([
+            bright_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            composite_ldr_pipeline,
+            fxaa_pipeline,
+        ])
+    }
+
+    fn targets(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) -> Targets {
+        let hdr = texture::Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            HDR_FORMAT,
+            1,
+            "hdr_target",
+        );
+        let (bloom_width, bloom_height) = ((config.width / 2).max(1), (config.height / 2).max(1));
+        let bloom = ["bloom_target_a", "bloom_target_b"].map(|label| {
+            texture::Texture::create_render_target(
+                device,
+                bloom_width,
+                bloom_height,
+                HDR_FORMAT,
+                1,
+                label,
+            )
+        });
+
+        let directions = [
+            [0.0, 0.0],
+            [1.0 / bloom_width as f32, 0.0],
+            [0.0, 1.0 / bloom_height as f32],
+        ];
+        for (buffer, direction) in self.filter_buffers.iter().zip(directions) {
+            let uniform = FilterUniform {
+                direction,
+                threshold: BLOOM_THRESHOLD,
+                _padding: 0.0,
+            };
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+
+        let filter_bind_group = |source: &texture::Texture, buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.filter_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("filter_bind_group"),
+            })
+        };
+        let filter_bind_groups = [
+            filter_bind_group(&hdr, &self.filter_buffers[0]),
+            filter_bind_group(&bloom[0], &self.filter_buffers[1]),
+            filter_bind_group(&bloom[1], &self.filter_buffers[2]),
+        ];
+
+        let composite_bind_groups = self
+            .luts
+            .iter()
+            .map(|lut| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.composite_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(&hdr.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 5,
+                            resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 6,
+                            resource: wgpu::BindingResource::TextureView(&bloom[0].view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 7,
+                            resource: wgpu::BindingResource::TextureView(&lut.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 8,
+                            resource: self.uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("composite_bind_group"),
+                })
+            })
+            .collect();
+
+        let ldr = texture::Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            LDR_FORMAT,
+            1,
+            "ldr_target",
+        );
+        let fxaa_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.fxaa_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&ldr.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("fxaa_bind_group"),
+        });
+
+        Targets {
+            hdr,
+            bloom,
+            filter_bind_groups,
+            composite_bind_groups,
+            ldr,
+            fxaa_bind_group,
+        }
+    }
+}
+
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    target: &wgpu::TextureView,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Post Pipeline Layout"),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
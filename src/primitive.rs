@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use cgmath::Vector3;
+
+use crate::{
+    cube::Cube,
+    cylinder::Cylinder,
+    floor::Floor,
+    model::ModelVertex,
+    slope::{Direction, Slope, SlopeOrientation},
+    sprite::Sprite,
+    stairs::Stairs,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Bounds {
+    /// Box centred on the origin.
+    pub(crate) fn centred(width: f32, height: f32, depth: f32) -> Self {
+        Self {
+            min: Vector3::new(-width, -height, -depth),
+            max: Vector3::new(width, height, depth),
+        }
+    }
+
+    pub(crate) fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    pub(crate) fn centre(&self) -> Vector3<f32> {
+        (self.max + self.min) / 2.0
+    }
+
+    /// Smallest box holding every point, empty (min above max) if there are none.
+    pub(crate) fn enclosing(points: impl IntoIterator<Item = Vector3<f32>>) -> Self {
+        points.into_iter().fold(
+            Self {
+                min: Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+                max: Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+            },
+            |bounds, point| Self {
+                min: Vector3::new(
+                    bounds.min.x.min(point.x),
+                    bounds.min.y.min(point.y),
+                    bounds.min.z.min(point.z),
+                ),
+                max: Vector3::new(
+                    bounds.max.x.max(point.x),
+                    bounds.max.y.max(point.y),
+                    bounds.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+}
+
+/// How the character collides with a primitive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum CollisionShape {
+    /// Blocks movement on every side of its bounds.
+    Solid,
+    /// Flat ground at the bottom of its bounds.
+    Floor,
+    /// Ground that follows `Primitive::surface_height`.
+    Ramp,
+    /// Ground that rises in steps, climbed if they are low enough.
+    Steps,
+    None,
+}
+
+pub(crate) trait Primitive {
+    fn vertexes(&self) -> &[ModelVertex];
+    fn indices(&self) -> &[u16];
+    /// Extents around the primitive's centre.
+    fn bounds(&self) -> Bounds;
+    fn collision_shape(&self) -> CollisionShape;
+
+    /// Height of the walkable surface above the centre, for a point given relative to
+    /// the centre.
+    fn surface_height(&self, _x: f32, _z: f32) -> f32 {
+        self.bounds().max.y
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PrimitiveParams {
+    pub width: f32,
+    pub height: f32,
+    pub depth: f32,
+    pub orientation: SlopeOrientation,
+    pub steps: u32,
+    pub segments: u32,
+}
+
+impl Default for PrimitiveParams {
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            height: 1.0,
+            depth: 1.0,
+            orientation: SlopeOrientation::Ramp(Direction::Forward),
+            steps: 4,
+            segments: 12,
+        }
+    }
+}
+
+pub(crate) type PrimitiveBuilder = fn(&PrimitiveParams) -> anyhow::Result<Box<dyn Primitive>>;
+
+/// Named primitive constructors, so level data can refer to shapes by name.
+pub(crate) struct PrimitiveRegistry {
+    builders: HashMap<&'static str, PrimitiveBuilder>,
+}
+
+impl PrimitiveRegistry {
+    pub(crate) fn new() -> Self {
+        Self {
+            builders: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("cube", |p| Ok(Box::new(Cube::new(p.width, p.height, p.depth))));
+        registry.register("floor", |p| Ok(Box::new(Floor::new(p.width, p.height, p.depth))));
+        registry.register("sprite", |p| Ok(Box::new(Sprite::new(p.width, p.height))));
+        registry.register("slope", |p| {
+            Ok(Box::new(Slope::new(p.width, p.height, p.depth, p.orientation)))
+        });
+        registry.register("stairs", |p| {
+            let direction = match p.orientation {
+                SlopeOrientation::Ramp(direction) => direction,
+                orientation => bail!("stairs can't have a {:?} orientation", orientation),
+            };
+            Ok(Box::new(Stairs::new(p.width, p.height, p.depth, p.steps, direction)))
+        });
+        registry.register("cylinder", |p| {
+            Ok(Box::new(Cylinder::new(p.width, p.height, p.segments)))
+        });
+        registry
+    }
+
+    pub(crate) fn register(&mut self, name: &'static str, builder: PrimitiveBuilder) {
+        self.builders.insert(name, builder);
+    }
+
+    pub(crate) fn build(
+        &self,
+        name: &str,
+        params: &PrimitiveParams,
+    ) -> anyhow::Result<Box<dyn Primitive>> {
+        match self.builders.get(name) {
+            Some(builder) => builder(params),
+            None => bail!("unknown primitive {:?}", name),
+        }
+    }
+}
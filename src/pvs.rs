@@ -0,0 +1,480 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+
+use crate::{
+    level::{Level, CELL_SIZE},
+    primitive::{CollisionShape, PrimitiveRegistry},
+};
+
+/// Where the `pvs` command writes visibility sets and the renderer looks for
+/// them.
+pub(crate) const PVS_DIR: &str = "pvs";
+const MAGIC: &[u8; 4] = b"PVS2";
+/// Magic, width, depth and the cells' hash.
+const HEADER_BYTES: usize = 20;
+/// Columns with at least this many solid cells from the ground block sight
+/// from eyes below their top.
+const OCCLUDER_CELLS: i32 = 2;
+/// Height of the eye standing on the floor, where the camera starts.
+const GROUND_EYE: f32 = 1.0;
+/// How far above a ramp or a tread the camera rests, as in collision detection.
+const EYE_HEIGHT: f32 = 1.3;
+/// Rays are cast between a grid of this many by this many points in each cell.
+const SAMPLES_PER_SIDE: usize = 4;
+
+/// Path of the visibility set for a level.
+fn pvs_path(dir: &Path, level: &str) -> PathBuf {
+    dir.join(format!("{}.pvs", level))
+}
+
+/// For each open cell of a level's grid, the cells that can possibly be seen
+/// from anywhere inside it. Walls count as seen when the open space in front
+/// of them is.
+pub(crate) struct Pvs {
+    width: usize,
+    depth: usize,
+    /// Of the cells it was computed for, to notice saved sets that are out of
+    /// date with the level.
+    hash: u64,
+    open: Vec<bool>,
+    /// One row of `row_bytes` bits per cell.
+    visible: Vec<u8>,
+}
+
+/// Summary printed by the `pvs` command.
+pub(crate) struct PvsStats {
+    pub cells: usize,
+    pub open: usize,
+    pub min_visible: usize,
+    pub max_visible: usize,
+    pub average_visible: f32,
+}
+
+impl Pvs {
+    /// Casts rays between sample points of every open cell and every other
+    /// cell through the level's merged layers.
+    pub(crate) fn compute(level: &Level, registry: &PrimitiveRegistry) -> anyhow::Result<Self> {
+        Ok(Self::from_cells(&Cells::from_level(level, registry)?))
+    }
+
+    fn from_cells(cells: &Cells) -> Self {
+        let (width, depth) = (cells.width, cells.depth);
+        let mut pvs = Self {
+            width,
+            depth,
+            hash: cells.hash(),
+            open: cells.walls.iter().map(Option::is_none).collect(),
+            visible: vec![0; width * depth * row_bytes(width * depth)],
+        };
+        for from in 0..width * depth {
+            if !pvs.open[from] {
+                continue;
+            }
+            for to in 0..width * depth {
+                // Open cells see each other both ways.
+                let known = to < from && pvs.open[to];
+                let visible = if known {
+                    pvs.get(to, from)
+                } else {
+                    to == from || cells.any_ray(from, to)
+                };
+                if visible {
+                    pvs.set(from, to);
+                }
+            }
+        }
+        pvs
+    }
+
+    /// Loads the level's saved set, or computes it if there isn't one for the
+    /// current walls and ground.
+    pub(crate) fn load_or_compute(
+        level: &Level,
+        registry: &PrimitiveRegistry,
+    ) -> anyhow::Result<Self> {
+        let path = pvs_path(Path::new(PVS_DIR), level.name);
+        let cells = Cells::from_level(level, registry)?;
+        if path.exists() {
+            match Self::load(&path) {
+                Ok(pvs) if pvs.hash == cells.hash() => return Ok(pvs),
+                Ok(_) => log::warn!(
+                    "{} doesn't match the level's walls and ground, run `pvs` again",
+                    path.display()
+                ),
+                Err(err) => log::warn!("couldn't load {}: {:#}", path.display(), err),
+            }
+        }
+        log::info!("computing PVS for {}", level.name);
+        Ok(Self::from_cells(&cells))
+    }
+
+    pub(crate) fn save(&self, dir: &Path, level: &str) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(self.width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.depth as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.hash.to_le_bytes());
+        bytes.extend(self.open.iter().map(|&open| open as u8));
+        bytes.extend_from_slice(&self.visible);
+        let path = pvs_path(dir, level);
+        std::fs::write(&path, bytes).with_context(|| format!("writing {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < HEADER_BYTES || &bytes[..4] != MAGIC {
+            bail!("not a PVS file");
+        }
+        let width = u32::from_le_bytes(bytes[4..8].try_into()?) as usize;
+        let depth = u32::from_le_bytes(bytes[8..12].try_into()?) as usize;
+        let hash = u64::from_le_bytes(bytes[12..HEADER_BYTES].try_into()?);
+        let cells = width * depth;
+        if bytes.len() != HEADER_BYTES + cells + cells * row_bytes(cells) {
+            bail!("expected {}x{} cells", width, depth);
+        }
+        Ok(Self {
+            width,
+            depth,
+            hash,
+            open: bytes[HEADER_BYTES..HEADER_BYTES + cells]
+                .iter()
+                .map(|&open| open != 0)
+                .collect(),
+            visible: bytes[HEADER_BYTES + cells..].to_vec(),
+        })
+    }
+
+    pub(crate) fn stats(&self) -> PvsStats {
+        let cells = self.width * self.depth;
+        let counts: Vec<usize> = (0..cells)
+            .filter(|&cell| self.open[cell])
+            .map(|from| (0..cells).filter(|&to| self.get(from, to)).count())
+            .collect();
+        PvsStats {
+            cells,
+            open: counts.len(),
+            min_visible: counts.iter().copied().min().unwrap_or(0),
+            max_visible: counts.iter().copied().max().unwrap_or(0),
+            average_visible: counts.iter().sum::<usize>() as f32 / counts.len().max(1) as f32,
+        }
+    }
+
+    /// What can be seen from `eye`, or `None` if it isn't in an open cell.
+    pub(crate) fn view_from(&self, eye: Vector3<f32>) -> Option<PvsView<'_>> {
+        let (x, z) = world_cell(eye);
+        let from = self.index(x, z)?;
+        self.open[from].then_some(PvsView { pvs: self, from })
+    }
+
+    pub(crate) fn grid_size(&self) -> (usize, usize) {
+        (self.width, self.depth)
+    }
+
+    fn index(&self, x: i32, z: i32) -> Option<usize> {
+        cell_index(self.width, self.depth, x, z)
+    }
+
+    fn get(&self, from: usize, to: usize) -> bool {
+        let byte = from * row_bytes(self.width * self.depth) + to / 8;
+        self.visible[byte] & (1 << (to % 8)) != 0
+    }
+
+    fn set(&mut self, from: usize, to: usize) {
+        let byte = from * row_bytes(self.width * self.depth) + to / 8;
+        self.visible[byte] |= 1 << (to % 8);
+    }
+}
+
+/// The visibility set of the cell the eye is in.
+pub(crate) struct PvsView<'a> {
+    pvs: &'a Pvs,
+    from: usize,
+}
+
+impl PvsView<'_> {
+    /// Whether the cell holding `position` might be seen. Anything outside
+    /// the grid is assumed visible.
+    pub(crate) fn sees(&self, position: Vector3<f32>) -> bool {
+        let (x, z) = world_cell(position);
+        self.pvs
+            .index(x, z)
+            .is_none_or(|to| self.pvs.get(self.from, to))
+    }
+
+    /// One bit per cell, lowest first within each byte.
+    pub(crate) fn row(&self) -> &[u8] {
+        let bytes = row_bytes(self.pvs.width * self.pvs.depth);
+        &self.pvs.visible[self.from * bytes..(self.from + 1) * bytes]
+    }
+}
+
+/// The grid a visibility set is computed from, row by row.
+struct Cells {
+    width: usize,
+    depth: usize,
+    /// Top of each cell's wall, or `None` where sight passes through.
+    walls: Vec<Option<f32>>,
+    /// Highest the eye gets in each cell, raised by ramps and stairs.
+    eyes: Vec<f32>,
+}
+
+impl Cells {
+    fn from_level(level: &Level, registry: &PrimitiveRegistry) -> anyhow::Result<Self> {
+        let occluders = level.occluders();
+        let (width, depth) = (occluders.width, occluders.depth);
+        let walls = (0..width * depth)
+            .map(|cell| {
+                let (x, z) = ((cell % width) as i32, (cell / width) as i32);
+                let solid = (0..).take_while(|&y| occluders.is_solid(x, y, z)).count() as i32;
+                // Cubes are centred on even multiples of `CELL_SIZE`.
+                (solid >= OCCLUDER_CELLS).then(|| (2 * solid - 1) as f32 * CELL_SIZE)
+            })
+            .collect();
+        let mut eyes = vec![GROUND_EYE; width * depth];
+        for layer in &level.layers {
+            let primitive = registry.build(layer.primitive, &layer.params)?;
+            if !matches!(
+                primitive.collision_shape(),
+                CollisionShape::Ramp | CollisionShape::Steps
+            ) {
+                continue;
+            }
+            let half = primitive.bounds().half_extents();
+            for instance in layer.instances(primitive.as_ref()) {
+                // Just inside the footprint, so cells it only touches are left out.
+                let corner =
+                    |dx: f32, dz: f32| world_cell(instance.position + Vector3::new(dx, 0.0, dz));
+                let (min, max) = (
+                    corner(0.01 - half.x, 0.01 - half.z),
+                    corner(half.x - 0.01, half.z - 0.01),
+                );
+                for (x, z) in (min.1..=max.1).flat_map(|z| (min.0..=max.0).map(move |x| (x, z))) {
+                    let Some(cell) = cell_index(width, depth, x, z) else {
+                        continue;
+                    };
+                    // Ramps and treads only rise or fall along each axis, so the
+                    // highest point of a cell is one of its corners, which the
+                    // surface clamps onto the footprint.
+                    for (sx, sz) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                        let dx = (2.0 * x as f32 + sx) * CELL_SIZE - instance.position.x;
+                        let dz = (2.0 * z as f32 + sz) * CELL_SIZE - instance.position.z;
+                        let eye =
+                            instance.position.y + primitive.surface_height(dx, dz) + EYE_HEIGHT;
+                        eyes[cell] = eyes[cell].max(eye);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            width,
+            depth,
+            walls,
+            eyes,
+        })
+    }
+
+    /// Whether any ray between sample points of the two cells gets through,
+    /// seen from as high as the eye gets in either of them.
+    fn any_ray(&self, from: usize, to: usize) -> bool {
+        let samples = |cell: usize| {
+            let (x, z) = ((cell % self.width) as f32, (cell / self.width) as f32);
+            (0..SAMPLES_PER_SIDE * SAMPLES_PER_SIDE).map(move |sample| {
+                // Kept just inside the cell so rays start and end where expected.
+                let offset = |i: usize| 0.02 + 0.96 * i as f32 / (SAMPLES_PER_SIDE - 1) as f32;
+                [
+                    x + offset(sample % SAMPLES_PER_SIDE),
+                    z + offset(sample / SAMPLES_PER_SIDE),
+                ]
+            })
+        };
+        let eye = self.eyes[from].max(self.eyes[to]);
+        samples(from).any(|start| samples(to).any(|end| self.ray_clear(start, end, eye)))
+    }
+
+    /// Walks the cells a segment crosses in grid units, blocked by any wall
+    /// between its ends that is taller than `eye`.
+    fn ray_clear(&self, start: [f32; 2], end: [f32; 2], eye: f32) -> bool {
+        let mut cell = start.map(|c| c.floor() as i32);
+        let target = end.map(|c| c.floor() as i32);
+        let mut step = [0; 2];
+        let mut t_max = [f32::INFINITY; 2];
+        let mut t_delta = [f32::INFINITY; 2];
+        for axis in 0..2 {
+            let d = end[axis] - start[axis];
+            if d > 0.0 {
+                step[axis] = 1;
+                t_max[axis] = (cell[axis] as f32 + 1.0 - start[axis]) / d;
+                t_delta[axis] = 1.0 / d;
+            } else if d < 0.0 {
+                step[axis] = -1;
+                t_max[axis] = (start[axis] - cell[axis] as f32) / -d;
+                t_delta[axis] = 1.0 / -d;
+            }
+        }
+        let max_steps = self.width + self.depth;
+        for _ in 0..max_steps {
+            if cell == target {
+                return true;
+            }
+            let axis = if t_max[0] < t_max[1] { 0 } else { 1 };
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            let wall =
+                cell_index(self.width, self.depth, cell[0], cell[1]).and_then(|i| self.walls[i]);
+            if cell != target && wall.is_some_and(|top| top > eye) {
+                return false;
+            }
+        }
+        cell == target
+    }
+
+    /// FNV-1a over the grid's size, wall tops and eye heights. Walls too low
+    /// to count don't change it, since they don't change what can be seen.
+    fn hash(&self) -> u64 {
+        let size = [self.width as u32, self.depth as u32].map(u32::to_le_bytes);
+        // Walls are over a cell tall, so a top of 0 stands for no wall.
+        let walls = self
+            .walls
+            .iter()
+            .map(|wall| wall.unwrap_or(0.0).to_le_bytes());
+        let eyes = self.eyes.iter().map(|eye| eye.to_le_bytes());
+        size.into_iter()
+            .chain(walls)
+            .chain(eyes)
+            .flatten()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+}
+
+fn cell_index(width: usize, depth: usize, x: i32, z: i32) -> Option<usize> {
+    let inside = (0..width as i32).contains(&x) && (0..depth as i32).contains(&z);
+    inside.then(|| z as usize * width + x as usize)
+}
+
+fn row_bytes(cells: usize) -> usize {
+    cells.div_ceil(8)
+}
+
+/// Grid cell holding a world position. Cells are centred on even multiples
+/// of `CELL_SIZE`.
+pub(crate) fn world_cell(position: Vector3<f32>) -> (i32, i32) {
+    let cell = |c: f32| ((c + CELL_SIZE) / (2.0 * CELL_SIZE)).floor() as i32;
+    (cell(position.x), cell(position.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Top of a wall five cubes tall, like the demo level's.
+    const WALL_TOP: f32 = 9.0;
+
+    /// `#` for walls and `.` for open floor, one string per row.
+    fn cells(rows: &[&str]) -> Cells {
+        let walls: Vec<_> = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| (c == '#').then_some(WALL_TOP)))
+            .collect();
+        Cells {
+            width: rows[0].len(),
+            depth: rows.len(),
+            eyes: vec![GROUND_EYE; walls.len()],
+            walls,
+        }
+    }
+
+    fn maze(rows: &[&str]) -> Pvs {
+        Pvs::from_cells(&cells(rows))
+    }
+
+    fn sees(pvs: &Pvs, from: (i32, i32), to: (i32, i32)) -> bool {
+        let from = pvs.index(from.0, from.1).unwrap();
+        pvs.get(from, pvs.index(to.0, to.1).unwrap())
+    }
+
+    #[test]
+    fn wall_hides_the_far_side() {
+        let pvs = maze(&["..#..", "..#..", "..#.."]);
+        assert!(sees(&pvs, (0, 0), (1, 2)));
+        // The wall itself counts as seen.
+        assert!(sees(&pvs, (0, 0), (2, 1)));
+        assert!(!sees(&pvs, (0, 0), (3, 0)));
+        assert!(!sees(&pvs, (1, 1), (4, 2)));
+        assert!(!sees(&pvs, (4, 2), (0, 0)));
+    }
+
+    #[test]
+    fn gap_lets_sight_through_both_ways() {
+        let pvs = maze(&["..#..", ".....", "..#.."]);
+        assert!(sees(&pvs, (0, 1), (4, 1)));
+        assert!(sees(&pvs, (4, 1), (0, 1)));
+        assert!(sees(&pvs, (0, 0), (4, 2)));
+    }
+
+    #[test]
+    fn corridor_hides_round_the_corner() {
+        let pvs = maze(&["......", "#####.", "#####.", "#####."]);
+        assert!(sees(&pvs, (0, 0), (5, 0)));
+        assert!(sees(&pvs, (5, 3), (5, 0)));
+        assert!(!sees(&pvs, (0, 0), (5, 3)));
+        assert!(!sees(&pvs, (5, 3), (0, 0)));
+    }
+
+    #[test]
+    fn rays_stop_at_closed_cells_between_their_ends() {
+        let cells = cells(&["..#..", "..#..", "..#.."]);
+        assert!(cells.ray_clear([0.5, 0.5], [1.5, 2.5], GROUND_EYE));
+        assert!(cells.ray_clear([0.5, 0.5], [2.5, 0.5], GROUND_EYE));
+        assert!(!cells.ray_clear([0.5, 0.5], [3.5, 0.5], GROUND_EYE));
+        assert!(!cells.ray_clear([4.5, 2.5], [0.5, 0.5], GROUND_EYE));
+        assert!(cells.ray_clear([4.5, 2.5], [0.5, 0.5], WALL_TOP + 1.0));
+    }
+
+    #[test]
+    fn eye_on_raised_ground_sees_over_walls() {
+        let mut grid = cells(&["..#..", "..#..", "..#.."]);
+        // Partway up a ramp, still below the wall.
+        grid.eyes[0] = WALL_TOP - 2.0;
+        // On top of a ramp taller than the wall.
+        grid.eyes[2 * 5] = WALL_TOP + 2.0;
+        let pvs = Pvs::from_cells(&grid);
+        assert!(!sees(&pvs, (0, 0), (3, 0)));
+        assert!(sees(&pvs, (0, 2), (4, 2)));
+        assert!(sees(&pvs, (0, 2), (4, 0)));
+        // The ramp's top can be seen from the far side too.
+        assert!(sees(&pvs, (4, 0), (0, 2)));
+        assert!(!sees(&pvs, (4, 0), (0, 0)));
+        assert_ne!(pvs.hash, maze(&["..#..", "..#..", "..#.."]).hash);
+    }
+
+    #[test]
+    fn demo_ramp_looks_over_the_inner_wall() {
+        let level = Level::demo();
+        let grid = Cells::from_level(&level, &PrimitiveRegistry::with_builtins()).unwrap();
+        let top_of_ramp = cell_index(grid.width, grid.depth, 6, 1).unwrap();
+        assert!(grid.eyes[top_of_ramp] > grid.walls[7].unwrap());
+        let pvs = Pvs::from_cells(&grid);
+        assert!(sees(&pvs, (6, 1), (1, 1)));
+        assert!(!sees(&pvs, (5, 1), (1, 1)));
+    }
+
+    #[test]
+    fn saved_sets_remember_their_walls() {
+        let pvs = maze(&["..#..", ".....", "..#.."]);
+        let dir = std::env::temp_dir().join(format!("pvs_test_{}", std::process::id()));
+        let path = pvs.save(&dir, "maze").unwrap();
+        let loaded = Pvs::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.hash, pvs.hash);
+        assert_eq!(loaded.visible, pvs.visible);
+
+        // Same size, one more wall.
+        let edited = maze(&["..#..", "..#..", "..#.."]);
+        assert_ne!(loaded.hash, edited.hash);
+    }
+}
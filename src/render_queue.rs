@@ -0,0 +1,290 @@
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Rotation, Vector4};
+
+use crate::{
+    camera_uniform::CameraUniform,
+    culling::Frustum,
+    gpu_culling::GpuCulling,
+    instance::{Billboard, InstanceRaw},
+    level::{LevelBatch, LoadedLayer},
+    material::BlendMode,
+    pvs::{Pvs, PvsView},
+    shaders,
+    systems::pipeline_init,
+};
+
+/// The main pass's pipeline for each blend mode, with the shaders they were
+/// built from so they can be rebuilt for another sample count.
+pub(crate) struct ScenePipelines {
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    alpha_test_shader: wgpu::ShaderModule,
+    sample_count: u32,
+    opaque: wgpu::RenderPipeline,
+    alpha_tested: wgpu::RenderPipeline,
+    blended: wgpu::RenderPipeline,
+}
+
+impl ScenePipelines {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> anyhow::Result<Self> {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[
+                texture_bind_group_layout,
+                camera_bind_group_layout,
+                light_bind_group_layout,
+                shadow_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let (shader, alpha_test_shader) = load_shaders(device)?;
+        let [opaque, alpha_tested, blended] =
+            build(device, &layout, &shader, &alpha_test_shader, sample_count);
+        Ok(Self {
+            layout,
+            shader,
+            alpha_test_shader,
+            sample_count,
+            opaque,
+            alpha_tested,
+            blended,
+        })
+    }
+
+    pub(crate) fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        [self.opaque, self.alpha_tested, self.blended] = build(
+            device,
+            &self.layout,
+            &self.shader,
+            &self.alpha_test_shader,
+            sample_count,
+        );
+        self.sample_count = sample_count;
+    }
+
+    /// Rebuilds every pipeline from the shaders on disk, keeping the old ones
+    /// if the new shaders or pipelines don't validate.
+    pub(crate) fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        let (shader, alpha_test_shader, pipelines) =
+            shaders::catch_validation_errors(device, || {
+                let (shader, alpha_test_shader) = load_shaders(device)?;
+                let pipelines = build(
+                    device,
+                    &self.layout,
+                    &shader,
+                    &alpha_test_shader,
+                    self.sample_count,
+                );
+                Ok((shader, alpha_test_shader, pipelines))
+            })?;
+        self.shader = shader;
+        self.alpha_test_shader = alpha_test_shader;
+        [self.opaque, self.alpha_tested, self.blended] = pipelines;
+        Ok(())
+    }
+}
+
+fn load_shaders(device: &wgpu::Device) -> anyhow::Result<(wgpu::ShaderModule, wgpu::ShaderModule)> {
+    Ok((
+        shaders::create_module(device, "shader.wgsl", &[], "Shader")?,
+        shaders::create_module(device, "shader.wgsl", &["ALPHA_TEST"], "Alpha Test Shader")?,
+    ))
+}
+
+/// The opaque, alpha-tested and blended pipelines.
+fn build(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    alpha_test_shader: &wgpu::ShaderModule,
+    sample_count: u32,
+) -> [wgpu::RenderPipeline; 3] {
+    [
+        pipeline_init(device, layout, shader, BlendMode::Opaque, sample_count),
+        pipeline_init(
+            device,
+            layout,
+            alpha_test_shader,
+            BlendMode::AlphaTested,
+            sample_count,
+        ),
+        pipeline_init(device, layout, shader, BlendMode::Blended, sample_count),
+    ]
+}
+
+/// A range of one layer's culled instances, drawn with one call.
+struct Draw {
+    layer: usize,
+    instances: Range<u32>,
+}
+
+/// How much CPU culling of blended instances saved this frame. Everything
+/// else is culled on the GPU, see `GpuCulling`.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct CullStats {
+    pub drawn: usize,
+    /// Outside the view frustum.
+    pub culled: usize,
+    /// In cells the PVS says can't be seen from the camera's cell.
+    pub hidden: usize,
+}
+
+/// The order layers are drawn in this frame: opaque, then alpha tested, then
+/// blended instances back to front across every blended layer. The first two
+/// are culled on the GPU; only blended instances that are potentially visible
+/// and inside the view frustum are queued here.
+pub(crate) struct RenderQueues {
+    blended: Vec<Draw>,
+    pub stats: CullStats,
+}
+
+impl RenderQueues {
+    /// Culls blended instances against the PVS and the camera's
+    /// view-projection and writes the visible ones back to front into each
+    /// layer's range of the culled buffer.
+    pub(crate) fn build(
+        queue: &wgpu::Queue,
+        layers: &[LoadedLayer],
+        camera_uniform: &CameraUniform,
+        pvs: &Pvs,
+        culling: &GpuCulling,
+    ) -> Self {
+        let frustum = Frustum::from_view_proj(camera_uniform.view_proj.into());
+        let eye = Vector4::from(camera_uniform.view_position).truncate();
+        // Everything counts as potentially visible from outside the open cells.
+        let view = pvs.view_from(eye);
+        let in_pvs = |position| {
+            view.as_ref()
+                .is_none_or(|view: &PvsView| view.sees(position))
+        };
+        let mut stats = CullStats::default();
+        // Distance, layer and position in that layer's sorted range.
+        let mut blended_instances = Vec::new();
+        // Blended layers are never static, see `LoadedLayer::blend`.
+        for (index, layer) in layers
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.blend == BlendMode::Blended)
+        {
+            let bounds = layer.primitive.bounds();
+            let radius = bounds.half_extents().magnitude();
+            let centre_distance = bounds.centre().magnitude();
+            let potentially_visible: Vec<_> = layer
+                .instances
+                .iter()
+                .filter(|instance| in_pvs(instance.position))
+                .collect();
+            let mut visible: Vec<_> = potentially_visible
+                .iter()
+                .filter(|instance| {
+                    if instance.billboard != Billboard::None {
+                        // Billboards turn about their origin rather than
+                        // with the instance.
+                        return instance
+                            .billboard
+                            .bounding_radius(centre_distance, radius)
+                            .is_none_or(|radius| {
+                                frustum.intersects_sphere(instance.position, radius)
+                            });
+                    }
+                    let centre =
+                        instance.position + instance.rotation.rotate_vector(bounds.centre());
+                    frustum.intersects_sphere(centre, radius)
+                })
+                .map(|instance| ((instance.position - eye).magnitude2(), instance))
+                .collect();
+            stats.drawn += visible.len();
+            stats.culled += potentially_visible.len() - visible.len();
+            stats.hidden += layer.instances.len() - potentially_visible.len();
+            if visible.is_empty() {
+                continue;
+            }
+
+            visible.sort_by(|a, b| b.0.total_cmp(&a.0));
+            blended_instances.extend(
+                visible
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, (distance, _))| (*distance, index, slot as u32)),
+            );
+            let raw: Vec<InstanceRaw> = visible
+                .iter()
+                .map(|(_, instance)| instance.to_raw(index as u32))
+                .collect();
+            culling.write_instances(queue, index, &raw);
+        }
+
+        // Neighbours from the same layer are consecutive in its range, so
+        // they merge into one instanced draw.
+        blended_instances.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut blended: Vec<Draw> = Vec::new();
+        for (_, layer, slot) in blended_instances {
+            match blended.last_mut() {
+                Some(draw) if draw.layer == layer && draw.instances.end == slot => {
+                    draw.instances.end += 1;
+                }
+                _ => blended.push(Draw {
+                    layer,
+                    instances: slot..slot + 1,
+                }),
+            }
+        }
+
+        Self { blended, stats }
+    }
+
+    /// Records every queue into `render_pass`, which should already have the
+    /// camera, light and shadow bind groups set. `culling` must have culled
+    /// this frame already.
+    pub(crate) fn draw<'a>(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a ScenePipelines,
+        layers: &'a [LoadedLayer],
+        batch: &'a LevelBatch,
+        culling: &'a GpuCulling,
+    ) {
+        // Only the instances, and now and then the textures, change from
+        // draw to draw.
+        batch.bind(render_pass);
+        let culled = [
+            (&pipelines.opaque, BlendMode::Opaque),
+            (&pipelines.alpha_tested, BlendMode::AlphaTested),
+        ];
+        for (pipeline, blend) in culled {
+            render_pass.set_pipeline(pipeline);
+            for (index, layer) in layers.iter().enumerate() {
+                if layer.blend != blend {
+                    continue;
+                }
+                if let Some(instances) = culling.instances(index) {
+                    batch.bind_textures(render_pass, layer);
+                    render_pass.set_vertex_buffer(1, instances);
+                    culling.draw_indirect(render_pass, index);
+                }
+            }
+        }
+
+        render_pass.set_pipeline(&pipelines.blended);
+        for draw in &self.blended {
+            let layer = &layers[draw.layer];
+            if let Some(instances) = culling.instances(draw.layer) {
+                batch.bind_textures(render_pass, layer);
+                render_pass.set_vertex_buffer(1, instances);
+                render_pass.draw_indexed(
+                    layer.first_index..layer.first_index + layer.num_indices,
+                    layer.base_vertex,
+                    draw.instances.clone(),
+                );
+            }
+        }
+    }
+}
@@ -2,7 +2,12 @@ use std::io::{Cursor, BufReader};
 
 use wgpu::util::DeviceExt;
 
-use crate::{texture, model};
+use crate::{
+    material::{MaterialRaw, MaterialTextures, SurfaceMaps},
+    material_library::{MaterialDef, MaterialLibrary},
+    model, systems, texture,
+    texture_file::TextureFile,
+};
 
 pub fn load_string(file_name: &str) -> anyhow::Result<String> {
     let path = std::path::Path::new(env!("OUT_DIR")).join("res").join(file_name);
@@ -25,15 +30,109 @@ pub fn load_texture(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name)?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    let file = TextureFile::parse(&load_binary(file_name)?)?;
+    texture::Texture::from_files(device, queue, &[file], texture::Sampling::default(), file_name)
 }
 
+/// An optional map named by a material file, left empty when it has none.
+fn load_map(file_name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    if file_name.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(load_binary(file_name)?))
+}
+
+/// The material an MTL file describes, for models whose materials aren't in
+/// the library.
+fn mtl_material(m: &tobj::Material) -> anyhow::Result<MaterialDef> {
+    // MTL's PBR extension has roughness and metalness maps of their own
+    // rather than one packed map, so only its factors are used.
+    let factor = |key: &str, default| {
+        m.unknown_param
+            .get(key)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let mut material = MaterialDef::new(load_binary(&m.diffuse_texture)?);
+    material.maps = SurfaceMaps {
+        normal: load_map(&m.normal_texture)?,
+        emissive: match m.unknown_param.get("map_Ke") {
+            Some(file_name) => load_map(file_name)?,
+            None => None,
+        },
+        roughness: factor("Pr", 1.0),
+        metallic: factor("Pm", 0.0),
+        ..Default::default()
+    };
+    Ok(material)
+}
+
+fn model_material(
+    name: String,
+    def: &MaterialDef,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let diffuse = TextureFile::parse(&def.texture)?;
+    let diffuse_texture =
+        texture::Texture::from_files(device, queue, &[diffuse], def.sampling, &name)?;
+    let normal = def
+        .maps
+        .normal
+        .as_deref()
+        .map(|bytes| TextureFile::parse(bytes).map(TextureFile::into_linear))
+        .transpose()?;
+    let roughness_metallic = def
+        .maps
+        .roughness_metallic
+        .as_deref()
+        .map(|bytes| TextureFile::parse(bytes).map(TextureFile::into_linear))
+        .transpose()?;
+    let emissive = def.maps.emissive.as_deref().map(TextureFile::parse).transpose()?;
+    let material = MaterialRaw::new(def.uv_mode, diffuse_texture.size.width, 0, None)
+        .with_maps(
+            &def.maps,
+            [&normal, &roughness_metallic, &emissive].map(|map| map.as_ref().map(|_| 0)),
+        )
+        .with_tint(def.tint);
+    let material_buffer = MaterialRaw::create_buffer(&[material], device);
+    let lightmap = texture::Texture::from_lightmaps(device, queue, &[], "empty_lightmap");
+    let textures = MaterialTextures::new(
+        device,
+        queue,
+        diffuse_texture,
+        [
+            normal.into_iter().collect(),
+            roughness_metallic.into_iter().collect(),
+            emissive.into_iter().collect(),
+        ],
+        lightmap,
+        def.sampling,
+        &name,
+    )?;
+    let bind_group = systems::create_texture_bind_group(
+        device,
+        layout,
+        &textures,
+        &textures.diffuse.sampler,
+        &material_buffer,
+    );
+    Ok(model::Material {
+        name,
+        textures,
+        bind_group,
+    })
+}
+
+/// Loads an OBJ model. Its materials come from `library` when it has one of
+/// the same name, and from the model's MTL file otherwise.
 pub fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    library: &MaterialLibrary,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name)?;
     let obj_cursor = Cursor::new(obj_text);
@@ -54,47 +153,20 @@ pub fn load_model(
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue)?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+        let material = match library.get(&m.name) {
+            Some(def) => model_material(m.name, def, device, queue, layout)?,
+            None => {
+                let def = mtl_material(&m)?;
+                model_material(m.name, &def, device, queue, layout)?
+            }
+        };
+        materials.push(material);
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                })
-                .collect::<Vec<_>>();
+            let vertices = mesh_vertices(&m.mesh);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
@@ -119,3 +191,70 @@ pub fn load_model(
 
     Ok(model::Model { meshes, materials })
 }
+
+/// The mesh's vertices, with normals worked out from its faces when the file
+/// has none and tangents always worked out from its texture coordinates.
+fn mesh_vertices(mesh: &tobj::Mesh) -> Vec<model::ModelVertex> {
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+    let mut vertices = (0..mesh.positions.len() / 3)
+        .map(|i| model::ModelVertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: if mesh.texcoords.is_empty() {
+                [0.0, 0.0]
+            } else {
+                [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]]
+            },
+            normal: if has_normals {
+                [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ]
+            } else {
+                [0.0, 0.0, 0.0]
+            },
+            tangent: [0.0; 4],
+            lightmap_coords: [0.0; 2],
+            ao: 1.0,
+        })
+        .collect::<Vec<_>>();
+    if !has_normals {
+        model::compute_normals(&mut vertices, &mesh.indices);
+    }
+    model::compute_tangents(&mut vertices, &mesh.indices);
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meshes_without_normals_get_them_from_their_faces() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   f 1/1 2/2 3/3 4/4\n";
+        let (models, _) = tobj::load_obj_buf(
+            &mut BufReader::new(Cursor::new(obj)),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )
+        .unwrap();
+        assert!(models[0].mesh.normals.is_empty());
+
+        let vertices = mesh_vertices(&models[0].mesh);
+        assert_eq!(vertices.len(), 4);
+        for vertex in vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+}
@@ -3,25 +3,52 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(10) material: u32,
+    @location(11) billboard: u32,
+    @location(12) billboard_size: f32,
+    @location(13) uv_offset: vec2<f32>,
+    @location(14) uv_scale: vec2<f32>,
 };
 
 // Vertex shader
 
+#include "billboard.wgsl"
+
 struct Camera {
-    input_values: vec4<f32>,
+    // Width and height in pixels in xy.
+    viewport: vec4<f32>,
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
 }
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
+struct Light {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+}
+@group(1) @binding(1)
+var<uniform> light: Light;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) lightmap_coords: vec2<f32>,
+    @location(9) ao: f32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec4<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) lightmap_coords: vec2<f32>,
+    @location(5) ao: f32,
+    @location(6) @interpolate(flat) material: u32,
 };
 
 @vertex
@@ -35,48 +62,380 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let origin = model_matrix * vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    // Instances are only translated and rotated, so the upper 3x3 is fine for normals.
+    var normal_matrix = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+    var world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    if (instance.billboard != BILLBOARD_NONE) {
+        // Billboards swap the instance's rotation for one facing the camera,
+        // and are lit as if their normal did too.
+        normal_matrix = billboard_basis(instance.billboard, camera.view_position.xyz - origin.xyz);
+        world_position = origin + vec4<f32>(normal_matrix * model.position * instance.billboard_size, 0.0);
+    }
+    var clip_position = camera.view_proj * world_position;
+    if (instance.billboard == BILLBOARD_SCREEN_SIZE) {
+        // Sized in pixels, so offset in clip space at the origin's depth
+        // and lit at the origin.
+        world_position = origin;
+        clip_position = camera.view_proj * origin;
+        let offset = model.position.xy * instance.billboard_size * 2.0 / camera.viewport.xy;
+        clip_position = vec4<f32>(clip_position.xy + offset * clip_position.w, clip_position.zw);
+    }
+
+    var out: VertexOutput;
+    out.tex_coords = instance.uv_offset + model.tex_coords * instance.uv_scale;
+    out.lightmap_coords = model.lightmap_coords;
+    out.ao = model.ao;
+    out.material = instance.material;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = vec4<f32>(normal_matrix * model.tangent.xyz, model.tangent.w);
+    out.world_position = world_position.xyz;
+    out.clip_position = clip_position;
+    return out;
+}
 
-    if (model.position.z == 0.0) {
-        // x-axis scalable billboard
-        let world_position = model_matrix * vec4<f32>(0.0, model.position.y, 0.0, 1.0);
-        var out: VertexOutput;
-        out.tex_coords = model.tex_coords;
-        out.clip_position = camera.view_proj * world_position + vec4((model.position.x / camera.input_values.x) * 2.66, 0.0, 0.0, 0.0);
+// Fragment shader
+
+#include "material.wgsl"
+
+@group(0) @binding(3)
+var t_lightmap: texture_2d_array<f32>;
+@group(0) @binding(4)
+var s_lightmap: sampler;
+
+// Sampled with s_diffuse at the same coordinates as the diffuse texture.
+@group(0) @binding(5)
+var t_normal: texture_2d_array<f32>;
+// Roughness in green, metalness in blue.
+@group(0) @binding(6)
+var t_roughness: texture_2d_array<f32>;
+@group(0) @binding(7)
+var t_emissive: texture_2d_array<f32>;
 
+let NO_MAP: u32 = 4294967295u;
+let PI: f32 = 3.14159265;
 
-        // scalable billboard
-        // let world_position = model_matrix * vec4<f32>(0.0, 0.0, 0.0, 1.0);
-        // var out: VertexOutput;
-        // out.tex_coords = model.tex_coords;
-        // out.clip_position = camera.view_proj * world_position + vec4((model.position.x / camera.input_values.x) * 2.0, model.position.y * 2.0, 0.0, 0.0);
+// Lightmaps are stored divided by this. Must match the baker.
+let LIGHTMAP_RANGE: f32 = 4.0;
 
+// Where a material's textures are sampled: the mesh's own coordinates or
+// planar projections along each world axis, weighted by how much of the
+// surface faces that axis.
+struct Projection {
+    uv_x: vec2<f32>,
+    uv_y: vec2<f32>,
+    uv_z: vec2<f32>,
+    // All zero for mesh coordinates.
+    weights: vec3<f32>,
+}
 
-        // unscalable billboard
-        // let world_position = model_matrix * vec4<f32>(0.0, 0.0, 0.0, 1.0);
-        // var out: VertexOutput;
-        // out.tex_coords = model.tex_coords;
-        // let clipposition: vec4<f32> = camera.view_proj * world_position + vec4((model.position.x / camera.input_values.x) * 2.0, model.position.y * 2.0, 0.0, 0.0);
-        // let clipposition: vec4<f32> = camera.view_proj * world_position + vec4((model.position.x / camera.input_values.x) * 2.0 * clipposition.z * 0.3, model.position.y * 2.0 * clipposition.z * 0.3, 0.0, 0.0);
-        // let clipposition: vec4<f32> = vec4(clipposition.xy, 1.0, clipposition.w);
-        // out.clip_position = clipposition;
-        return out;
-    } else {
-        let world_position = model_matrix * vec4<f32>(model.position, 1.0);
-        var out: VertexOutput;
-        out.tex_coords = model.tex_coords;
-        out.clip_position = camera.view_proj * world_position;
-        return out;
+fn projection(in: VertexOutput, material: Material) -> Projection {
+    let p = (in.world_position + vec3<f32>(material.uv_offset)) * material.uv_scale;
+    var out: Projection;
+    // Same orientation as the per-face coordinates of a cube.
+    out.uv_x = vec2<f32>(-p.z, -p.y);
+    out.uv_y = vec2<f32>(p.x, p.z);
+    out.uv_z = vec2<f32>(p.x, -p.y);
+    out.weights = vec3<f32>(0.0);
+    let n = abs(normalize(in.world_normal));
+    if (material.uv_mode == 2u) {
+        let weights = n * n * n * n;
+        out.weights = weights / (weights.x + weights.y + weights.z);
+    } else if (material.uv_mode == 1u) {
+        if (n.x >= n.y && n.x >= n.z) {
+            out.weights.x = 1.0;
+        } else if (n.y >= n.z) {
+            out.weights.y = 1.0;
+        } else {
+            out.weights.z = 1.0;
+        }
     }
+    return out;
 }
 
-// Fragment shader
+struct Samples {
+    x: vec4<f32>,
+    y: vec4<f32>,
+    z: vec4<f32>,
+    mesh: vec4<f32>,
+}
+
+// Every projection is sampled so sampling stays in uniform control flow.
+fn sample_layer(t: texture_2d_array<f32>, layer: i32, in: VertexOutput, projection: Projection) -> Samples {
+    var out: Samples;
+    out.x = textureSample(t, s_diffuse, projection.uv_x, layer);
+    out.y = textureSample(t, s_diffuse, projection.uv_y, layer);
+    out.z = textureSample(t, s_diffuse, projection.uv_z, layer);
+    out.mesh = textureSample(t, s_diffuse, in.tex_coords, layer);
+    return out;
+}
+
+fn blend(samples: Samples, weights: vec3<f32>) -> vec4<f32> {
+    let mesh = 1.0 - weights.x - weights.y - weights.z;
+    return samples.x * weights.x + samples.y * weights.y + samples.z * weights.z + samples.mesh * mesh;
+}
+
+// The layer to sample for a map, any layer when there's none.
+fn map_layer(map: u32) -> i32 {
+    return select(i32(map), 0, map == NO_MAP);
+}
+
+// A tangent space normal, +Y up the texture, in the frame where u runs
+// along `tangent` and v along `bitangent`.
+fn tangent_to_world(texel: vec4<f32>, tangent: vec3<f32>, bitangent: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let t = normalize(tangent - normal * dot(normal, tangent));
+    let b = normalize(bitangent - normal * dot(normal, bitangent));
+    let ts = texel.xyz * 2.0 - 1.0;
+    return ts.x * t - ts.y * b + ts.z * normal;
+}
+
+fn surface_normal(in: VertexOutput, material: Material, projection: Projection) -> vec3<f32> {
+    let normal = normalize(in.world_normal);
+    let samples = sample_layer(t_normal, map_layer(material.normal_map), in, projection);
+    if (material.normal_map == NO_MAP) {
+        return normal;
+    }
+    let tangent = in.world_tangent.xyz;
+    let bitangent = in.world_tangent.w * cross(normal, tangent);
+    let weights = projection.weights;
+    let mesh = 1.0 - weights.x - weights.y - weights.z;
+    // Each projection's u and v directions, see `projection`.
+    return normalize(
+        tangent_to_world(samples.x, vec3<f32>(0.0, 0.0, -1.0), vec3<f32>(0.0, -1.0, 0.0), normal) * weights.x
+        + tangent_to_world(samples.y, vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), normal) * weights.y
+        + tangent_to_world(samples.z, vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, -1.0, 0.0), normal) * weights.z
+        + tangent_to_world(samples.mesh, tangent, bitangent, normal) * mesh
+    );
+}
+
+struct Surface {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    // Towards the camera.
+    view: vec3<f32>,
+    roughness: f32,
+    metallic: f32,
+}
 
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0)@binding(1)
-var s_diffuse: sampler;
+fn surface(in: VertexOutput, material: Material, projection: Projection, albedo: vec3<f32>) -> Surface {
+    let roughness_metallic = blend(
+        sample_layer(t_roughness, map_layer(material.roughness_map), in, projection),
+        projection.weights,
+    );
+    var out: Surface;
+    out.albedo = albedo;
+    out.normal = surface_normal(in, material, projection);
+    out.view = normalize(camera.view_position.xyz - in.world_position);
+    out.roughness = material.roughness;
+    out.metallic = material.metallic;
+    if (material.roughness_map != NO_MAP) {
+        out.roughness = out.roughness * roughness_metallic.g;
+        out.metallic = out.metallic * roughness_metallic.b;
+    }
+    return out;
+}
+
+// Light arriving along `direction` reflected towards the camera: Lambert
+// diffuse, without the 1/pi like the rest of the lighting, plus GGX
+// specular scaled to match.
+fn reflected(surface: Surface, direction: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = dot(surface.normal, direction);
+    if (n_dot_l <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let n_dot_v = max(dot(surface.normal, surface.view), 0.0001);
+    let half_vector = normalize(direction + surface.view);
+    let n_dot_h = max(dot(surface.normal, half_vector), 0.0);
+    let alpha = max(surface.roughness * surface.roughness, 0.002);
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (PI * d * d);
+    let k = (surface.roughness + 1.0) * (surface.roughness + 1.0) / 8.0;
+    let visibility = 0.25 / ((n_dot_l * (1.0 - k) + k) * (n_dot_v * (1.0 - k) + k));
+    let f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(half_vector, surface.view), 0.0), 5.0);
+    let diffuse = surface.albedo * (1.0 - surface.metallic) * (1.0 - fresnel);
+    return (diffuse + distribution * visibility * fresnel * PI) * radiance * n_dot_l;
+}
+
+struct PointLight {
+    position_range: vec4<f32>,
+    color_intensity: vec4<f32>,
+    // xyz = direction, w = cosine of the outer angle
+    spot_direction: vec4<f32>,
+    // x = cosine of the inner angle, y = 1 for spot lights
+    spot_params: vec4<f32>,
+}
+@group(2) @binding(0)
+var<storage, read> point_lights: array<PointLight>;
+// Offset and count into cluster_lights for each cluster.
+@group(2) @binding(1)
+var<storage, read> clusters: array<vec2<u32>>;
+@group(2) @binding(2)
+var<storage, read> cluster_lights: array<u32>;
+
+struct ClusterInfo {
+    // x, y, z cluster counts and the number of lights
+    grid: vec4<u32>,
+    // width, height, near, far
+    screen: vec4<f32>,
+    forward: vec4<f32>,
+}
+@group(2) @binding(3)
+var<uniform> cluster_info: ClusterInfo;
+
+fn cluster_index(frag_coord: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let grid = cluster_info.grid;
+    let tile = vec2<u32>(clamp(
+        frag_coord / cluster_info.screen.xy * vec2<f32>(grid.xy),
+        vec2<f32>(0.0),
+        vec2<f32>(grid.xy - vec2<u32>(1u)),
+    ));
+    let near = cluster_info.screen.z;
+    let far = cluster_info.screen.w;
+    let depth = max(dot(world_position - camera.view_position.xyz, cluster_info.forward.xyz), near);
+    let slice = min(u32(log(depth / near) / log(far / near) * f32(grid.z)), grid.z - 1u);
+    return tile.x + tile.y * grid.x + slice * grid.x * grid.y;
+}
+
+struct Shadows {
+    // Sun cascades, then spot light maps.
+    view_proj: array<mat4x4<f32>, 7>,
+    // Far view depth of each cascade, then the shadow distance.
+    cascade_splits: vec4<f32>,
+    // World size of a texel in each cascade, then the texel size in uv.
+    texel_sizes: vec4<f32>,
+}
+@group(3) @binding(0)
+var shadow_maps: texture_depth_2d_array;
+@group(3) @binding(1)
+var shadow_sampler: sampler_comparison;
+@group(3) @binding(2)
+var<uniform> shadows: Shadows;
+
+// Fraction of `world_position` lit in the shadow map `layer`, 3x3 PCF.
+fn shadow_factor(layer: u32, world_position: vec3<f32>) -> f32 {
+    let clip = shadows.view_proj[layer] * vec4<f32>(world_position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    let texel = shadows.texel_sizes.w;
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(shadow_maps, shadow_sampler, uv + offset, i32(layer), ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+fn sun_shadow(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let depth = dot(world_position - camera.view_position.xyz, cluster_info.forward.xyz);
+    if (depth >= shadows.cascade_splits.w) {
+        return 1.0;
+    }
+    var cascade = 2u;
+    if (depth < shadows.cascade_splits.x) {
+        cascade = 0u;
+    } else if (depth < shadows.cascade_splits.y) {
+        cascade = 1u;
+    }
+    // Pushing the lookup along the normal keeps surfaces from shadowing themselves.
+    let offset = normal * shadows.texel_sizes[cascade];
+    return shadow_factor(cascade, world_position + offset);
+}
+
+// `normal` is the geometric normal, used to offset shadow lookups.
+fn point_lighting(
+    surface: Surface,
+    normal: vec3<f32>,
+    world_position: vec3<f32>,
+    frag_coord: vec2<f32>,
+    lightmapped: bool,
+) -> vec3<f32> {
+    let cluster = clusters[cluster_index(frag_coord, world_position)];
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < cluster.y; i = i + 1u) {
+        let source = point_lights[cluster_lights[cluster.x + i]];
+        let to_light = source.position_range.xyz - world_position;
+        let distance = length(to_light);
+        let range = source.position_range.w;
+        let flags = u32(source.spot_params.y);
+        if (distance >= range || (lightmapped && (flags & 2u) != 0u)) {
+            continue;
+        }
+        let direction = to_light / max(distance, 0.0001);
+        // Inverse square, windowed so it reaches zero at the range.
+        let ratio = distance / range;
+        let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+        var attenuation = window * window / (distance * distance + 1.0);
+        if ((flags & 1u) != 0u) {
+            let cos_angle = dot(-direction, source.spot_direction.xyz);
+            attenuation = attenuation * smoothstep(source.spot_direction.w, source.spot_params.x, cos_angle);
+            if (source.spot_params.w > 0.0) {
+                // Texels grow with distance from a perspective map.
+                let offset = normal * shadows.texel_sizes.w * distance * 2.0;
+                attenuation = attenuation * shadow_factor(u32(source.spot_params.z), world_position + offset);
+            }
+        }
+        total = total + reflected(surface, direction, source.color_intensity.rgb * source.color_intensity.w * attenuation);
+    }
+    return total;
+}
+
+fn lighting(surface: Surface, normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let direction = -light.sun_direction.xyz;
+    var shadow = 1.0;
+    if (dot(surface.normal, direction) > 0.0) {
+        shadow = sun_shadow(world_position, normal);
+    }
+    return surface.albedo * light.ambient_color.rgb
+        + reflected(surface, direction, light.sun_color.rgb * shadow);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let material = materials[in.material];
+    let projection = projection(in, material);
+    let albedo = blend(sample_layer(t_diffuse, i32(material.texture), in, projection), projection.weights)
+        * material.tint;
+    let emissive = blend(sample_layer(t_emissive, map_layer(material.emissive_map), in, projection), projection.weights);
+    let surface = surface(in, material, projection, albedo.rgb);
+    let normal = normalize(in.world_normal);
+    let baked = textureSample(t_lightmap, s_lightmap, in.lightmap_coords, i32(material.lightmap)).rgb
+        * LIGHTMAP_RANGE;
+    let lightmapped = material.lightmapped != 0u;
+    var color = albedo.rgb * baked;
+    if (!lightmapped) {
+        // Baked lighting already accounts for occlusion.
+        color = lighting(surface, normal, in.world_position) * in.ao;
+    }
+    color = color + point_lighting(surface, normal, in.world_position, in.clip_position.xy, lightmapped);
+    if (material.emissive_map != NO_MAP) {
+        color = color + emissive.rgb * material.emissive_strength;
+    }
+    return vec4<f32>(color, albedo.a);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = shade(in);
+#ifdef ALPHA_TEST
+    // Cut-out surfaces write depth like opaque ones.
+    if (color.a < 0.5) {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+#else
+    return color;
+#endif
 }
@@ -0,0 +1,437 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+/// Where shaders are read from while the game runs, so saving one rebuilds
+/// its pipelines without restarting.
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+/// Built-in copies of every shader, used when `SHADER_DIR` isn't around.
+const EMBEDDED: &[(&str, &str)] = &[
+    ("shader.wgsl", include_str!("shader.wgsl")),
+    ("shadow.wgsl", include_str!("shadow.wgsl")),
+    ("material.wgsl", include_str!("material.wgsl")),
+    ("billboard.wgsl", include_str!("billboard.wgsl")),
+    ("cull.wgsl", include_str!("cull.wgsl")),
+    ("post.wgsl", include_str!("post.wgsl")),
+];
+
+/// Every shader the game builds and the defines it's built with, so they
+/// can all be checked at once.
+pub(crate) const VARIANTS: &[(&str, &[&str])] = &[
+    ("shader.wgsl", &[]),
+    ("shader.wgsl", &["ALPHA_TEST"]),
+    ("shadow.wgsl", &[]),
+    ("cull.wgsl", &[]),
+    ("post.wgsl", &[]),
+];
+
+/// How often `ShaderWatcher` looks at the shaders' modification times.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn read(name: &str) -> anyhow::Result<String> {
+    if let Ok(code) = std::fs::read_to_string(Path::new(SHADER_DIR).join(name)) {
+        return Ok(code);
+    }
+    EMBEDDED
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, code)| code.to_string())
+        .with_context(|| format!("no shader named {}", name))
+}
+
+/// An `#ifdef` or `#ifndef` that hasn't reached its `#endif`.
+struct Condition {
+    /// Whether the lines around it are kept.
+    parent: bool,
+    /// Whether its own lines are kept.
+    active: bool,
+    has_else: bool,
+    line: usize,
+}
+
+/// WGSL after its preprocessor directives, which must start their line:
+///
+/// - `#include "file.wgsl"` pastes in another shader, once per source
+///   however many files include it.
+/// - `#define NAME` defines a name for the rest of the source, as do the
+///   defines a source is loaded with.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop lines
+///   by whether a name is defined. They nest but can't span files.
+pub(crate) struct ShaderSource {
+    pub code: String,
+    files: Vec<String>,
+    /// The index in `files` and the line each line of `code` came from.
+    origins: Vec<(usize, usize)>,
+}
+
+impl ShaderSource {
+    pub(crate) fn load(name: &str, defines: &[&str]) -> anyhow::Result<Self> {
+        Self::load_with(name, defines, &read)
+    }
+
+    /// Like `load`, with `read` giving each file's code by name.
+    fn load_with(
+        name: &str,
+        defines: &[&str],
+        read: &dyn Fn(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<Self> {
+        let mut source = Self {
+            code: String::new(),
+            files: Vec::new(),
+            origins: Vec::new(),
+        };
+        let mut defines = defines.iter().map(|define| define.to_string()).collect();
+        source.append(name, read, &mut defines, &mut HashSet::new())?;
+        Ok(source)
+    }
+
+    fn append(
+        &mut self,
+        name: &str,
+        read: &dyn Fn(&str) -> anyhow::Result<String>,
+        defines: &mut HashSet<String>,
+        included: &mut HashSet<String>,
+    ) -> anyhow::Result<()> {
+        if !included.insert(name.to_string()) {
+            return Ok(());
+        }
+        let code = read(name)?;
+        let file = self.files.len();
+        self.files.push(name.to_string());
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (index, line) in code.lines().enumerate() {
+            let number = index + 1;
+            let active = conditions.last().is_none_or(|condition| condition.active);
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        self.code.push_str(line);
+                        self.code.push('\n');
+                        self.origins.push((file, number));
+                    }
+                    continue;
+                }
+            };
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(keyword, argument)| {
+                    (keyword, argument.trim())
+                });
+            let error = |message: &str| anyhow!("{}:{}: {}", name, number, message);
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let defined = defines.contains(argument);
+                    conditions.push(Condition {
+                        parent: active,
+                        active: active && defined == (keyword == "ifdef"),
+                        has_else: false,
+                        line: number,
+                    });
+                }
+                "else" => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #ifdef"))?;
+                    if condition.has_else {
+                        return Err(error("second #else for one #ifdef"));
+                    }
+                    condition.has_else = true;
+                    condition.active = condition.parent && !condition.active;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef"))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    defines.insert(argument.to_string());
+                }
+                "include" => {
+                    let path = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .ok_or_else(|| error("expected #include \"file.wgsl\""))?;
+                    self.append(path, read, defines, included)
+                        .with_context(|| format!("included from {}:{}", name, number))?;
+                }
+                _ => return Err(error(&format!("unknown directive #{}", keyword))),
+            }
+        }
+        if let Some(condition) = conditions.last() {
+            bail!("{}:{}: #ifdef without #endif", name, condition.line);
+        }
+        Ok(())
+    }
+
+    /// Parses and validates the code with naga, the way wgpu would, but
+    /// reports problems by the file and line they came from instead of
+    /// panicking.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let module = naga::front::wgsl::parse_str(&self.code)
+            .map_err(|err| anyhow!("{}: {}", self.position(err.location(&self.code)), err))?;
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .map_err(|err| {
+            let mut message = err.to_string();
+            let mut source = std::error::Error::source(err.as_inner());
+            while let Some(cause) = source {
+                message = format!("{}: {}", message, cause);
+                source = cause.source();
+            }
+            // Spans run from the function to the expression that broke it.
+            let location = err
+                .spans()
+                .last()
+                .map(|(span, _)| span.location(&self.code));
+            anyhow!("{}: {}", self.position(location), message)
+        })?;
+        Ok(())
+    }
+
+    fn position(&self, location: Option<naga::SourceLocation>) -> String {
+        location
+            .and_then(|location| {
+                let (file, line) = self.origins.get(location.line_number as usize - 1)?;
+                Some(format!(
+                    "{}:{}:{}",
+                    self.files[*file], line, location.line_position
+                ))
+            })
+            .unwrap_or_else(|| "shader".to_string())
+    }
+}
+
+/// Loads, preprocesses and validates a shader, then hands it to the GPU.
+pub(crate) fn create_module(
+    device: &wgpu::Device,
+    name: &str,
+    defines: &[&str],
+    label: &str,
+) -> anyhow::Result<wgpu::ShaderModule> {
+    let source = ShaderSource::load(name, defines)
+        .and_then(|source| source.validate().map(|()| source))
+        .with_context(|| format!("{} {:?} is invalid", name, defines))?;
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.code.into()),
+    }))
+}
+
+/// Runs `create`, failing instead of panicking if wgpu finds anything it
+/// creates invalid, such as a pipeline whose shader doesn't fit its layout.
+pub(crate) fn catch_validation_errors<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(anyhow!("{}", err)),
+        None => created,
+    }
+}
+
+/// Validates every variant in `VARIANTS`, printing each problem.
+pub(crate) fn check_all() -> anyhow::Result<()> {
+    let mut failed = 0;
+    for (name, defines) in VARIANTS {
+        let checked = ShaderSource::load(name, defines).and_then(|source| source.validate());
+        match checked {
+            Ok(()) => println!("{} {:?}: ok", name, defines),
+            Err(err) => {
+                println!("{} {:?}: {:#}", name, defines, err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} shaders are invalid", failed, VARIANTS.len());
+    }
+    Ok(())
+}
+
+/// Notices when a shader in `SHADER_DIR` is saved by polling modification
+/// times, which needs no platform file watching.
+pub(crate) struct ShaderWatcher {
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            modified: latest_modification(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// True once for each round of changes, looking at most every
+    /// `POLL_INTERVAL`.
+    pub(crate) fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified = latest_modification();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn latest_modification() -> Option<SystemTime> {
+    std::fs::read_dir(SHADER_DIR)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension() == Some("wgsl".as_ref()))
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `name` from in-memory `files`.
+    fn load(files: &[(&str, &str)], name: &str, defines: &[&str]) -> anyhow::Result<ShaderSource> {
+        let read = |name: &str| {
+            files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, code)| code.to_string())
+                .with_context(|| format!("no shader named {}", name))
+        };
+        ShaderSource::load_with(name, defines, &read)
+    }
+
+    /// The kept lines of `code`, loaded alone.
+    fn kept(code: &str, defines: &[&str]) -> Vec<String> {
+        let source = load(&[("main.wgsl", code)], "main.wgsl", defines).unwrap();
+        source.code.lines().map(str::to_string).collect()
+    }
+
+    fn error(files: &[(&str, &str)]) -> String {
+        let err = load(files, "main.wgsl", &[]).err().unwrap();
+        format!("{:#}", err)
+    }
+
+    const NESTED: &str = "a
+#ifdef A
+b
+  #ifndef B
+c
+  #else
+d
+  #endif
+#else
+e
+#endif
+f";
+
+    #[test]
+    fn conditions_nest() {
+        assert_eq!(kept(NESTED, &[]), ["a", "e", "f"]);
+        assert_eq!(kept(NESTED, &["A"]), ["a", "b", "c", "f"]);
+        assert_eq!(kept(NESTED, &["A", "B"]), ["a", "b", "d", "f"]);
+        assert_eq!(kept(NESTED, &["B"]), ["a", "e", "f"]);
+    }
+
+    #[test]
+    fn defines_apply_to_later_lines_unless_dropped() {
+        let code = "#ifdef A\na\n#endif\n#define A\n#ifdef A\nb\n#endif";
+        assert_eq!(kept(code, &[]), ["b"]);
+        let code = "#ifdef X\n#define A\n#bogus\n#endif\n#ifdef A\na\n#endif";
+        assert!(kept(code, &[]).is_empty());
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let files = [
+            (
+                "main.wgsl",
+                "#include \"common.wgsl\"\n#include \"lib.wgsl\"\n#ifdef LIB\nmain\n#endif",
+            ),
+            ("lib.wgsl", "#include \"common.wgsl\"\n#define LIB\nlib"),
+            ("common.wgsl", "common"),
+        ];
+        let source = load(&files, "main.wgsl", &[]).unwrap();
+        assert_eq!(source.code, "common\nlib\nmain\n");
+        assert_eq!(source.files, ["main.wgsl", "common.wgsl", "lib.wgsl"]);
+    }
+
+    #[test]
+    fn directive_errors_give_their_line() {
+        let cases = [
+            ("a\n#else", "main.wgsl:2: #else without #ifdef"),
+            ("#endif", "main.wgsl:1: #endif without #ifdef"),
+            (
+                "#ifdef A\n#else\n#else\n#endif",
+                "main.wgsl:3: second #else for one #ifdef",
+            ),
+            (
+                "a\n#ifndef A\n#ifdef B\n#endif",
+                "main.wgsl:2: #ifdef without #endif",
+            ),
+            ("#pragma once", "main.wgsl:1: unknown directive #pragma"),
+            (
+                "#include common.wgsl",
+                "main.wgsl:1: expected #include \"file.wgsl\"",
+            ),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(error(&[("main.wgsl", code)]), expected);
+        }
+    }
+
+    #[test]
+    fn missing_includes_say_where_they_were_included() {
+        let files = [
+            ("main.wgsl", "\n#include \"lib.wgsl\""),
+            ("lib.wgsl", "#include \"gone.wgsl\""),
+        ];
+        assert_eq!(
+            error(&files),
+            "included from main.wgsl:2: included from lib.wgsl:1: no shader named gone.wgsl"
+        );
+    }
+
+    #[test]
+    fn positions_map_back_to_their_file_and_line() {
+        let files = [
+            (
+                "main.wgsl",
+                "#ifdef A\ndropped\n#endif\nfn main() {}\n#include \"lib.wgsl\"",
+            ),
+            ("lib.wgsl", "\n// Comment\nfn lib() -> f32 { return 1; }"),
+        ];
+        let source = load(&files, "main.wgsl", &[]).unwrap();
+        let at = |line_number, line_position| {
+            source.position(Some(naga::SourceLocation {
+                line_number,
+                line_position,
+                offset: 0,
+                length: 0,
+            }))
+        };
+        assert_eq!(at(1, 4), "main.wgsl:4:4");
+        assert_eq!(at(4, 19), "lib.wgsl:3:19");
+        assert_eq!(at(5, 1), "shader");
+        assert_eq!(source.position(None), "shader");
+
+        // Validation errors in included code point into the included file.
+        let err = source.validate().unwrap_err().to_string();
+        assert!(err.starts_with("lib.wgsl:3:"), "{}", err);
+    }
+}
@@ -0,0 +1,458 @@
+use cgmath::{
+    Angle, Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4,
+};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX},
+    instance,
+    level::{LevelBatch, LoadedLayer},
+    light::{Lighting, PointLight},
+    model::{self, Vertex},
+    shaders, texture,
+};
+
+/// Sun shadow cascades, nearest first.
+const CASCADES: usize = 3;
+/// Spot lights past this count don't cast shadows.
+const MAX_SPOT_SHADOWS: usize = 4;
+const SHADOW_LAYERS: usize = CASCADES + MAX_SPOT_SHADOWS;
+const SHADOW_MAP_SIZE: u32 = 1024;
+/// Sun shadows fade out past this view depth.
+const SHADOW_DISTANCE: f32 = 40.0;
+/// How far behind a cascade the sun still picks up casters.
+const CASTER_MARGIN: f32 = 20.0;
+/// Blend between uniform (0) and logarithmic (1) cascade splits.
+const SPLIT_LAMBDA: f32 = 0.6;
+/// Receivers are pushed this far along their normal, in shadow map texels.
+const NORMAL_OFFSET_TEXELS: f32 = 1.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowPassUniform {
+    view_proj: [[f32; 4]; 4],
+    /// Direction the light travels in, to turn billboards towards it.
+    light_direction: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; SHADOW_LAYERS],
+    /// Far view depth of each cascade, then the shadow distance.
+    cascade_splits: [f32; 4],
+    /// World size of a shadow texel for each cascade, then the texel size in uv.
+    texel_sizes: [f32; 4],
+}
+
+/// Shadow maps for the sun and spot lights, rendered into one depth texture
+/// array before the main pass.
+pub(crate) struct ShadowMaps {
+    layer_views: Vec<wgpu::TextureView>,
+    pass_buffers: Vec<wgpu::Buffer>,
+    pass_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    /// Cascades plus the spot lights that got a map this frame.
+    active_layers: usize,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMaps {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let map = texture::Texture::create_shadow_map(
+            device,
+            SHADOW_MAP_SIZE,
+            SHADOW_LAYERS as u32,
+            "shadow_map",
+        );
+        let layer_views = (0..SHADOW_LAYERS as u32)
+            .map(|layer| {
+                map.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_map_layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+        let pass_buffers: Vec<_> = (0..SHADOW_LAYERS)
+            .map(|_| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shadow Pass Buffer"),
+                    contents: bytemuck::cast_slice(&[ShadowPassUniform {
+                        view_proj: Matrix4::identity().into(),
+                        light_direction: [0.0, -1.0, 0.0, 0.0],
+                    }]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        let pass_bind_groups = pass_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pass_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("shadow_pass_bind_group"),
+                })
+            })
+            .collect();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: [Matrix4::identity().into(); SHADOW_LAYERS],
+                cascade_splits: [0.0; 4],
+                texel_sizes: [0.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[texture_bind_group_layout, &pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders::create_module(device, "shadow.wgsl", &[], "Shadow Shader")?;
+        let pipeline = shadow_pipeline_init(device, &pipeline_layout, &shader);
+
+        Ok(Self {
+            layer_views,
+            pass_buffers,
+            pass_bind_groups,
+            pipeline_layout,
+            pipeline,
+            uniform_buffer,
+            active_layers: CASCADES,
+            bind_group_layout,
+            bind_group,
+        })
+    }
+
+    /// Rebuilds the pipeline from the shader on disk, keeping the old one if
+    /// the new shader or pipeline doesn't validate.
+    pub(crate) fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        self.pipeline = shaders::catch_validation_errors(device, || {
+            let shader = shaders::create_module(device, "shadow.wgsl", &[], "Shadow Shader")?;
+            Ok(shadow_pipeline_init(device, &self.pipeline_layout, &shader))
+        })?;
+        Ok(())
+    }
+
+    /// Fits the sun cascades to the camera and points a map down each of the
+    /// first spot lights. Returns the shadow map layer given to each light.
+    pub(crate) fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+        lighting: &Lighting,
+        lights: &[PointLight],
+    ) -> Vec<Option<u32>> {
+        let mut view_proj = [Matrix4::identity(); SHADOW_LAYERS];
+        let mut directions = [Vector3::new(0.0, -1.0, 0.0); SHADOW_LAYERS];
+
+        let sun = lighting.sun_direction.normalize();
+        let near = projection.znear;
+        let far = projection.zfar.min(SHADOW_DISTANCE);
+        let mut cascade_splits = [far; 4];
+        let mut texel_sizes = [0.0; 4];
+        let mut split_near = near;
+        for cascade in 0..CASCADES {
+            let t = (cascade + 1) as f32 / CASCADES as f32;
+            let uniform = near + (far - near) * t;
+            let logarithmic = near * (far / near).powf(t);
+            let split_far = uniform + (logarithmic - uniform) * SPLIT_LAMBDA;
+
+            let (matrix, texel) = cascade_matrix(camera, projection, split_near, split_far, sun);
+            view_proj[cascade] = matrix;
+            directions[cascade] = sun;
+            cascade_splits[cascade] = split_far;
+            texel_sizes[cascade] = texel;
+            split_near = split_far;
+        }
+        texel_sizes[3] = 1.0 / SHADOW_MAP_SIZE as f32;
+
+        let mut layers = vec![None; lights.len()];
+        let mut next = CASCADES;
+        for (layer, light) in layers.iter_mut().zip(lights) {
+            let Some(spot) = light.spot else { continue };
+            if next == SHADOW_LAYERS {
+                break;
+            }
+            let direction = spot.direction.normalize();
+            let fovy = if spot.outer_angle > Deg(85.0) {
+                Deg(170.0)
+            } else {
+                spot.outer_angle * 2.0
+            };
+            let view = Matrix4::look_to_rh(
+                Point3::from_vec(light.position),
+                direction,
+                up_for(direction),
+            );
+            let proj = cgmath::perspective(fovy, 1.0, 0.05, light.range);
+            view_proj[next] = OPENGL_TO_WGPU_MATRIX * proj * view;
+            directions[next] = direction;
+            *layer = Some(next as u32);
+            next += 1;
+        }
+        self.active_layers = next;
+
+        for layer in 0..self.active_layers {
+            let direction = directions[layer];
+            let uniform = ShadowPassUniform {
+                view_proj: view_proj[layer].into(),
+                light_direction: [direction.x, direction.y, direction.z, 0.0],
+            };
+            queue.write_buffer(
+                &self.pass_buffers[layer],
+                0,
+                bytemuck::cast_slice(&[uniform]),
+            );
+        }
+        let uniform = ShadowUniform {
+            view_proj: view_proj.map(Into::into),
+            cascade_splits,
+            texel_sizes,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        layers
+    }
+
+    /// Renders every layer's depth into the active shadow maps.
+    pub(crate) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        layers: &[LoadedLayer],
+        batch: &LevelBatch,
+    ) {
+        for (view, pass_bind_group) in self
+            .layer_views
+            .iter()
+            .zip(&self.pass_bind_groups)
+            .take(self.active_layers)
+        {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            shadow_pass.set_pipeline(&self.pipeline);
+            shadow_pass.set_bind_group(1, pass_bind_group, &[]);
+            batch.bind(&mut shadow_pass);
+            for layer in layers {
+                batch.bind_textures(&mut shadow_pass, layer);
+                shadow_pass.set_vertex_buffer(1, layer.instance_buffer.slice(..));
+                shadow_pass.draw_indexed(
+                    layer.first_index..layer.first_index + layer.num_indices,
+                    layer.base_vertex,
+                    0..layer.instance_count,
+                );
+            }
+        }
+    }
+}
+
+/// Any up vector that isn't parallel to `direction`.
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+/// Orthographic sun matrix covering the camera frustum between `near` and
+/// `far`, and the world size of one of its texels.
+fn cascade_matrix(
+    camera: &Camera,
+    projection: &Projection,
+    near: f32,
+    far: f32,
+    sun: Vector3<f32>,
+) -> (Matrix4<f32>, f32) {
+    let forward = camera.forward();
+    let right = forward.cross(Vector3::unit_y()).normalize();
+    let up = right.cross(forward);
+    let tan_y = (projection.fovy / 2.0).tan();
+    let tan_x = tan_y * projection.aspect;
+
+    let mut corners = Vec::with_capacity(8);
+    for depth in [near, far] {
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                corners.push(
+                    camera.position.to_vec()
+                        + forward * depth
+                        + right * (x * tan_x * depth)
+                        + up * (y * tan_y * depth),
+                );
+            }
+        }
+    }
+    let centre = corners
+        .iter()
+        .fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + c)
+        / 8.0;
+    // A bounding sphere keeps the cascade the same size as the camera turns,
+    // rounded so it doesn't flicker by tiny amounts either.
+    let radius = corners
+        .iter()
+        .map(|corner| (corner - centre).magnitude())
+        .fold(0.0_f32, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let eye = Point3::from_vec(centre - sun * (radius + CASTER_MARGIN));
+    let view = Matrix4::look_to_rh(eye, sun, up_for(sun));
+    let proj = cgmath::ortho(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_MARGIN,
+    );
+    let mut view_proj = OPENGL_TO_WGPU_MATRIX * proj * view;
+
+    // Snap to whole texels so shadow edges don't crawl as the camera moves.
+    let half_size = SHADOW_MAP_SIZE as f32 / 2.0;
+    let origin = view_proj * Vector4::new(0.0, 0.0, 0.0, 1.0) * half_size;
+    let offset = Vector3::new(
+        (origin.x.round() - origin.x) / half_size,
+        (origin.y.round() - origin.y) / half_size,
+        0.0,
+    );
+    view_proj = Matrix4::from_translation(offset) * view_proj;
+
+    (
+        view_proj,
+        2.0 * radius / SHADOW_MAP_SIZE as f32 * NORMAL_OFFSET_TEXELS,
+    )
+}
+
+fn shadow_pipeline_init(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+        },
+        // Only used to discard transparent texels.
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Billboards turn to the light, so their winding isn't fixed.
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
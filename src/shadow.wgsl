@@ -0,0 +1,77 @@
+// Depth-only pass for the shadow maps. Shares the vertex layout and the
+// texture bind group with the main shader so transparent texels can be
+// discarded.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(10) material: u32,
+    @location(11) billboard: u32,
+    @location(12) billboard_size: f32,
+    @location(13) uv_offset: vec2<f32>,
+    @location(14) uv_scale: vec2<f32>,
+};
+
+#include "billboard.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+struct ShadowPass {
+    view_proj: mat4x4<f32>,
+    light_direction: vec4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> shadow_pass: ShadowPass;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) material: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let origin = model_matrix * vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    var world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    if (instance.billboard == BILLBOARD_SCREEN_SIZE) {
+        // No size in the world to cast a shadow with.
+        world_position = origin;
+    } else if (instance.billboard != BILLBOARD_NONE) {
+        // Billboards face the light, the way they face the camera.
+        let basis = billboard_basis(instance.billboard, -shadow_pass.light_direction.xyz);
+        world_position = origin + vec4<f32>(basis * model.position * instance.billboard_size, 0.0);
+    }
+    var out: VertexOutput;
+    out.tex_coords = instance.uv_offset + model.tex_coords * instance.uv_scale;
+    out.material = instance.material;
+    out.clip_position = shadow_pass.view_proj * world_position;
+    return out;
+}
+
+#include "material.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) {
+    // Alpha-tested, so sprites cast their outline rather than a quad.
+    let material = materials[in.material];
+    let alpha = textureSample(t_diffuse, s_diffuse, in.tex_coords, i32(material.texture)).a * material.tint.a;
+    if (alpha < 0.5) {
+        discard;
+    }
+}
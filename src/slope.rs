@@ -1,4 +1,50 @@
-use crate::model::ModelVertex;
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+};
+
+/// Side of a tile, named after the side a ramp rises towards.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// +z
+    Forward,
+    /// -z
+    Backward,
+    /// -x
+    Left,
+    /// +x
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Corner {
+    ForwardLeft,
+    ForwardRight,
+    BackwardLeft,
+    BackwardRight,
+}
+
+impl Corner {
+    /// Signs of the corner along x and z.
+    fn signs(self) -> (f32, f32) {
+        match self {
+            Corner::ForwardLeft => (-1.0, 1.0),
+            Corner::ForwardRight => (1.0, 1.0),
+            Corner::BackwardLeft => (-1.0, -1.0),
+            Corner::BackwardRight => (1.0, -1.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SlopeOrientation {
+    /// Ramp rising towards one side.
+    Ramp(Direction),
+    /// Wedge that is only high at the given corner.
+    OuterCorner(Corner),
+    /// Wedge that is only low at the corner opposite the given one.
+    InnerCorner(Corner),
+}
 
 pub(crate) struct Slope {
     pub width: f32,
@@ -6,161 +52,94 @@ pub(crate) struct Slope {
     pub depth: f32,
     pub vertexes: Vec<ModelVertex>,
     pub indices: Vec<u16>,
-    pub orientation: i32,
+    pub orientation: SlopeOrientation,
 }
 
 impl Slope {
-    pub(crate) fn new(width: f32, height: f32, depth: f32, orientation: &str) -> Self {
-        if orientation == "forward" {
-            Self {
-                width,
-                height,
-                depth,
-                vertexes: vec![
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [1.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [0.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, 1.0 * height, 1.0 * depth],
-                        tex_coords: [0.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [1.0 * width, 1.0 * height, 1.0 * depth],
-                        tex_coords: [1.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                ],
-                indices: vec![0, 1, 2, 2, 3, 0],
-                orientation: 1,
-            }
-        } else if orientation == "backward" {
-            Self {
-                width,
-                height,
-                depth,
-                vertexes: vec![
-                    ModelVertex {
-                        position: [1.0 * width, 1.0 * height, -1.0 * depth],
-                        tex_coords: [1.0 * width, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, 1.0 * height, -1.0 * depth],
-                        tex_coords: [0.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [0.0, 1.0 * height],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [1.0 * width, 1.0 * height],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                ],
-                indices: vec![0, 1, 2, 2, 3, 0],
-                orientation: 2,
-            }
-        } else if orientation == "left" {
-            Self {
-                width,
-                height,
-                depth,
-                vertexes: vec![
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [1.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, 1.0 * height, -1.0 * depth],
-                        tex_coords: [0.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, 1.0 * height, 1.0 * depth],
-                        tex_coords: [0.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [1.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
+    pub(crate) fn new(width: f32, height: f32, depth: f32, orientation: SlopeOrientation) -> Self {
+        let mut slope = Self {
+            width,
+            height,
+            depth,
+            vertexes: Vec::new(),
+            indices: Vec::new(),
+            orientation,
+        };
+
+        // Top surface corners, counter-clockwise seen from above.
+        let corners = [(1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+        let top = corners
+            .iter()
+            .map(|&(sx, sz)| ModelVertex {
+                position: [
+                    sx * width,
+                    slope.surface_height(sx * width, sz * depth),
+                    sz * depth,
                 ],
-                indices: vec![0, 1, 2, 2, 3, 0],
-                orientation: 3,
+                tex_coords: [(sx + 1.0) / 2.0, (sz + 1.0) / 2.0],
+                normal: [0.0, 0.0, 0.0],
+                tangent: [0.0; 4],
+                lightmap_coords: [0.0; 2],
+                ao: 1.0,
+            })
+            .collect::<Vec<_>>();
+
+        // Corner wedges are two planes meeting on the diagonal through the named corner.
+        let triangles: [usize; 6] = match orientation {
+            SlopeOrientation::OuterCorner(corner) | SlopeOrientation::InnerCorner(corner)
+                if corner.signs().0 == corner.signs().1 =>
+            {
+                [1, 2, 3, 3, 0, 1]
             }
-        } else if orientation == "right" {
-            Self {
-                width,
-                height,
-                depth,
-                vertexes: vec![
-                    ModelVertex {
-                        position: [1.0 * width, 1.0 * height, -1.0 * depth],
-                        tex_coords: [1.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [0.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [0.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [1.0 * width, 1.0 * height, 1.0 * depth],
-                        tex_coords: [1.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                ],
-                indices: vec![0, 1, 2, 2, 3, 0],
-                orientation: 4,
+            _ => [0, 1, 2, 2, 3, 0],
+        };
+
+        // Unshared vertices, so each plane keeps its own normal.
+        slope.vertexes = triangles.iter().map(|&i| top[i]).collect();
+        slope.indices = (0..triangles.len() as u16).collect();
+        model::compute_normals(&mut slope.vertexes, &slope.indices);
+        model::compute_tangents(&mut slope.vertexes, &slope.indices);
+
+        slope
+    }
+}
+
+impl Primitive for Slope {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.width, self.height, self.depth)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Ramp
+    }
+
+    /// Height of the walkable surface above the slope's centre, for a point given
+    /// relative to the centre. Points outside the footprint are clamped onto it.
+    fn surface_height(&self, x: f32, z: f32) -> f32 {
+        let x = (x / self.width).clamp(-1.0, 1.0);
+        let z = (z / self.depth).clamp(-1.0, 1.0);
+        let t = match self.orientation {
+            SlopeOrientation::Ramp(Direction::Forward) => z,
+            SlopeOrientation::Ramp(Direction::Backward) => -z,
+            SlopeOrientation::Ramp(Direction::Left) => -x,
+            SlopeOrientation::Ramp(Direction::Right) => x,
+            SlopeOrientation::OuterCorner(corner) => {
+                let (sx, sz) = corner.signs();
+                (sx * x).min(sz * z)
             }
-        } else {
-            Self {
-                width,
-                height,
-                depth,
-                vertexes: vec![
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [1.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, -1.0 * depth],
-                        tex_coords: [0.0, 0.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [-1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [0.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                    ModelVertex {
-                        position: [1.0 * width, -1.0 * height, 1.0 * depth],
-                        tex_coords: [1.0, 1.0],
-                        normal: [0.0, 0.0, 0.0],
-                    },
-                ],
-                indices: vec![0, 1, 2, 2, 3, 0],
-                orientation: 0,
+            SlopeOrientation::InnerCorner(corner) => {
+                let (sx, sz) = corner.signs();
+                (sx * x).max(sz * z)
             }
-        }
+        };
+        t * self.height
     }
 }
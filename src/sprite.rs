@@ -1,4 +1,7 @@
-use crate::model::ModelVertex;
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+};
 
 pub(crate) struct Sprite {
     pub width: f32,
@@ -9,7 +12,7 @@ pub(crate) struct Sprite {
 
 impl Sprite {
     pub(crate) fn new(width: f32, height: f32) -> Self {
-        Self {
+        let mut sprite = Self {
             width,
             height,
             vertexes: vec![
@@ -17,25 +20,57 @@ impl Sprite {
                 ModelVertex {
                     position: [-1.0 * width, -1.0 * height, 0.0],
                     tex_coords: [0.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, -1.0 * height, 0.0],
                     tex_coords: [1.0, 1.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [1.0 * width, 1.0 * height, 0.0],
                     tex_coords: [1.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
                 ModelVertex {
                     position: [-1.0 * width, 1.0 * height, 0.0],
                     tex_coords: [0.0, 0.0],
-                    normal: [0.0, 0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    tangent: [0.0; 4],
+                    lightmap_coords: [0.0; 2],
+                    ao: 1.0,
                 },
             ],
             indices: vec![0, 1, 2, 2, 3, 0],
-        }
+        };
+        model::compute_tangents(&mut sprite.vertexes, &sprite.indices);
+        sprite
+    }
+}
+
+impl Primitive for Sprite {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.width, self.height, 0.0)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::None
     }
 }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context};
+use cgmath::{EuclideanSpace, Point3, Rotation, Vector3};
+use serde::Deserialize;
+
+use crate::instance::{Instance, UvRect};
+
+/// A grid of frames in one texture, played back as named animations:
+///
+/// ```toml
+/// [sheet]
+/// columns = 4
+/// rows = 1
+/// directions = 8
+///
+/// [sheet.animations.walk]
+/// frames = [0, 1, 2, 3]
+/// fps = 6.0
+/// ```
+///
+/// Frames are numbered across then down the `columns` by `rows` grid.
+/// Directional sheets repeat that grid below itself for each of their
+/// `directions`, starting with the sprite seen from the front and going
+/// round to its left 360 / `directions` degrees at a time, so a sheet of
+/// eight has the sprite's left side third.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SpriteSheet {
+    pub columns: u32,
+    pub rows: u32,
+    #[serde(default = "one")]
+    pub directions: u32,
+    #[serde(default)]
+    pub animations: HashMap<String, Animation>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Animation {
+    pub frames: Vec<u32>,
+    pub fps: f32,
+    /// Otherwise the animation holds its last frame.
+    #[serde(default = "yes")]
+    pub looping: bool,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl SpriteSheet {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.columns == 0 || self.rows == 0 {
+            bail!("a sprite sheet needs at least one column and row");
+        }
+        if ![1, 8].contains(&self.directions) {
+            bail!("sprites have 1 or 8 directions, not {}", self.directions);
+        }
+        let frames = self.columns * self.rows;
+        for (name, animation) in &self.animations {
+            if animation.frames.is_empty() || animation.fps <= 0.0 {
+                bail!("animation `{}` needs frames and a positive fps", name);
+            }
+            if let Some(frame) = animation.frames.iter().find(|&&frame| frame >= frames) {
+                bail!("animation `{}` has frame {} of {}", name, frame, frames);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn animation(&self, name: &str) -> anyhow::Result<&Animation> {
+        self.animations
+            .get(name)
+            .with_context(|| format!("no sprite animation named `{}`", name))
+    }
+
+    /// Where `frame` is in the texture, seen from `direction`.
+    pub(crate) fn uv(&self, frame: u32, direction: u32) -> UvRect {
+        let scale = [
+            1.0 / self.columns as f32,
+            1.0 / (self.rows * self.directions) as f32,
+        ];
+        let column = frame % self.columns;
+        let row = direction * self.rows + frame / self.columns;
+        UvRect {
+            offset: [column as f32 * scale[0], row as f32 * scale[1]],
+            scale,
+        }
+    }
+
+    /// Which direction `instance` is seen from by a camera at `eye`, by the
+    /// angle about the up axis between where it faces, +Z turned by its
+    /// rotation, and the camera.
+    pub(crate) fn direction(&self, instance: &Instance, eye: Point3<f32>) -> u32 {
+        if self.directions == 1 {
+            return 0;
+        }
+        let facing = instance.rotation.rotate_vector(Vector3::unit_z());
+        let to_eye = eye - Point3::from_vec(instance.position);
+        if to_eye.x == 0.0 && to_eye.z == 0.0 {
+            return 0;
+        }
+        let angle = to_eye.x.atan2(to_eye.z) - facing.x.atan2(facing.z);
+        let step = std::f32::consts::TAU / self.directions as f32;
+        ((angle / step).round() as i32).rem_euclid(self.directions as i32) as u32
+    }
+}
+
+/// How far an instance is through its animation.
+#[derive(Clone, Debug)]
+pub(crate) struct SpritePlayback {
+    animation: String,
+    time: f32,
+}
+
+impl SpritePlayback {
+    pub(crate) fn new(animation: &str) -> Self {
+        Self {
+            animation: animation.to_string(),
+            time: 0.0,
+        }
+    }
+
+    pub(crate) fn advance(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    /// The frame showing now, or the sheet's first if the animation isn't
+    /// in it.
+    pub(crate) fn frame(&self, sheet: &SpriteSheet) -> u32 {
+        let animation = match sheet.animations.get(&self.animation) {
+            Some(animation) => animation,
+            None => return 0,
+        };
+        let count = animation.frames.len();
+        let index = (self.time * animation.fps) as usize;
+        let index = if animation.looping {
+            index % count
+        } else {
+            index.min(count - 1)
+        };
+        animation.frames[index]
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    model::{self, ModelVertex},
+    primitive::{Bounds, CollisionShape, Primitive},
+    slope::Direction,
+};
+
+pub(crate) struct Stairs {
+    pub width: f32,
+    pub height: f32,
+    pub depth: f32,
+    pub steps: u32,
+    pub vertexes: Vec<ModelVertex>,
+    pub indices: Vec<u16>,
+    pub orientation: Direction,
+}
+
+impl Stairs {
+    pub(crate) fn new(width: f32, height: f32, depth: f32, steps: u32, orientation: Direction) -> Self {
+        let steps = steps.max(1);
+        let mut stairs = Self {
+            width,
+            height,
+            depth,
+            steps,
+            vertexes: Vec::new(),
+            indices: Vec::new(),
+            orientation,
+        };
+
+        // Built rising towards +t, then turned into place.
+        let (across, along) = stairs.extents();
+        let run = 2.0 * along / steps as f32;
+        let rise = stairs.step_height();
+        for k in 0..steps {
+            let t0 = -along + k as f32 * run;
+            let t1 = t0 + run;
+            let bottom = -height + k as f32 * rise;
+            let top = bottom + rise;
+
+            // Tread
+            stairs.push_quad([
+                ([across, top, t0], [1.0, 0.0]),
+                ([-across, top, t0], [0.0, 0.0]),
+                ([-across, top, t1], [0.0, 1.0]),
+                ([across, top, t1], [1.0, 1.0]),
+            ]);
+            // Riser
+            stairs.push_quad([
+                ([-across, top, t0], [1.0, 0.0]),
+                ([across, top, t0], [0.0, 0.0]),
+                ([across, bottom, t0], [0.0, 1.0]),
+                ([-across, bottom, t0], [1.0, 1.0]),
+            ]);
+            // Sides, down to the ground so the steps read as solid blocks.
+            let (u0, u1) = (k as f32 / steps as f32, (k + 1) as f32 / steps as f32);
+            let v = 1.0 - u1;
+            stairs.push_quad([
+                ([across, -height, t0], [u0, 1.0]),
+                ([across, top, t0], [u0, v]),
+                ([across, top, t1], [u1, v]),
+                ([across, -height, t1], [u1, 1.0]),
+            ]);
+            stairs.push_quad([
+                ([-across, -height, t1], [1.0 - u1, 1.0]),
+                ([-across, top, t1], [1.0 - u1, v]),
+                ([-across, top, t0], [1.0 - u0, v]),
+                ([-across, -height, t0], [1.0 - u0, 1.0]),
+            ]);
+        }
+        model::compute_normals(&mut stairs.vertexes, &stairs.indices);
+        model::compute_tangents(&mut stairs.vertexes, &stairs.indices);
+
+        stairs
+    }
+
+    /// Half extents across and along the direction of travel.
+    fn extents(&self) -> (f32, f32) {
+        match self.orientation {
+            Direction::Forward | Direction::Backward => (self.width, self.depth),
+            Direction::Left | Direction::Right => (self.depth, self.width),
+        }
+    }
+
+    /// Maps a point in the stairs' own frame (`s` across, `t` along) to the tile frame.
+    fn to_local(&self, s: f32, t: f32) -> (f32, f32) {
+        match self.orientation {
+            Direction::Forward => (s, t),
+            Direction::Backward => (-s, -t),
+            Direction::Right => (t, -s),
+            Direction::Left => (-t, s),
+        }
+    }
+
+    fn push_quad(&mut self, corners: [([f32; 3], [f32; 2]); 4]) {
+        let base = self.vertexes.len() as u16;
+        for ([s, y, t], tex_coords) in corners {
+            let (x, z) = self.to_local(s, t);
+            self.vertexes.push(ModelVertex {
+                position: [x, y, z],
+                tex_coords,
+                normal: [0.0, 0.0, 0.0],
+                tangent: [0.0; 4],
+                lightmap_coords: [0.0; 2],
+                ao: 1.0,
+            });
+        }
+        self.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+    }
+
+    pub(crate) fn step_height(&self) -> f32 {
+        2.0 * self.height / self.steps as f32
+    }
+}
+
+impl Primitive for Stairs {
+    fn vertexes(&self) -> &[ModelVertex] {
+        &self.vertexes
+    }
+
+    fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds::centred(self.width, self.height, self.depth)
+    }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Steps
+    }
+
+    /// Height of the tread under a point given relative to the centre, clamped onto
+    /// the footprint.
+    fn surface_height(&self, x: f32, z: f32) -> f32 {
+        let (_, along) = self.extents();
+        let t = match self.orientation {
+            Direction::Forward => z,
+            Direction::Backward => -z,
+            Direction::Right => x,
+            Direction::Left => -x,
+        };
+        let t = ((t + along) / (2.0 * along)).clamp(0.0, 1.0);
+        let step = ((t * self.steps as f32) as u32).min(self.steps - 1);
+        -self.height + (step + 1) as f32 * self.step_height()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn risers_face_down_the_stairs_and_sides_face_outwards() {
+        for (orientation, up) in [
+            (Direction::Forward, [0.0, 1.0]),
+            (Direction::Backward, [0.0, -1.0]),
+            (Direction::Right, [1.0, 0.0]),
+            (Direction::Left, [-1.0, 0.0]),
+        ] {
+            let stairs = Stairs::new(1.0, 1.0, 2.0, 4, orientation);
+            let (mut risers, mut sides) = (0, 0);
+            for vertex in &stairs.vertexes {
+                let [x, _, z] = vertex.position;
+                let [nx, ny, nz] = vertex.normal;
+                if ny.abs() > 0.5 {
+                    continue;
+                }
+                let along = nx * up[0] + nz * up[1];
+                if along.abs() > 0.5 {
+                    assert!(along < -0.99, "{orientation:?}: {vertex:?}");
+                    risers += 1;
+                } else {
+                    assert!(x * nx + z * nz > 0.0, "{orientation:?}: {vertex:?}");
+                    sides += 1;
+                }
+            }
+            assert_eq!((risers, sides), (4 * 4, 4 * 8));
+        }
+    }
+}
@@ -2,15 +2,17 @@ use cgmath::Rotation3;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    instance::{self, Instance},
+    instance::{self, Billboard, Instance, UvRect},
     model::{self, ModelVertex, Vertex},
-    texture, MapTiles,
+    level::MapTiles,
+    material::{BlendMode, MaterialTextures},
+    postprocess, texture,
 };
 
-pub(crate) fn create_buffers(
+pub(crate) fn create_buffers<I: bytemuck::Pod>(
     device: &wgpu::Device,
     vertexes: &[ModelVertex],
-    indices: &[u16],
+    indices: &[I],
 ) -> (wgpu::Buffer, wgpu::Buffer, u32) {
     let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Vertex Buffer"),
@@ -28,12 +30,12 @@ pub(crate) fn create_buffers(
 
 pub(crate) fn init_config(
     surface: &wgpu::Surface,
-    adapter: wgpu::Adapter,
+    adapter: &wgpu::Adapter,
     size: winit::dpi::PhysicalSize<u32>,
 ) -> wgpu::SurfaceConfiguration {
     wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: surface.get_supported_formats(&adapter)[0],
+        format: surface.get_supported_formats(adapter)[0],
         width: size.width,
         height: size.height,
         present_mode: wgpu::PresentMode::Fifo,
@@ -48,7 +50,7 @@ pub(crate) fn texture_bind_group_layout_init(device: &wgpu::Device) -> wgpu::Bin
                 visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Texture {
                     multisampled: false,
-                    view_dimension: wgpu::TextureViewDimension::D2,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
                     sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
                 count: None,
@@ -61,95 +63,171 @@ pub(crate) fn texture_bind_group_layout_init(device: &wgpu::Device) -> wgpu::Bin
                 ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                 count: None,
             },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Normal, roughness/metallic and emissive maps, sampled like the
+            // diffuse textures.
+            map_array_entry(5),
+            map_array_entry(6),
+            map_array_entry(7),
         ],
         label: Some("texture_bind_group_layout"),
     })
 }
 
+fn map_array_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2Array,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
 pub(crate) fn camera_bind_init(
     device: &wgpu::Device,
     camera_buffer: &wgpu::Buffer,
+    light_buffer: &wgpu::Buffer,
 ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
     let camera_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("camera_bind_group_layout"),
         });
     let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         layout: &camera_bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: camera_buffer.as_entire_binding(),
-        }],
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: light_buffer.as_entire_binding(),
+            },
+        ],
         label: Some("camera_bind_group"),
     });
     (camera_bind_group_layout, camera_bind_group)
 }
 
-pub(crate) fn create_texture(
+/// Binds a level's texture arrays and material buffer, every array sampled
+/// with `sampler` apart from the lightmaps.
+pub(crate) fn create_texture_bind_group(
     device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    texture_bytes: &[u8],
     texture_bind_group_layout: &wgpu::BindGroupLayout,
+    textures: &MaterialTextures,
+    sampler: &wgpu::Sampler,
+    material_buffer: &wgpu::Buffer,
 ) -> wgpu::BindGroup {
-    let img_texture =
-        texture::Texture::from_bytes(device, queue, texture_bytes, "texture").unwrap();
-    let img_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
         layout: texture_bind_group_layout,
         entries: &[
+            texture_view_entry(0, &textures.diffuse),
             wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&img_texture.view),
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
             },
             wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&img_texture.sampler),
+                binding: 2,
+                resource: material_buffer.as_entire_binding(),
+            },
+            texture_view_entry(3, &textures.lightmaps),
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&textures.lightmaps.sampler),
             },
+            texture_view_entry(5, &textures.normal),
+            texture_view_entry(6, &textures.roughness_metallic),
+            texture_view_entry(7, &textures.emissive),
         ],
-        label: Some("wall_bind_group"),
-    });
-    img_bind_group
+        label: Some("texture_bind_group"),
+    })
+}
+
+fn texture_view_entry(binding: u32, texture: &texture::Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(&texture.view),
+    }
 }
 
+/// The main pass's pipeline for one blend mode. Alpha-tested surfaces use
+/// the shader built with `ALPHA_TEST`.
 pub(crate) fn pipeline_init(
     device: &wgpu::Device,
-    texture_bind_group_layout: wgpu::BindGroupLayout,
-    camera_bind_group_layout: wgpu::BindGroupLayout,
-    config: &wgpu::SurfaceConfiguration,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    blend_mode: BlendMode,
+    sample_count: u32,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("Shader"),
-        source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-    });
-    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Render Pipeline Layout"),
-        bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-        push_constant_ranges: &[],
-    });
+    let (blend, depth_write_enabled) = match blend_mode {
+        BlendMode::Opaque | BlendMode::AlphaTested => (None, true),
+        BlendMode::Blended => (Some(wgpu::BlendState::ALPHA_BLENDING), false),
+    };
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
-        layout: Some(&render_pipeline_layout),
+        layout: Some(render_pipeline_layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
-                format: config.format,
-                //blend: Some(wgpu::BlendState::REPLACE),
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                format: postprocess::HDR_FORMAT,
+                blend,
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -167,13 +245,13 @@ pub(crate) fn pipeline_init(
         },
         depth_stencil: Some(wgpu::DepthStencilState {
             format: texture::Texture::DEPTH_FORMAT,
-            depth_write_enabled: true,
+            depth_write_enabled,
             depth_compare: wgpu::CompareFunction::Less, // 1.
             stencil: wgpu::StencilState::default(),     // 2.
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -182,14 +260,14 @@ pub(crate) fn pipeline_init(
     render_pipeline
 }
 
-pub(crate) fn instance_init(
-    device: &wgpu::Device,
-    tiles: MapTiles,
+pub(crate) fn tile_instances(
+    tiles: &MapTiles,
     width: f32,
     height: f32,
     depth: f32,
-) -> (Vec<Instance>, wgpu::Buffer) {
-    let instances = (0..tiles.depth)
+    billboard: Billboard,
+) -> Vec<Instance> {
+    (0..tiles.depth)
         .flat_map(|z| {
             (0..tiles.width)
                 .map(move |x| (x, z))
@@ -208,29 +286,20 @@ pub(crate) fn instance_init(
                 cgmath::Vector3::unit_z(),
                 cgmath::Deg(0.0),
             ),
+            billboard,
+            uv: UvRect::FULL,
         })
-        .collect::<Vec<_>>();
-
-    let instance_data = instances
-        .iter()
-        .map(instance::Instance::to_raw)
-        .collect::<Vec<_>>();
-    let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Instance Buffer"),
-        contents: bytemuck::cast_slice(&instance_data),
-        usage: wgpu::BufferUsages::VERTEX,
-    });
-    (instances, instance_buffer)
+        .collect()
 }
 
-pub(crate) fn slope_instance_init(
-    device: &wgpu::Device,
-    tiles: MapTiles,
+pub(crate) fn slope_tile_instances(
+    tiles: &MapTiles,
     width: f32,
     height: f32,
     depth: f32,
-) -> (Vec<Instance>, wgpu::Buffer) {
-    let instances = (0..tiles.depth)
+    billboard: Billboard,
+) -> Vec<Instance> {
+    (0..tiles.depth)
         .flat_map(|z| {
             (0..tiles.width)
                 .map(move |x| (x, z))
@@ -249,17 +318,33 @@ pub(crate) fn slope_instance_init(
                 cgmath::Vector3::unit_z(),
                 cgmath::Deg(0.0),
             ),
+            billboard,
+            uv: UvRect::FULL,
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
+pub(crate) fn instance_buffer_init(
+    device: &wgpu::Device,
+    instances: &[Instance],
+    material: u32,
+) -> wgpu::Buffer {
     let instance_data = instances
         .iter()
-        .map(instance::Instance::to_raw)
+        .map(|instance| instance.to_raw(material))
         .collect::<Vec<_>>();
-    let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Instance Buffer"),
         contents: bytemuck::cast_slice(&instance_data),
+        // Rewritten as sprites animate.
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+pub(crate) fn static_instance_init(device: &wgpu::Device, material: u32) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Static Instance Buffer"),
+        contents: bytemuck::cast_slice(&[Instance::identity().to_raw(material)]),
         usage: wgpu::BufferUsages::VERTEX,
-    });
-    (instances, instance_buffer)
+    })
 }
@@ -1,11 +1,91 @@
 use anyhow::*;
-use image::{GenericImageView};
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU8};
+
+use crate::{
+    block_compression,
+    lightmap::{linear_to_srgb, srgb_to_linear},
+    texture_file::TextureFile,
+};
+
+/// How a texture is filtered and wrapped. The default keeps texels crisp up
+/// close but filters and mipmaps them in the distance, which stops far floors
+/// shimmering.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sampling {
+    /// Used when a texel covers more than a pixel.
+    pub mag_filter: wgpu::FilterMode,
+    /// Used when a texel covers less than a pixel.
+    pub min_filter: wgpu::FilterMode,
+    /// Between mip levels. `Nearest` snaps to the closest level.
+    pub mipmap_filter: wgpu::FilterMode,
+    /// Samples taken along the view direction on surfaces seen at a grazing
+    /// angle: 1, 2, 4, 8 or 16. Only applies when every filter is linear,
+    /// since some backends filter magnification too once it's on, and is
+    /// ignored by adapters without anisotropic filtering.
+    pub anisotropy: u8,
+    pub address_mode: wgpu::AddressMode,
+}
+
+impl Sampling {
+    /// Nearest neighbour up close, trilinear further away.
+    pub const PIXELATED: Self = Self {
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        anisotropy: 1,
+        address_mode: wgpu::AddressMode::Repeat,
+    };
+    /// Trilinear and anisotropic everywhere.
+    pub const SMOOTH: Self = Self {
+        mag_filter: wgpu::FilterMode::Linear,
+        anisotropy: 16,
+        ..Self::PIXELATED
+    };
+    /// Nearest neighbour everywhere, mip levels included.
+    pub const NEAREST: Self = Self {
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        anisotropy: 1,
+        address_mode: wgpu::AddressMode::Repeat,
+    };
+
+    pub fn with_address_mode(self, address_mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode,
+            ..self
+        }
+    }
+
+    pub fn create_sampler(&self, device: &wgpu::Device, label: &str) -> wgpu::Sampler {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|&filter| filter == wgpu::FilterMode::Linear);
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: NonZeroU8::new(self.anisotropy).filter(|n| linear && n.get() > 1),
+            ..Default::default()
+        })
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self::PIXELATED
+    }
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub size: wgpu::Extent3d,
 }
 
 impl Texture {
@@ -14,6 +94,7 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -26,7 +107,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
@@ -53,52 +134,140 @@ impl Texture {
             texture,
             view,
             sampler,
+            size,
+        }
+    }
+
+    /// Depth texture array with one layer per shadow map, sampled with a
+    /// comparison sampler so lookups return how much of the texel is lit.
+    pub fn create_shadow_map(device: &wgpu::Device, size: u32, layers: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering makes each comparison a 2x2 PCF tap.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn from_bytes(
+    /// Offscreen colour target that later passes can sample, or that resolves
+    /// into one when multisampled.
+    pub fn create_render_target(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
         label: &str,
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
     }
 
-    pub fn from_image(
+    /// 3D colour grading table from the usual strip layout: `size` slices of
+    /// `size`x`size` laid side by side, red across each slice, green down and
+    /// blue from slice to slice.
+    pub fn from_lut_strip(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: &image::DynamicImage,
-        label: Option<&str>,
+        img: &image::RgbaImage,
+        label: &str,
     ) -> Result<Self> {
-        let dimensions = img.dimensions();
-        let mut rgba = img.to_rgba8();
-
-        for i in 0..(dimensions.1) as usize {
-            for j in 0..(dimensions.0) as usize {
-                if rgba[(j as u32, i as u32)].0 == [255, 0, 255, 255] {
-                    rgba[(j as u32, i as u32)].0 = [0, 0, 0, 0];
-                }
-            }
+        let lut_size = img.height();
+        if lut_size == 0 || img.width() != lut_size * lut_size {
+            bail!(
+                "LUT strip {} is {}x{}, expected {}x{}",
+                label,
+                img.width(),
+                img.height(),
+                lut_size * lut_size,
+                lut_size
+            );
         }
-
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
+            width: lut_size,
+            height: lut_size,
+            depth_or_array_layers: lut_size,
         };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
+            label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
+        let mut texels = Vec::with_capacity((lut_size * lut_size * lut_size * 4) as usize);
+        for b in 0..lut_size {
+            for g in 0..lut_size {
+                for r in 0..lut_size {
+                    texels.extend_from_slice(&img[(b * lut_size + r, g)].0);
+                }
+            }
+        }
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -106,22 +275,22 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            &texels,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: NonZeroU32::new(dimensions.1),
+                bytes_per_row: NonZeroU32::new(4 * lut_size),
+                rows_per_image: NonZeroU32::new(lut_size),
             },
             size,
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
@@ -130,6 +299,405 @@ impl Texture {
             texture,
             view,
             sampler,
+            size,
         })
     }
+
+    /// Baked lighting as one mipmapped array layer per lightmap, filtered
+    /// linearly so luxels blend across a surface. Smaller lightmaps sit in
+    /// the top left corner of their layer with their edges stretched over
+    /// the rest, so their coordinates need scaling by `lightmap_scale`. With
+    /// no lightmaps there's a single white layer.
+    pub fn from_lightmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        imgs: &[image::RgbaImage],
+        label: &str,
+    ) -> Self {
+        let white = [image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]))];
+        let imgs = if imgs.is_empty() { &white[..] } else { imgs };
+        let (width, height) = Self::layer_size(imgs.iter().map(|img| img.dimensions()));
+        let layers: Vec<_> = imgs
+            .iter()
+            .map(|img| {
+                image::RgbaImage::from_fn(width, height, |x, y| {
+                    *img.get_pixel(x.min(img.width() - 1), y.min(img.height() - 1))
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self::from_layers(device, queue, &layers, sampler, label)
+    }
+
+    /// How much a lightmap's coordinates shrink once it's in an array of
+    /// `array_size` layers.
+    pub fn lightmap_scale(img: &image::RgbaImage, array_size: wgpu::Extent3d) -> [f32; 2] {
+        [
+            img.width() as f32 / array_size.width as f32,
+            img.height() as f32 / array_size.height as f32,
+        ]
+    }
+
+    /// Images of one size as one mipmapped array layer each, so they can
+    /// share a bind group. Block compressed files of one format stay
+    /// compressed, with their own mip levels, when the device can sample the
+    /// format. Anything else is decompressed, with mip levels generated unless
+    /// a file has the full chain. HDR images make a half float array and
+    /// can't be mixed with others.
+    pub fn from_files(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        files: &[TextureFile],
+        sampling: Sampling,
+        label: &str,
+    ) -> Result<Self> {
+        let first = match files.first() {
+            Some(first) => first,
+            None => bail!("texture array {} has no images", label),
+        };
+        let sampler = sampling.create_sampler(device, label);
+        let info = first.format.describe();
+        let (block_width, block_height) = info.block_dimensions;
+        let compressed = first.is_compressed()
+            && device.features().contains(info.required_features)
+            && first.width % block_width as u32 == 0
+            && first.height % block_height as u32 == 0
+            && files.iter().all(|file| {
+                (file.format, file.width, file.height) == (first.format, first.width, first.height)
+                    && file.samples_as_stored()
+            });
+        if compressed {
+            let level_count = files.iter().map(|file| file.levels.len()).min().unwrap();
+            let layers: Vec<Vec<&[u8]>> = files
+                .iter()
+                .map(|file| file.levels[..level_count].iter().map(Vec::as_slice).collect())
+                .collect();
+            return Ok(Self::from_levels(
+                device,
+                queue,
+                first.format,
+                (first.width, first.height),
+                &layers,
+                sampler,
+                label,
+            ));
+        }
+
+        if let Some(file) = files
+            .iter()
+            .find(|file| !block_compression::can_decompress(file.format))
+        {
+            bail!(
+                "texture array {} has a {:?} image, which can't stay compressed on this device \
+                 or beside its other images and can't be decompressed on the CPU; convert it \
+                 to BC1 to BC5, ETC2 or PNG",
+                label,
+                file.format
+            );
+        }
+
+        let (width, height) = (first.width, first.height);
+        if let Some(file) = files
+            .iter()
+            .find(|file| (file.width, file.height) != (width, height))
+        {
+            bail!(
+                "texture array {} mixes {}x{} and {}x{} images, which would stretch one of them",
+                label,
+                width,
+                height,
+                file.width,
+                file.height
+            );
+        }
+        let full_chain = mip_level_count(width, height) as usize;
+        if files.iter().any(TextureFile::is_hdr) {
+            if !files.iter().all(TextureFile::is_hdr) {
+                bail!("texture array {} mixes HDR and LDR images", label);
+            }
+            let mut chains = Vec::with_capacity(files.len());
+            for file in files {
+                let mut levels = file.to_rgba32f()?;
+                if levels.len() < full_chain {
+                    levels = hdr_mip_chain(&levels[0]);
+                }
+                let bytes: Vec<Vec<u8>> = levels
+                    .iter()
+                    .map(|level| {
+                        level
+                            .iter()
+                            .flat_map(|&c| half::f16::from_f32(c).to_le_bytes())
+                            .collect()
+                    })
+                    .collect();
+                chains.push(bytes);
+            }
+            let layers: Vec<Vec<&[u8]>> = chains
+                .iter()
+                .map(|chain| chain.iter().map(Vec::as_slice).collect())
+                .collect();
+            return Ok(Self::from_levels(
+                device,
+                queue,
+                wgpu::TextureFormat::Rgba16Float,
+                (width, height),
+                &layers,
+                sampler,
+                label,
+            ));
+        }
+
+        // The array takes the first image's colour space.
+        let format = if info.srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let mut chains = Vec::with_capacity(files.len());
+        for file in files {
+            let mut levels = file.to_rgba8()?;
+            if levels.len() < full_chain {
+                levels = mip_chain(&levels[0], format);
+            }
+            chains.push(levels);
+        }
+        Ok(Self::from_rgba8_chains(device, queue, format, &chains, sampler, label))
+    }
+
+    /// Largest width and height among the layers.
+    fn layer_size(dimensions: impl Iterator<Item = (u32, u32)>) -> (u32, u32) {
+        dimensions.fold((1, 1), |(width, height), (w, h)| (width.max(w), height.max(h)))
+    }
+
+    /// sRGB texture array from same sized images, with every mip level down
+    /// to 1x1 generated on the CPU.
+    fn from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::RgbaImage],
+        sampler: wgpu::Sampler,
+        label: &str,
+    ) -> Self {
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let chains: Vec<_> = layers.iter().map(|layer| mip_chain(layer, format)).collect();
+        Self::from_rgba8_chains(device, queue, format, &chains, sampler, label)
+    }
+
+    fn from_rgba8_chains(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        chains: &[Vec<image::RgbaImage>],
+        sampler: wgpu::Sampler,
+        label: &str,
+    ) -> Self {
+        let layers: Vec<Vec<&[u8]>> = chains
+            .iter()
+            .map(|chain| chain.iter().map(|level| level.as_raw().as_slice()).collect())
+            .collect();
+        let size = chains[0][0].dimensions();
+        Self::from_levels(device, queue, format, size, &layers, sampler, label)
+    }
+
+    /// Texture array from each layer's mip levels, largest first, in
+    /// `format`. Every layer needs the same number of levels.
+    fn from_levels(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        layers: &[Vec<&[u8]>],
+        sampler: wgpu::Sampler,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers.len() as u32,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: layers[0].len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        let info = format.describe();
+        let (block_width, block_height) = (
+            info.block_dimensions.0 as u32,
+            info.block_dimensions.1 as u32,
+        );
+        for (layer, levels) in layers.iter().enumerate() {
+            for (mip_level, data) in levels.iter().enumerate() {
+                // Copies cover whole blocks, even past the edge of small levels.
+                let blocks_x = (width >> mip_level).max(1).div_ceil(block_width);
+                let blocks_y = (height >> mip_level).max(1).div_ceil(block_height);
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: mip_level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    data,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(blocks_x * info.block_size as u32),
+                        rows_per_image: NonZeroU32::new(blocks_y),
+                    },
+                    wgpu::Extent3d {
+                        width: blocks_x * block_width,
+                        height: blocks_y * block_height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        // A single layer would otherwise get a plain 2D view.
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+}
+
+/// Levels from `width`x`height` halving down to 1x1.
+fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// `img` followed by each smaller mip level. Every texel of an sRGB `format`
+/// averages the 2x2 above it in linear light, weighted by alpha so keyed out
+/// texels don't darken their neighbours. Other formats hold data such as
+/// normals or roughness, which are averaged as they are. Odd rows and columns
+/// are dropped.
+fn mip_chain(img: &image::RgbaImage, format: wgpu::TextureFormat) -> Vec<image::RgbaImage> {
+    let srgb = format.describe().srgb;
+    let to_linear: Vec<f32> = (0..=255).map(srgb_to_linear).collect();
+    let mut levels = vec![img.clone()];
+    for _ in 1..mip_level_count(img.width(), img.height()) {
+        let above = levels.last().unwrap();
+        let width = (above.width() / 2).max(1);
+        let height = (above.height() / 2).max(1);
+        let level = image::RgbaImage::from_fn(width, height, |x, y| {
+            let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                above.get_pixel(
+                    (x * 2 + dx).min(above.width() - 1),
+                    (y * 2 + dy).min(above.height() - 1),
+                )
+            });
+            if !srgb {
+                let channel = |i: usize| {
+                    let sum: u32 = texels.iter().map(|texel| texel.0[i] as u32).sum();
+                    ((sum + 2) / 4) as u8
+                };
+                return image::Rgba([channel(0), channel(1), channel(2), channel(3)]);
+            }
+            let mut colour = [0.0; 3];
+            let mut alpha = 0.0;
+            for texel in texels {
+                let a = texel.0[3] as f32 / 255.0;
+                for (sum, &c) in colour.iter_mut().zip(&texel.0[..3]) {
+                    *sum += to_linear[c as usize] * a;
+                }
+                alpha += a;
+            }
+            let channel = |c: f32| linear_to_srgb(if alpha > 0.0 { c / alpha } else { 0.0 });
+            image::Rgba([
+                channel(colour[0]),
+                channel(colour[1]),
+                channel(colour[2]),
+                (alpha / 4.0 * 255.0).round() as u8,
+            ])
+        });
+        levels.push(level);
+    }
+    levels
+}
+
+/// Like `mip_chain` for HDR images, averaging without weights.
+fn hdr_mip_chain(img: &image::Rgba32FImage) -> Vec<image::Rgba32FImage> {
+    let mut levels = vec![img.clone()];
+    for _ in 1..mip_level_count(img.width(), img.height()) {
+        let above = levels.last().unwrap();
+        let width = (above.width() / 2).max(1);
+        let height = (above.height() / 2).max(1);
+        let level = image::Rgba32FImage::from_fn(width, height, |x, y| {
+            let mut sum = [0.0; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let texel = above.get_pixel(
+                    (x * 2 + dx).min(above.width() - 1),
+                    (y * 2 + dy).min(above.height() - 1),
+                );
+                for (sum, c) in sum.iter_mut().zip(texel.0) {
+                    *sum += c / 4.0;
+                }
+            }
+            image::Rgba(sum)
+        });
+        levels.push(level);
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_mips_average_in_linear_light() {
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba([if x == 0 { 0 } else { 255 }, 0, 0, 255])
+        });
+        let levels = mip_chain(&img, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(levels.len(), 2);
+        // Half of full red in linear light, well above the raw midpoint.
+        assert_eq!(levels[1].get_pixel(0, 0).0, [188, 0, 0, 255]);
+    }
+
+    #[test]
+    fn srgb_mips_ignore_transparent_texels() {
+        let img = image::RgbaImage::from_fn(2, 2, |x, y| {
+            if (x, y) == (0, 0) {
+                image::Rgba([200, 100, 50, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        });
+        let levels = mip_chain(&img, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(levels[1].get_pixel(0, 0).0, [200, 100, 50, 64]);
+    }
+
+    #[test]
+    fn data_mips_average_raw_values() {
+        // A normal map tilting left and right averages to straight up.
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba([if x == 0 { 38 } else { 218 }, 128, 218, 0])
+        });
+        let levels = mip_chain(&img, wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(levels[1].get_pixel(0, 0).0, [128, 128, 218, 0]);
+    }
 }
@@ -0,0 +1,371 @@
+use anyhow::{bail, Context};
+
+use crate::block_compression;
+
+const KTX2_MAGIC: &[u8; 12] = b"\xabKTX 20\xbb\r\n\x1a\n";
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// Compression formats the device is asked for when the adapter has them.
+/// Textures in formats it lacks are decompressed on the CPU instead, except
+/// for BC6H and BC7, which can't be.
+pub(crate) fn required_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features()
+        & (wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+}
+
+/// A 2D image as it was stored: KTX2 and DDS files keep their format and
+/// pre-built mip levels, anything else is decoded by the `image` crate into
+/// 8-bit sRGB, or half floats for Radiance HDR and OpenEXR.
+#[derive(Clone)]
+pub(crate) struct TextureFile {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Each mip level's texels, largest first.
+    pub levels: Vec<Vec<u8>>,
+    /// BC1 from a format without alpha. wgpu only has BC1 with alpha, which
+    /// turns the black of three-colour blocks transparent.
+    pub bc1_rgb: bool,
+}
+
+impl TextureFile {
+    pub(crate) fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.starts_with(KTX2_MAGIC) {
+            Self::ktx2(bytes).context("reading KTX2")
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::dds(bytes).context("reading DDS")
+        } else {
+            Ok(Self::from_image(image::load_from_memory(bytes)?))
+        }
+    }
+
+    /// A single 8-bit texel, for arrays with nothing else to hold.
+    pub(crate) fn solid(rgba: [u8; 4], format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            width: 1,
+            height: 1,
+            levels: vec![rgba.to_vec()],
+            bc1_rgb: false,
+        }
+    }
+
+    /// The same texels read as linear data rather than sRGB colour, for
+    /// normal and roughness maps.
+    pub(crate) fn into_linear(self) -> Self {
+        use wgpu::TextureFormat as F;
+        let format = match self.format {
+            F::Rgba8UnormSrgb => F::Rgba8Unorm,
+            F::Bc1RgbaUnormSrgb => F::Bc1RgbaUnorm,
+            F::Bc2RgbaUnormSrgb => F::Bc2RgbaUnorm,
+            F::Bc3RgbaUnormSrgb => F::Bc3RgbaUnorm,
+            F::Bc7RgbaUnormSrgb => F::Bc7RgbaUnorm,
+            F::Etc2Rgb8UnormSrgb => F::Etc2Rgb8Unorm,
+            F::Etc2Rgb8A1UnormSrgb => F::Etc2Rgb8A1Unorm,
+            F::Etc2Rgba8UnormSrgb => F::Etc2Rgba8Unorm,
+            format => format,
+        };
+        Self { format, ..self }
+    }
+
+    /// Pure magenta is keyed out of 8-bit images.
+    fn from_image(img: image::DynamicImage) -> Self {
+        let (width, height) = (img.width(), img.height());
+        if matches!(
+            img,
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+        ) {
+            let texels: Vec<u8> = img
+                .to_rgba32f()
+                .into_raw()
+                .into_iter()
+                .flat_map(|c| half::f16::from_f32(c).to_le_bytes())
+                .collect();
+            return Self {
+                format: wgpu::TextureFormat::Rgba16Float,
+                width,
+                height,
+                levels: vec![texels],
+                bc1_rgb: false,
+            };
+        }
+        let mut rgba = img.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            if pixel.0 == [255, 0, 255, 255] {
+                pixel.0 = [0, 0, 0, 0];
+            }
+        }
+        Self {
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            levels: vec![rgba.into_raw()],
+            bc1_rgb: false,
+        }
+    }
+
+    /// Single 2D images only, without supercompression.
+    fn ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        let u32_at = |offset: usize| read_u32(bytes, offset);
+        let u64_at = |offset: usize| read_u64(bytes, offset);
+        let format = match u32_at(12)? {
+            37 => wgpu::TextureFormat::Rgba8Unorm,
+            43 => wgpu::TextureFormat::Rgba8UnormSrgb,
+            97 => wgpu::TextureFormat::Rgba16Float,
+            vk_format => vk_block_format(vk_format)
+                .with_context(|| format!("unsupported VkFormat {}", vk_format))?,
+        };
+        let (width, height) = (u32_at(20)?, u32_at(24)?);
+        let (depth, layers, faces) = (u32_at(28)?, u32_at(32)?, u32_at(36)?);
+        if depth > 1 || layers > 1 || faces != 1 {
+            bail!("only single 2D images are supported");
+        }
+        if u32_at(44)? != 0 {
+            bail!("supercompressed files aren't supported");
+        }
+        // A level count of 0 asks the loader to generate them.
+        let level_count = u32_at(40)?.max(1) as usize;
+        let levels = (0..level_count)
+            .map(|level| {
+                let index = 80 + level * 24;
+                let (offset, length) = (u64_at(index)? as usize, u64_at(index + 8)? as usize);
+                let data = bytes
+                    .get(offset..offset + length)
+                    .with_context(|| format!("level {} is past the end of the file", level))?;
+                Ok(data.to_vec())
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            bc1_rgb: matches!(u32_at(12)?, 131 | 132),
+            ..Self::checked(format, width, height, levels)?
+        })
+    }
+
+    /// Legacy DXTn and ATI headers, DX10 headers and uncompressed 32-bit
+    /// RGBA or BGRA. Legacy headers don't give a colour space, so colour
+    /// formats are taken as sRGB.
+    fn dds(bytes: &[u8]) -> anyhow::Result<Self> {
+        let u32_at = |offset: usize| read_u32(bytes, offset);
+        let (height, width) = (u32_at(12)?, u32_at(16)?);
+        let level_count = u32_at(28)?.max(1) as usize;
+        const CUBEMAP: u32 = 0x200;
+        const VOLUME: u32 = 0x20_0000;
+        if u32_at(112)? & (CUBEMAP | VOLUME) != 0 {
+            bail!("only single 2D images are supported");
+        }
+        const FOURCC: u32 = 0x4;
+        const RGB: u32 = 0x40;
+        let pixel_flags = u32_at(80)?;
+        let four_cc = bytes.get(84..88).context("truncated header")?;
+        let mut bgra = false;
+        let (format, data_offset) = if pixel_flags & FOURCC != 0 && four_cc == b"DX10" {
+            if u32_at(140)? > 1 {
+                bail!("only single 2D images are supported");
+            }
+            let dxgi_format = u32_at(128)?;
+            bgra = matches!(dxgi_format, 87 | 91);
+            let format = dxgi_format_to_wgpu(dxgi_format)
+                .with_context(|| format!("unsupported DXGI format {}", dxgi_format))?;
+            (format, 148)
+        } else if pixel_flags & FOURCC != 0 {
+            use wgpu::TextureFormat as F;
+            let format = match four_cc {
+                b"DXT1" => F::Bc1RgbaUnormSrgb,
+                b"DXT2" | b"DXT3" => F::Bc2RgbaUnormSrgb,
+                b"DXT4" | b"DXT5" => F::Bc3RgbaUnormSrgb,
+                b"ATI1" | b"BC4U" => F::Bc4RUnorm,
+                b"ATI2" | b"BC5U" => F::Bc5RgUnorm,
+                _ => bail!("unsupported FourCC {}", String::from_utf8_lossy(four_cc)),
+            };
+            (format, 128)
+        } else if pixel_flags & RGB != 0 && u32_at(88)? == 32 {
+            // Either red or blue in the lowest byte.
+            bgra = u32_at(92)? == 0x00ff_0000;
+            (wgpu::TextureFormat::Rgba8UnormSrgb, 128)
+        } else {
+            bail!("unsupported pixel format");
+        };
+
+        let mut offset = data_offset;
+        let mut levels = Vec::with_capacity(level_count);
+        for level in 0..level_count {
+            let length = level_bytes(format, width >> level, height >> level);
+            let mut data = bytes
+                .get(offset..offset + length)
+                .with_context(|| format!("level {} is past the end of the file", level))?
+                .to_vec();
+            if bgra {
+                for texel in data.chunks_exact_mut(4) {
+                    texel.swap(0, 2);
+                }
+            }
+            levels.push(data);
+            offset += length;
+        }
+        Self::checked(format, width, height, levels)
+    }
+
+    /// Makes sure every level holds as many bytes as its size needs.
+    fn checked(
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Self> {
+        if width == 0 || height == 0 {
+            bail!("image is empty");
+        }
+        for (level, data) in levels.iter().enumerate() {
+            let expected = level_bytes(format, width >> level, height >> level);
+            if data.len() < expected {
+                bail!(
+                    "level {} has {} bytes, expected {}",
+                    level,
+                    data.len(),
+                    expected
+                );
+            }
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+            bc1_rgb: false,
+        })
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.format.describe().block_dimensions != (1, 1)
+    }
+
+    /// Whether a GPU sampling the format reads what the file means, which
+    /// it doesn't for BC1 without alpha that uses black.
+    pub(crate) fn samples_as_stored(&self) -> bool {
+        !self.bc1_rgb
+            || !self
+                .levels
+                .iter()
+                .any(|level| block_compression::bc1_has_black(level))
+    }
+
+    pub(crate) fn is_hdr(&self) -> bool {
+        matches!(
+            self.format,
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Bc6hRgbUfloat
+        )
+    }
+
+    /// Size of mip level `level`.
+    pub(crate) fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    /// Every level as 8-bit RGBA, decompressing block formats on the CPU.
+    pub(crate) fn to_rgba8(&self) -> anyhow::Result<Vec<image::RgbaImage>> {
+        use wgpu::TextureFormat as F;
+        self.levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_size(level);
+                match self.format {
+                    F::Rgba8Unorm | F::Rgba8UnormSrgb => {
+                        let texels = data[..(width * height * 4) as usize].to_vec();
+                        Ok(image::RgbaImage::from_raw(width, height, texels).unwrap())
+                    }
+                    format => {
+                        block_compression::decompress(format, self.bc1_rgb, width, height, data)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Every level as 32-bit float RGBA. Only for half float images.
+    pub(crate) fn to_rgba32f(&self) -> anyhow::Result<Vec<image::Rgba32FImage>> {
+        if self.format != wgpu::TextureFormat::Rgba16Float {
+            bail!("{:?} can't be read on the CPU as floats", self.format);
+        }
+        Ok(self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_size(level);
+                let texels = data[..(width * height * 8) as usize]
+                    .chunks_exact(2)
+                    .map(|c| half::f16::from_le_bytes([c[0], c[1]]).to_f32())
+                    .collect();
+                image::Rgba32FImage::from_raw(width, height, texels).unwrap()
+            })
+            .collect())
+    }
+}
+
+/// Bytes in a `width`x`height` level, counting partial blocks whole.
+fn level_bytes(format: wgpu::TextureFormat, width: u32, height: u32) -> usize {
+    let info = format.describe();
+    let (block_width, block_height) = info.block_dimensions;
+    let blocks_x = width.max(1).div_ceil(block_width as u32);
+    let blocks_y = height.max(1).div_ceil(block_height as u32);
+    (blocks_x * blocks_y) as usize * info.block_size as usize
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let field = bytes.get(offset..offset + 4).context("truncated header")?;
+    Ok(u32::from_le_bytes(field.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> anyhow::Result<u64> {
+    let field = bytes.get(offset..offset + 8).context("truncated header")?;
+    Ok(u64::from_le_bytes(field.try_into().unwrap()))
+}
+
+fn vk_block_format(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+    Some(match vk_format {
+        // 131 and 132 are BC1 without alpha, which `TextureFile::bc1_rgb`
+        // marks.
+        131 | 133 => F::Bc1RgbaUnorm,
+        132 | 134 => F::Bc1RgbaUnormSrgb,
+        135 => F::Bc2RgbaUnorm,
+        136 => F::Bc2RgbaUnormSrgb,
+        137 => F::Bc3RgbaUnorm,
+        138 => F::Bc3RgbaUnormSrgb,
+        139 => F::Bc4RUnorm,
+        141 => F::Bc5RgUnorm,
+        143 => F::Bc6hRgbUfloat,
+        145 => F::Bc7RgbaUnorm,
+        146 => F::Bc7RgbaUnormSrgb,
+        147 => F::Etc2Rgb8Unorm,
+        148 => F::Etc2Rgb8UnormSrgb,
+        149 => F::Etc2Rgb8A1Unorm,
+        150 => F::Etc2Rgb8A1UnormSrgb,
+        151 => F::Etc2Rgba8Unorm,
+        152 => F::Etc2Rgba8UnormSrgb,
+        _ => return None,
+    })
+}
+
+fn dxgi_format_to_wgpu(dxgi_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+    Some(match dxgi_format {
+        10 => F::Rgba16Float,
+        // BGRA is swizzled to RGBA as it's read.
+        28 | 87 => F::Rgba8Unorm,
+        29 | 91 => F::Rgba8UnormSrgb,
+        71 => F::Bc1RgbaUnorm,
+        72 => F::Bc1RgbaUnormSrgb,
+        74 => F::Bc2RgbaUnorm,
+        75 => F::Bc2RgbaUnormSrgb,
+        77 => F::Bc3RgbaUnorm,
+        78 => F::Bc3RgbaUnormSrgb,
+        80 => F::Bc4RUnorm,
+        83 => F::Bc5RgUnorm,
+        95 => F::Bc6hRgbUfloat,
+        98 => F::Bc7RgbaUnorm,
+        99 => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}